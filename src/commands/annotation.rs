@@ -0,0 +1,182 @@
+// annotation.rs
+//
+// write longer notes on tasks
+
+use serde::{Deserialize, Serialize};
+use std::env;
+use std::fs;
+use std::io::{self, Write};
+#[cfg(unix)]
+use std::os::unix::fs::OpenOptionsExt;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+use crate::commands::config;
+use crate::commands::error::RustaskError;
+use crate::commands::task::{self, Deadline, Task};
+
+/// A note on a task, kept along with when it was written
+///
+/// Annotations are only ever added to a task, never changed.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+pub struct Annotation {
+    pub created: Deadline,
+    pub text: String,
+}
+
+impl Annotation {
+    pub fn new(text: String, created: Deadline) -> Annotation {
+        Annotation { created, text }
+    }
+}
+
+/// Lines starting with this are left out of the notes written in an editor
+const COMMENT: char = '#';
+
+/// What the editor opens with: the task and its annotations, commented out
+pub fn template(task: &Task) -> String {
+    let format = &config::get().deadlines.output_format;
+    let mut template = format!(
+        "\n{} Write a note on \"{}\" above.\n{} Lines starting with '{}' are left out; \
+         an empty note adds nothing.\n",
+        COMMENT, task.description, COMMENT, COMMENT
+    );
+    for annotation in &task.annotations {
+        template.push_str(&format!(
+            "{}\n{} {}\n",
+            COMMENT,
+            COMMENT,
+            annotation.created.format(format)
+        ));
+        for line in annotation.text.lines() {
+            template.push_str(&format!("{}   {}\n", COMMENT, line));
+        }
+    }
+    template
+}
+
+/// The note written in an editor opened on [`template`], if any
+///
+/// Comment lines are dropped, as are the blank lines around the note.
+pub fn parse_note(text: &str) -> Option<String> {
+    let lines: Vec<&str> = text
+        .lines()
+        .filter(|l| !l.starts_with(COMMENT))
+        .map(str::trim_end)
+        .collect();
+    let note = lines.join("\n").trim_matches('\n').to_string();
+    (!note.trim().is_empty()).then_some(note)
+}
+
+/// The editor to write notes in: `$VISUAL`, `$EDITOR` or `vi`
+fn editor() -> String {
+    ["VISUAL", "EDITOR"]
+        .iter()
+        .filter_map(|var| env::var(var).ok())
+        .find(|e| !e.trim().is_empty())
+        .unwrap_or_else(|| "vi".to_string())
+}
+
+/// Open an editor on a temporary file to write a note on `task`
+///
+/// The editor is run by the shell, so it may be given with arguments (e.g.
+/// `code --wait`). Returns the note written, if any.
+pub fn compose(task: &Task) -> Result<Option<String>, RustaskError> {
+    let (path, mut file) = create_private(&env::temp_dir())?;
+    let written = file
+        .write_all(template(task).as_bytes())
+        .map_err(RustaskError::from)
+        .and_then(|()| {
+            drop(file);
+            run_editor(&editor(), &path)
+        })
+        .and_then(|()| Ok(fs::read_to_string(&path)?));
+    let _ = fs::remove_file(&path);
+    Ok(parse_note(&written?))
+}
+
+/// Create a new file in `dir` which only the user can read, under a random name
+///
+/// The file is never one which existed before, so it cannot be a link planted there.
+fn create_private(dir: &Path) -> Result<(PathBuf, fs::File), RustaskError> {
+    loop {
+        let path = dir.join(format!("rustask-note-{}.txt", task::new_id()));
+        let mut options = fs::OpenOptions::new();
+        options.write(true).create_new(true);
+        #[cfg(unix)]
+        options.mode(0o600);
+        match options.open(&path) {
+            Ok(file) => return Ok((path, file)),
+            Err(e) if e.kind() == io::ErrorKind::AlreadyExists => continue,
+            Err(e) => return Err(e.into()),
+        }
+    }
+}
+
+fn run_editor(editor: &str, path: &Path) -> Result<(), RustaskError> {
+    let status = Command::new("sh")
+        .arg("-c")
+        .arg(format!("{} \"$1\"", editor))
+        .arg("sh")
+        .arg(path)
+        .status()
+        .map_err(|e| RustaskError::EditorFailed(editor.to_string(), e.to_string()))?;
+    if !status.success() {
+        return Err(RustaskError::EditorFailed(
+            editor.to_string(),
+            status.to_string(),
+        ));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::commands::task::{parse_deadline, TaskBuilder};
+
+    #[test]
+    fn template_and_note() {
+        let mut task = TaskBuilder::new("task".to_string()).build();
+        task.annotations.push(Annotation::new(
+            "first line\nsecond line".to_string(),
+            parse_deadline("2022-05-01 10:30").unwrap(),
+        ));
+        let template = template(&task);
+        assert!(template.contains("# Write a note on \"task\" above."));
+        assert!(template.contains("# 2022-05-01 10:30\n#   first line\n#   second line\n"));
+        assert_eq!(parse_note(&template), None);
+
+        let written = format!("\n  \ncalled them \n\n# not this\nagain\n\n{}", template);
+        assert_eq!(
+            parse_note(&written),
+            Some("called them\n\nagain".to_string())
+        );
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn private_file() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let (path, _) = create_private(Path::new(".")).unwrap();
+        let (other, _) = create_private(Path::new(".")).unwrap();
+        assert_ne!(path, other);
+        let mode = fs::metadata(&path).unwrap().permissions().mode();
+        assert_eq!(mode & 0o777, 0o600);
+        fs::remove_file(path).unwrap();
+        fs::remove_file(other).unwrap();
+    }
+
+    #[test]
+    fn failing_editor() {
+        let path = Path::new("test_file_annotation_editor");
+        fs::write(path, "").unwrap();
+        assert!(run_editor("true", path).is_ok());
+        assert!(matches!(
+            run_editor("false", path),
+            Err(RustaskError::EditorFailed(editor, _)) if editor == "false"
+        ));
+        fs::remove_file(path).unwrap();
+    }
+}
@@ -0,0 +1,515 @@
+// backend.rs
+//
+// define where and how projects are stored
+
+use serde::de::DeserializeOwned;
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+use crate::commands::change::{Change, Projects};
+use crate::commands::error::{FileError, RustaskError};
+use crate::commands::filter::Scope;
+use crate::commands::project::{Progress, Project, Summary};
+use crate::commands::repair::{self, Salvage};
+use crate::commands::schema::{self, Document};
+use crate::commands::sqlite::SqliteStorage;
+use crate::commands::storage;
+use crate::commands::task::Task;
+
+/// A place projects are loaded from and stored to
+///
+/// Only `load` and `store` are required: the other methods are answered from them,
+/// and backends which can read or write less than every project override them.
+pub trait Storage {
+    /// Load every project, sorted
+    fn load(&self) -> Result<Vec<Project>, RustaskError>;
+
+    /// Replace every project
+    fn store(&self, projects: &[Project]) -> Result<(), RustaskError>;
+
+    /// The version of the format the projects are stored in
+    fn version(&self) -> Result<u32, RustaskError>;
+
+    /// The version of the format the projects are stored in by this rustask
+    fn current_version(&self) -> u32;
+
+    /// Load the project named `name`, if there is one
+    fn load_project(&self, name: &str) -> Result<Option<Project>, RustaskError> {
+        Ok(self.load()?.into_iter().find(|p| p.name == name))
+    }
+
+    /// Load the tasks within `scope`, by project
+    ///
+    /// Every project in the scope is returned, even without tasks in it; the summaries
+    /// account for all the tasks of the projects.
+    fn query(&self, scope: &Scope) -> Result<Vec<(Summary, Vec<Task>)>, RustaskError> {
+        Ok(self
+            .load()?
+            .into_iter()
+            .filter(|p| scope.project.as_ref().is_none_or(|name| p.name == *name))
+            .map(|mut p| {
+                let summary = p.summary();
+                p.retain(|t| scope.matches(t));
+                (summary, p.tasks().clone())
+            })
+            .collect())
+    }
+
+    /// The progress of every task with subtasks, by project and then by id, in one
+    /// project or in all of them
+    fn progress(
+        &self,
+        project: Option<&str>,
+    ) -> Result<BTreeMap<String, BTreeMap<String, Progress>>, RustaskError> {
+        Ok(self
+            .load()?
+            .into_iter()
+            .filter(|p| project.is_none_or(|name| p.name == name))
+            .map(|p| (p.name.clone(), p.progress()))
+            .collect())
+    }
+
+    /// Which of the tasks with the given ids, in any project, are not done yet
+    fn pending(&self, ids: &BTreeSet<String>) -> Result<BTreeSet<String>, RustaskError> {
+        Ok(self
+            .load()?
+            .iter()
+            .flat_map(|p| p.tasks())
+            .filter(|t| !t.is_done() && ids.contains(&t.id))
+            .map(|t| t.id.clone())
+            .collect())
+    }
+
+    /// Read whatever projects and tasks can be read, for when `load` fails
+    fn salvage(&self) -> Result<Salvage, RustaskError> {
+        Ok(Salvage {
+            projects: self.load()?,
+            dropped: vec![],
+            renamed: vec![],
+        })
+    }
+
+    /// Apply `changes`, in order, as a whole
+    ///
+    /// Fails without writing anything if the stored projects are not as the changes expect.
+    fn apply(&self, changes: &[Change]) -> Result<(), RustaskError> {
+        let mut projects: Projects = self
+            .load()?
+            .into_iter()
+            .map(|p| (p.name.clone(), Some(p)))
+            .collect();
+        for change in changes {
+            if !change.apply(&mut projects) {
+                return Err(RustaskError::ChangeConflict(change.projects().join(", ")));
+            }
+        }
+        self.store(&projects.into_values().flatten().collect::<Vec<_>>())
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Backend {
+    Json,
+    Toml,
+    Yaml,
+    /// An embedded SQLite database
+    Sqlite,
+}
+
+impl Backend {
+    /// The backend implied by the extension of `path`: `.toml`, `.yaml`/`.yml`,
+    /// `.db`/`.sqlite`/`.sqlite3`, and JSON for anything else
+    pub fn from_path(path: &Path) -> Backend {
+        match path
+            .extension()
+            .and_then(|e| e.to_str())
+            .map(|e| e.to_lowercase())
+            .as_deref()
+        {
+            Some("toml") => Backend::Toml,
+            Some("yaml") | Some("yml") => Backend::Yaml,
+            Some("db") | Some("sqlite") | Some("sqlite3") => Backend::Sqlite,
+            _ => Backend::Json,
+        }
+    }
+
+    /// The storage for the task file at `path`
+    pub fn open(self, path: &Path) -> Box<dyn Storage> {
+        match self {
+            Backend::Sqlite => Box::new(SqliteStorage::new(path)),
+            format => Box::new(FileStorage {
+                path: path.to_path_buf(),
+                format,
+            }),
+        }
+    }
+}
+
+#[derive(thiserror::Error, Debug, PartialEq, Eq)]
+#[error("unknown backend `{}`: expected json, toml, yaml or sqlite", .0)]
+pub struct BackendParseError(String);
+
+impl FromStr for Backend {
+    type Err = BackendParseError;
+    fn from_str(s: &str) -> Result<Self, BackendParseError> {
+        match s.to_lowercase().as_str() {
+            "json" => Ok(Backend::Json),
+            "toml" => Ok(Backend::Toml),
+            "yaml" | "yml" => Ok(Backend::Yaml),
+            "sqlite" | "sqlite3" => Ok(Backend::Sqlite),
+            _ => Err(BackendParseError(s.to_string())),
+        }
+    }
+}
+
+impl fmt::Display for Backend {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Backend::Json => write!(f, "json"),
+            Backend::Toml => write!(f, "toml"),
+            Backend::Yaml => write!(f, "yaml"),
+            Backend::Sqlite => write!(f, "sqlite"),
+        }
+    }
+}
+
+/// A text file, replaced atomically on every store and backed up
+///
+/// Every format holds a [`Document`]: the projects and the version of the format.
+struct FileStorage {
+    path: PathBuf,
+    format: Backend,
+}
+
+impl FileStorage {
+    fn serialize(&self, projects: &[Project]) -> Result<Vec<u8>, RustaskError> {
+        let format_error = |e: &dyn fmt::Display| RustaskError::FormatError(e.to_string());
+        let document = Document::new(projects.to_vec());
+        match self.format {
+            Backend::Toml => toml::to_string(&document)
+                .map(String::into_bytes)
+                .map_err(|e| format_error(&e)),
+            Backend::Yaml => serde_yaml::to_string(&document)
+                .map(String::into_bytes)
+                .map_err(|e| format_error(&e)),
+            _ => Ok(serde_json::to_vec(&document)?),
+        }
+    }
+
+    fn read(&self) -> Result<Vec<u8>, RustaskError> {
+        fs::read(&self.path).map_err(|e| storage::io_error(&self.path, "read the task file", e))
+    }
+
+    /// The contents of the task file cannot be read, at `position` if it is known
+    fn corrupt(&self, position: Option<(usize, usize)>, message: &str) -> RustaskError {
+        // the parsers append the position to their messages
+        let message = match position {
+            Some((line, column)) => message
+                .trim_end_matches(&format!(" at line {} column {}", line, column))
+                .to_string(),
+            None => message.to_string(),
+        };
+        let mut suggestion = format!(
+            "run `rustask -f {} repair` to keep whatever can be read",
+            self.path.display()
+        );
+        let backup = storage::backup_path(&self.path, 1);
+        if backup.exists() {
+            suggestion += &format!(
+                ", or restore the previous version from `{}`",
+                backup.display()
+            );
+        }
+
+        RustaskError::FileError(FileError {
+            path: self.path.display().to_string(),
+            operation: "read the task file".to_string(),
+            position,
+            message,
+            suggestion: Some(suggestion),
+        })
+    }
+
+    /// Deserialize `contents`, in the format of the file
+    fn decode<T: DeserializeOwned>(&self, contents: &[u8]) -> Result<T, RustaskError> {
+        match self.format {
+            Backend::Toml => {
+                let text = std::str::from_utf8(contents).map_err(|e| {
+                    let position = FileError::position_of(contents, e.valid_up_to());
+                    self.corrupt(Some(position), &e.to_string())
+                })?;
+                toml::from_str(text).map_err(|e| {
+                    let position = e.span().map(|s| FileError::position_of(contents, s.start));
+                    self.corrupt(position, e.message())
+                })
+            }
+            Backend::Yaml => serde_yaml::from_slice(contents).map_err(|e| {
+                let position = e.location().map(|l| (l.line(), l.column()));
+                self.corrupt(position, &e.to_string())
+            }),
+            _ => serde_json::from_slice(contents).map_err(|e| {
+                let position = (e.line() > 0).then(|| (e.line(), e.column()));
+                self.corrupt(position, &e.to_string())
+            }),
+        }
+    }
+
+    /// Parse `contents`, in whichever version of the format they are
+    fn parse(&self, contents: &[u8]) -> Result<serde_json::Value, RustaskError> {
+        match self.format {
+            // an empty YAML document is null rather than an empty list
+            Backend::Yaml if contents.iter().all(u8::is_ascii_whitespace) => {
+                Ok(serde_json::Value::Array(vec![]))
+            }
+            Backend::Yaml => self.decode::<serde_yaml::Value>(contents).map(yaml_to_json),
+            _ => self.decode(contents),
+        }
+    }
+
+    /// Read `contents` as a document of the current version, upgrading older ones
+    fn document(&self, contents: &[u8]) -> Result<Document, RustaskError> {
+        match self.decode::<Document>(contents) {
+            Ok(document) if document.version == schema::CURRENT_VERSION => return Ok(document),
+            _ => {}
+        }
+
+        // only errors in files of the current version can be located in the file
+        let document = self.parse(contents)?;
+        match schema::version_of(&document) {
+            Ok(schema::CURRENT_VERSION) => self.decode(contents),
+            _ => schema::upgrade(document).map_err(|e| match e {
+                RustaskError::FormatError(message) => self.corrupt(None, &message),
+                e => e,
+            }),
+        }
+    }
+}
+
+/// YAML tags enum variants (`!Variant value`), where JSON nests them (`{"Variant": value}`)
+fn yaml_to_json(value: serde_yaml::Value) -> serde_json::Value {
+    use serde_json::Value;
+    match value {
+        serde_yaml::Value::Null => Value::Null,
+        serde_yaml::Value::Bool(b) => Value::Bool(b),
+        serde_yaml::Value::Number(n) => match (n.as_u64(), n.as_i64(), n.as_f64()) {
+            (Some(u), _, _) => Value::from(u),
+            (_, Some(i), _) => Value::from(i),
+            (_, _, f) => Value::from(f.unwrap_or(f64::NAN)),
+        },
+        serde_yaml::Value::String(s) => Value::String(s),
+        serde_yaml::Value::Sequence(values) => {
+            Value::Array(values.into_iter().map(yaml_to_json).collect())
+        }
+        serde_yaml::Value::Mapping(fields) => Value::Object(
+            fields
+                .into_iter()
+                .map(|(k, v)| {
+                    let key = match yaml_to_json(k) {
+                        Value::String(s) => s,
+                        other => other.to_string(),
+                    };
+                    (key, yaml_to_json(v))
+                })
+                .collect(),
+        ),
+        serde_yaml::Value::Tagged(tagged) => {
+            let tag = tagged.tag.to_string();
+            let variant = tag.trim_start_matches('!').to_string();
+            Value::Object(
+                [(variant, yaml_to_json(tagged.value))]
+                    .into_iter()
+                    .collect(),
+            )
+        }
+    }
+}
+
+impl Storage for FileStorage {
+    fn load(&self) -> Result<Vec<Project>, RustaskError> {
+        let mut projects = self.document(&self.read()?)?.projects;
+        // files written before task ids existed get them derived on load,
+        // and persisted by the next write
+        for p in projects.iter_mut() {
+            p.assign_missing_ids();
+            p.sort();
+        }
+        projects.sort();
+        Ok(projects)
+    }
+
+    fn store(&self, projects: &[Project]) -> Result<(), RustaskError> {
+        storage::store_atomic(&self.path, &self.serialize(projects)?, true)
+    }
+
+    fn version(&self) -> Result<u32, RustaskError> {
+        schema::version_of(&self.parse(&self.read()?)?)
+    }
+
+    fn current_version(&self) -> u32 {
+        schema::CURRENT_VERSION
+    }
+
+    fn salvage(&self) -> Result<Salvage, RustaskError> {
+        let contents = self.read()?;
+        match self.parse(&contents) {
+            Ok(document) => Ok(repair::salvage(document)),
+            Err(e) if self.format != Backend::Json => Err(e),
+            Err(e) => {
+                let (document, kept) = repair::recover_json(&contents).ok_or(e)?;
+                let (line, column) = FileError::position_of(&contents, kept);
+                let mut salvage = repair::salvage(document);
+                salvage.dropped.insert(
+                    0,
+                    format!(
+                        "everything after line {}, column {}: it cannot be read",
+                        line, column
+                    ),
+                );
+                Ok(salvage)
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::commands::recurrence::{Recurrence, Rule};
+    use crate::commands::task::{now_deadline, Priority, TaskBuilder};
+
+    fn projects() -> Vec<Project> {
+        let mut work = Project::new("work".to_string());
+        work.push(
+            TaskBuilder::new("everything \"quoted\"".to_string())
+                .priority(Priority::High)
+                .deadline(now_deadline())
+                .recurrence(
+                    Recurrence::new(Rule::Weekdays(vec![chrono::Weekday::Mon]))
+                        .until(now_deadline()),
+                )
+                .tag("@review".to_string())
+                .build(),
+        );
+        let mut done = TaskBuilder::new("done".to_string()).build();
+        done.completed = Some(now_deadline());
+        work.push(done);
+        work.push(
+            TaskBuilder::new("every other day".to_string())
+                .recurrence(Recurrence::new(Rule::EveryNDays(2)))
+                .build(),
+        );
+        vec![Project::new("empty".to_string()), work]
+    }
+
+    #[test]
+    fn from_path() {
+        assert_eq!(Backend::from_path(Path::new("tasks")), Backend::Json);
+        assert_eq!(Backend::from_path(Path::new("tasks.json")), Backend::Json);
+        assert_eq!(Backend::from_path(Path::new("tasks.TOML")), Backend::Toml);
+        assert_eq!(Backend::from_path(Path::new("a/tasks.yml")), Backend::Yaml);
+        assert_eq!(Backend::from_path(Path::new("tasks.db")), Backend::Sqlite);
+        assert_eq!("yaml".parse::<Backend>(), Ok(Backend::Yaml));
+        assert!("xml".parse::<Backend>().is_err());
+    }
+
+    #[test]
+    fn round_trip() {
+        let projects = projects();
+        for (backend, name) in [
+            (Backend::Json, "test_file_backend.json"),
+            (Backend::Toml, "test_file_backend.toml"),
+            (Backend::Yaml, "test_file_backend.yaml"),
+            (Backend::Sqlite, "test_file_backend.db"),
+        ] {
+            let path = Path::new(name);
+            let storage = backend.open(path);
+            storage.store(&projects).unwrap();
+            assert_eq!(storage.load().unwrap(), projects, "{}", backend);
+
+            // storing again replaces everything
+            storage.store(&projects[1..]).unwrap();
+            assert_eq!(storage.load().unwrap(), &projects[1..], "{}", backend);
+
+            fs::remove_file(path).unwrap();
+            for n in 1..=3 {
+                let _ = fs::remove_file(format!("{}.bak.{}", name, n));
+            }
+        }
+    }
+
+    #[test]
+    fn empty_files() {
+        for (backend, name) in [
+            (Backend::Toml, "test_file_backend_empty.toml"),
+            (Backend::Yaml, "test_file_backend_empty.yaml"),
+            (Backend::Sqlite, "test_file_backend_empty.db"),
+        ] {
+            let path = Path::new(name);
+            fs::write(path, "").unwrap();
+            assert_eq!(backend.open(path).load().unwrap(), vec![], "{}", backend);
+            fs::remove_file(path).unwrap();
+        }
+    }
+
+    #[test]
+    fn version_1_files() {
+        let projects = projects();
+        for (backend, name) in [
+            (Backend::Json, "test_file_backend_v1.json"),
+            (Backend::Toml, "test_file_backend_v1.toml"),
+            (Backend::Yaml, "test_file_backend_v1.yaml"),
+        ] {
+            let path = Path::new(name);
+            let contents = match backend {
+                Backend::Toml => {
+                    let mut document = toml::Table::new();
+                    document.insert(
+                        "projects".to_string(),
+                        toml::Value::try_from(&projects).unwrap(),
+                    );
+                    toml::to_string(&document).unwrap()
+                }
+                Backend::Yaml => serde_yaml::to_string(&projects).unwrap(),
+                _ => serde_json::to_string(&projects).unwrap(),
+            };
+            fs::write(path, contents).unwrap();
+
+            let storage = backend.open(path);
+            assert_eq!(storage.version().unwrap(), 1, "{}", backend);
+            assert_eq!(storage.load().unwrap(), projects, "{}", backend);
+            storage.store(&projects).unwrap();
+            assert_eq!(storage.version().unwrap(), schema::CURRENT_VERSION);
+
+            fs::remove_file(path).unwrap();
+            let _ = fs::remove_file(format!("{}.bak.1", name));
+        }
+    }
+
+    #[test]
+    fn format_errors() {
+        let path = Path::new("test_file_backend_invalid.toml");
+        fs::write(path, "projects = 3").unwrap();
+        assert!(matches!(
+            Backend::Toml.open(path).load(),
+            Err(RustaskError::FileError(_))
+        ));
+        fs::remove_file(path).unwrap();
+
+        let path = Path::new("test_file_backend_invalid.json");
+        fs::write(path, "{\n  \"version\": 2,\n  \"projects\": [}\n}").unwrap();
+        match Backend::Json.open(path).load() {
+            Err(RustaskError::FileError(e)) => {
+                assert_eq!(e.path, path.display().to_string());
+                assert_eq!(e.operation, "read the task file");
+                assert_eq!(e.position, Some((3, 16)));
+                assert!(!e.message.contains("at line"));
+                assert!(e.suggestion.unwrap().contains("repair"));
+            }
+            other => panic!("unexpected {:?}", other),
+        }
+        fs::remove_file(path).unwrap();
+    }
+}
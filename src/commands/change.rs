@@ -0,0 +1,234 @@
+// change.rs
+//
+// describe mutations of single projects and tasks
+
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+
+use crate::commands::project::Project;
+use crate::commands::task::Task;
+
+/// Projects by name, as far as they are known: `None` for projects which do not exist
+pub type Projects = BTreeMap<String, Option<Project>>;
+
+/// A mutation of a single project or task, with what it replaces
+///
+/// Storing only the changes rather than every project lets a backend write just the
+/// rows a mutation touches.
+// an operation makes a handful of changes, not worth boxing the tasks for
+#[allow(clippy::large_enum_variant)]
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+pub enum Change {
+    /// A project created (no `before`), removed (no `after`) or renamed
+    Project {
+        before: Option<String>,
+        after: Option<String>,
+    },
+    /// A task added (no `before`), removed (no `after`) or modified
+    Task {
+        project: String,
+        before: Option<Task>,
+        after: Option<Task>,
+    },
+}
+
+impl Change {
+    /// The change which reverts this one
+    pub fn inverse(&self) -> Change {
+        match self {
+            Change::Project { before, after } => Change::Project {
+                before: after.clone(),
+                after: before.clone(),
+            },
+            Change::Task {
+                project,
+                before,
+                after,
+            } => Change::Task {
+                project: project.clone(),
+                before: after.clone(),
+                after: before.clone(),
+            },
+        }
+    }
+
+    /// The names of the projects the change reads or writes
+    pub fn projects(&self) -> Vec<&str> {
+        match self {
+            Change::Project { before, after } => before
+                .iter()
+                .chain(after.iter())
+                .map(String::as_str)
+                .collect(),
+            Change::Task { project, .. } => vec![project.as_str()],
+        }
+    }
+
+    /// Apply the change to `projects`, which must hold every project it names
+    ///
+    /// Returns false, leaving `projects` untouched, if they are not as the change
+    /// expects: a project or task it replaces is missing or different, one it creates
+    /// already exists, or a project it removes still has tasks.
+    pub fn apply(&self, projects: &mut Projects) -> bool {
+        match self {
+            Change::Project { before, after } => {
+                let source = match before {
+                    Some(name) => match existing(projects, name) {
+                        Some(p) if after.is_some() || p.is_empty() => Some(p.clone()),
+                        _ => return false,
+                    },
+                    None => None,
+                };
+                if let Some(name) = after {
+                    if existing(projects, name).is_some() {
+                        return false;
+                    }
+                }
+
+                if let Some(name) = before {
+                    projects.insert(name.clone(), None);
+                }
+                if let Some(name) = after {
+                    let mut project = source.unwrap_or_else(|| Project::new(name.clone()));
+                    project.rename(name.clone());
+                    projects.insert(name.clone(), Some(project));
+                }
+                true
+            }
+            Change::Task {
+                project,
+                before,
+                after,
+            } => {
+                let Some(Some(p)) = projects.get_mut(project) else {
+                    return false;
+                };
+                let expected = match (before, after) {
+                    (Some(task), _) => p.get(&task.id) == Some(task),
+                    (None, Some(task)) => p.get(&task.id).is_none(),
+                    (None, None) => true,
+                };
+                if !expected {
+                    return false;
+                }
+
+                if let Some(task) = before {
+                    let _ = p.remove(&task.id);
+                }
+                if let Some(task) = after {
+                    p.push(task.clone());
+                }
+                true
+            }
+        }
+    }
+}
+
+fn existing<'a>(projects: &'a Projects, name: &str) -> Option<&'a Project> {
+    projects.get(name).and_then(Option::as_ref)
+}
+
+/// The changes which revert `changes`, in the order to apply them
+pub fn inverse(changes: &[Change]) -> Vec<Change> {
+    changes.iter().rev().map(Change::inverse).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::commands::task::TaskBuilder;
+
+    fn projects(names: &[&str]) -> Projects {
+        names
+            .iter()
+            .map(|n| (n.to_string(), Some(Project::new(n.to_string()))))
+            .collect()
+    }
+
+    #[test]
+    fn apply_and_revert() {
+        let task = TaskBuilder::new("task".to_string()).build();
+        let mut done = task.clone();
+        done.completed = Some(crate::commands::task::now_deadline());
+        let changes = vec![
+            Change::Project {
+                before: None,
+                after: Some("b".to_string()),
+            },
+            Change::Task {
+                project: "b".to_string(),
+                before: None,
+                after: Some(task.clone()),
+            },
+            Change::Task {
+                project: "b".to_string(),
+                before: Some(task.clone()),
+                after: Some(done.clone()),
+            },
+            Change::Project {
+                before: Some("b".to_string()),
+                after: Some("c".to_string()),
+            },
+        ];
+
+        let original = projects(&["a"]);
+        let mut current = original.clone();
+        for change in &changes {
+            assert!(change.apply(&mut current), "{:?}", change);
+        }
+        assert_eq!(current["b"], None);
+        assert_eq!(current["c"].as_ref().unwrap().tasks(), &vec![done]);
+
+        for change in inverse(&changes) {
+            assert!(change.apply(&mut current), "{:?}", change);
+        }
+        assert_eq!(current["c"], None);
+        assert_eq!(current["b"], None);
+        assert_eq!(current["a"], original["a"]);
+    }
+
+    #[test]
+    fn conflicts() {
+        let task = TaskBuilder::new("task".to_string()).build();
+        let mut current = projects(&["a", "b"]);
+        let add = Change::Task {
+            project: "a".to_string(),
+            before: None,
+            after: Some(task.clone()),
+        };
+        assert!(add.apply(&mut current));
+        let before = current.clone();
+
+        // the task already exists
+        assert!(!add.apply(&mut current));
+        // the project does not exist
+        assert!(!Change::Task {
+            project: "c".to_string(),
+            before: None,
+            after: Some(task.clone()),
+        }
+        .apply(&mut current));
+        // the name is taken
+        assert!(!Change::Project {
+            before: Some("a".to_string()),
+            after: Some("b".to_string()),
+        }
+        .apply(&mut current));
+        // the project still has tasks
+        assert!(!Change::Project {
+            before: Some("a".to_string()),
+            after: None,
+        }
+        .apply(&mut current));
+        // the task is not as expected
+        let mut edited = task.clone();
+        edited.description = "edited".to_string();
+        assert!(!Change::Task {
+            project: "a".to_string(),
+            before: Some(edited),
+            after: None,
+        }
+        .apply(&mut current));
+        assert_eq!(current, before);
+    }
+}
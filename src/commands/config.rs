@@ -0,0 +1,415 @@
+// config.rs
+//
+// read the configuration, from the config directory and next to the task file
+
+use chrono::format::{Item, StrftimeItems};
+use colored::{Color, ColoredString, Colorize};
+use serde::{Deserialize, Deserializer};
+use std::collections::BTreeMap;
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::sync::OnceLock;
+
+use crate::commands::error::{FileError, RustaskError};
+use crate::commands::selection::Probabilities;
+use crate::commands::task::Priority;
+
+static CONFIG: OnceLock<Config> = OnceLock::new();
+
+/// Set the configuration for the rest of the process
+///
+/// Returns false, leaving it as it was, if it was already set or used.
+pub fn set(config: Config) -> bool {
+    CONFIG.set(config).is_ok()
+}
+
+/// The configuration set with [`set`], or the default one
+pub fn get() -> &'static Config {
+    CONFIG.get_or_init(Config::default)
+}
+
+/// The configuration file shared by every task file: `$XDG_CONFIG_HOME/rustask/config.toml`,
+/// or `~/.config/rustask/config.toml` if that is not set
+pub fn global_path() -> Option<PathBuf> {
+    let config_home = env::var_os("XDG_CONFIG_HOME")
+        .map(PathBuf::from)
+        .filter(|path| path.is_absolute())
+        .or_else(|| env::var_os("HOME").map(|home| Path::new(&home).join(".config")))?;
+    Some(config_home.join("rustask").join("config.toml"))
+}
+
+/// The configuration file of the task file at `path`, overriding the global one
+pub fn task_file_path(path: &Path) -> PathBuf {
+    let mut name = path.as_os_str().to_owned();
+    name.push(".config.toml");
+    PathBuf::from(name)
+}
+
+/// A text style: `[bold] [dimmed] [italic] [underline] [<color>] [on <color>]`, with
+/// colors such as `red` or `bright white`
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Style {
+    pub foreground: Option<Color>,
+    pub background: Option<Color>,
+    pub bold: bool,
+    pub dimmed: bool,
+    pub italic: bool,
+    pub underline: bool,
+}
+
+impl Style {
+    pub fn paint(&self, text: &str) -> ColoredString {
+        let mut painted = ColoredString::from(text);
+        if let Some(color) = self.foreground {
+            painted = painted.color(color);
+        }
+        if let Some(color) = self.background {
+            painted = painted.on_color(color);
+        }
+        if self.bold {
+            painted = painted.bold();
+        }
+        if self.dimmed {
+            painted = painted.dimmed();
+        }
+        if self.italic {
+            painted = painted.italic();
+        }
+        if self.underline {
+            painted = painted.underline();
+        }
+        painted
+    }
+}
+
+impl FromStr for Style {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut style = Style::default();
+        let lower = s.to_lowercase();
+        let mut words = lower.split_whitespace();
+        let color = |word: &str, words: &mut dyn Iterator<Item = &str>| {
+            let name = match word {
+                "bright" => format!("bright {}", words.next().unwrap_or_default()),
+                _ => word.to_string(),
+            };
+            Color::from_str(&name).map_err(|_| format!("unknown color `{}` in `{}`", name, s))
+        };
+
+        while let Some(word) = words.next() {
+            match word {
+                "bold" => style.bold = true,
+                "dimmed" => style.dimmed = true,
+                "italic" => style.italic = true,
+                "underline" => style.underline = true,
+                "on" => {
+                    let word = words.next().unwrap_or_default();
+                    style.background = Some(color(word, &mut words)?);
+                }
+                word => style.foreground = Some(color(word, &mut words)?),
+            }
+        }
+        Ok(style)
+    }
+}
+
+impl<'de> Deserialize<'de> for Style {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        String::deserialize(deserializer)?
+            .parse()
+            .map_err(serde::de::Error::custom)
+    }
+}
+
+/// How tasks are shown, by priority
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Colors {
+    pub urgent: Style,
+    pub high: Style,
+    pub normal: Style,
+    pub low: Style,
+    pub note: Style,
+    /// Tasks without a priority
+    pub unset: Style,
+    pub tag: Style,
+    /// The mark of tasks waiting on unfinished ones
+    pub blocked: Style,
+}
+
+impl Default for Colors {
+    fn default() -> Colors {
+        let style = |s: &str| s.parse().unwrap();
+        Colors {
+            urgent: style("bold bright white on red"),
+            high: style("red"),
+            normal: style("yellow"),
+            low: style("green"),
+            note: style("cyan"),
+            unset: style("bold"),
+            tag: style("blue"),
+            blocked: style("dimmed"),
+        }
+    }
+}
+
+impl Colors {
+    /// The style of tasks with the given priority
+    pub fn priority(&self, priority: Option<&Priority>) -> &Style {
+        match priority {
+            Some(Priority::Urgent) => &self.urgent,
+            Some(Priority::High) => &self.high,
+            Some(Priority::Normal) => &self.normal,
+            Some(Priority::Low) => &self.low,
+            Some(Priority::Note) => &self.note,
+            None => &self.unset,
+        }
+    }
+}
+
+/// How deadlines are read and shown
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Deadlines {
+    /// How many days before its deadline a task starts being listed more often
+    pub horizon_days: u32,
+    /// `strftime` formats of dates (and times) accepted besides the usual forms
+    pub input_formats: Vec<String>,
+    /// `strftime` format of the times shown
+    pub output_format: String,
+}
+
+impl Default for Deadlines {
+    fn default() -> Deadlines {
+        Deadlines {
+            horizon_days: 7,
+            input_formats: vec![],
+            output_format: "%F %H:%M".to_string(),
+        }
+    }
+}
+
+/// Defaults and display settings
+///
+/// Read from the global configuration file and then from the one of the task file,
+/// whose settings take precedence; anything not set in either keeps its default.
+#[derive(Debug, Clone, Default, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    /// Project of tasks added without one
+    pub default_project: Option<String>,
+    /// Priority of tasks added without one
+    #[serde(deserialize_with = "priority")]
+    pub default_priority: Option<Priority>,
+    /// Subcommand, with its arguments, run when none is given
+    pub default_command: Option<String>,
+    pub probabilities: Probabilities,
+    pub colors: Colors,
+    pub deadlines: Deadlines,
+    /// Task files by name, to select with `--context`; a leading `~/` is the home
+    /// directory
+    pub contexts: BTreeMap<String, PathBuf>,
+}
+
+fn priority<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Option<Priority>, D::Error> {
+    let priority = String::deserialize(deserializer)?;
+    priority
+        .parse()
+        .map(Some)
+        .map_err(|_| serde::de::Error::custom(format!("unknown priority `{}`", priority)))
+}
+
+impl Config {
+    /// Read the configuration files at `paths` in order, later ones overriding earlier
+    /// ones; files which do not exist are skipped
+    pub fn load(paths: &[PathBuf]) -> Result<Config, RustaskError> {
+        let mut merged = toml::Table::new();
+        for path in paths.iter().filter(|path| path.exists()) {
+            let error = |position, message: &str| {
+                RustaskError::FileError(FileError {
+                    path: path.display().to_string(),
+                    operation: "read the configuration".to_string(),
+                    position,
+                    message: message.to_string(),
+                    suggestion: None,
+                })
+            };
+            let contents = fs::read_to_string(path).map_err(|e| error(None, &e.to_string()))?;
+            let table = contents.parse::<toml::Table>().map_err(|e| {
+                let position = e
+                    .span()
+                    .map(|s| FileError::position_of(contents.as_bytes(), s.start));
+                error(position, e.message())
+            })?;
+            // check each file on its own, to point at the one in error
+            Config::from_table(table.clone()).map_err(|e| error(None, &e))?;
+            merge(&mut merged, table);
+        }
+        Config::from_table(merged).map_err(RustaskError::FormatError)
+    }
+
+    /// The task file of the context with the given name
+    pub fn context(&self, name: &str) -> Result<PathBuf, RustaskError> {
+        let path = self
+            .contexts
+            .get(name)
+            .ok_or_else(|| RustaskError::ContextNotFound(name.to_string()))?;
+        match (path.strip_prefix("~"), env::var_os("HOME")) {
+            (Ok(rest), Some(home)) => Ok(Path::new(&home).join(rest)),
+            _ => Ok(path.clone()),
+        }
+    }
+
+    /// The task file of every context, by name
+    pub fn contexts(&self) -> Result<Vec<(String, PathBuf)>, RustaskError> {
+        self.contexts
+            .keys()
+            .map(|name| Ok((name.clone(), self.context(name)?)))
+            .collect()
+    }
+
+    fn from_table(table: toml::Table) -> Result<Config, String> {
+        let config: Config = toml::Value::Table(table)
+            .try_into()
+            .map_err(|e: toml::de::Error| e.message().to_string())?;
+
+        let p = &config.probabilities;
+        for (priority, probability) in [
+            ("urgent", p.urgent),
+            ("high", p.high),
+            ("normal", p.normal),
+            ("low", p.low),
+            ("note", p.note),
+        ] {
+            if !(0.0..=1.0).contains(&probability) {
+                return Err(format!(
+                    "the probability of {} tasks is not between 0 and 1",
+                    priority
+                ));
+            }
+        }
+        let d = &config.deadlines;
+        for format in d.input_formats.iter().chain([&d.output_format]) {
+            if StrftimeItems::new(format).any(|item| item == Item::Error) {
+                return Err(format!("invalid date format `{}`", format));
+            }
+        }
+        Ok(config)
+    }
+}
+
+/// Merge `overlay` into `base`, tables key by key
+fn merge(base: &mut toml::Table, overlay: toml::Table) {
+    for (key, value) in overlay {
+        match (base.get_mut(&key), value) {
+            (Some(toml::Value::Table(base)), toml::Value::Table(overlay)) => merge(base, overlay),
+            (_, value) => {
+                base.insert(key, value);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn styles() {
+        assert_eq!(
+            "bold bright white on red"
+                .parse::<Style>()
+                .unwrap()
+                .paint("text"),
+            "text".on_red().bold().bright_white()
+        );
+        assert_eq!("".parse::<Style>().unwrap().paint("text"), "text".normal());
+        assert_eq!(
+            "on bright blue underline".parse::<Style>(),
+            Ok(Style {
+                background: Some(Color::BrightBlue),
+                underline: true,
+                ..Style::default()
+            })
+        );
+        assert!("reddish".parse::<Style>().is_err());
+        assert!("on".parse::<Style>().is_err());
+    }
+
+    #[test]
+    fn load_and_override() {
+        let global = PathBuf::from("test_file_config_global.toml");
+        let local = PathBuf::from("test_file_config_local.toml");
+        fs::write(
+            &global,
+            r#"
+default_project = "inbox"
+default_priority = "low"
+default_command = "listall"
+
+[colors]
+normal = "blue"
+
+[deadlines]
+horizon_days = 3
+input_formats = ["%d/%m/%Y"]
+
+[contexts]
+team = "/shared/team.json"
+"#,
+        )
+        .unwrap();
+        fs::write(
+            &local,
+            "default_project = \"work\"\n[deadlines]\nhorizon_days = 14\n[probabilities]\nnote = 0.5\n",
+        )
+        .unwrap();
+
+        let config = Config::load(&[global.clone(), local.clone(), "missing".into()]).unwrap();
+        assert_eq!(config.default_project.as_deref(), Some("work"));
+        assert_eq!(config.default_priority, Some(Priority::Low));
+        assert_eq!(config.default_command.as_deref(), Some("listall"));
+        assert_eq!(config.colors.normal, "blue".parse().unwrap());
+        assert_eq!(config.colors.high, Colors::default().high);
+        assert_eq!(config.deadlines.horizon_days, 14);
+        assert_eq!(config.deadlines.input_formats, vec!["%d/%m/%Y"]);
+        assert_eq!(config.deadlines.output_format, "%F %H:%M");
+        assert_eq!(config.probabilities.note, 0.5);
+        assert_eq!(config.probabilities.urgent, 1.0);
+        assert_eq!(
+            config.contexts().unwrap(),
+            vec![("team".to_string(), PathBuf::from("/shared/team.json"))]
+        );
+        assert_eq!(
+            config.context("personal"),
+            Err(RustaskError::ContextNotFound("personal".to_string()))
+        );
+
+        assert_eq!(Config::load(&[]).unwrap(), Config::default());
+        fs::remove_file(global).unwrap();
+        fs::remove_file(local).unwrap();
+    }
+
+    #[test]
+    fn errors() {
+        let path = PathBuf::from("test_file_config_errors.toml");
+        for (contents, position) in [
+            ("default_project = ", Some((1, 19))),
+            ("colour = \"red\"", None),
+            ("default_priority = \"whenever\"", None),
+            ("[colors]\nhigh = \"reddish\"", None),
+            ("[probabilities]\nhigh = 2.0", None),
+            ("[deadlines]\noutput_format = \"%Q\"", None),
+        ] {
+            fs::write(&path, contents).unwrap();
+            match Config::load(std::slice::from_ref(&path)) {
+                Err(RustaskError::FileError(e)) => assert_eq!(e.position, position, "{}", contents),
+                other => panic!("unexpected {:?} for {}", other, contents),
+            }
+        }
+        fs::remove_file(path).unwrap();
+    }
+}
@@ -0,0 +1,332 @@
+// deadline.rs
+//
+// parse deadlines, absolute or relative to now
+
+use chrono::naive::{NaiveDate, NaiveDateTime, NaiveTime};
+use chrono::{Datelike, Days, Local, Months, TimeZone, Weekday};
+
+use crate::commands::config;
+use crate::commands::task::{now_deadline, Deadline};
+
+const ACCEPTED_FORMS: &str = "\
+    YYYY-MM-DD, now, today, tomorrow, yesterday, eod (end of day), eow (end of week), \
+    eom (end of month), a weekday (fri, friday), next <weekday>, next week, next month, \
+    +<n><unit> (e.g. +3d) or in <n> <unit>s (e.g. in 2 weeks) with unit one of \
+    m(inute), h(our), d(ay), w(eek), mo(nth); \
+    any of these may be followed by a time: 17:00, 5pm, 5:30pm, noon or midnight, \
+    and a time alone means its next occurrence";
+
+#[derive(thiserror::Error, Debug, PartialEq, Eq)]
+pub enum DeadlineParseError {
+    #[error("failed to parse `{}` as a deadline; accepted forms: {}", .0, ACCEPTED_FORMS)]
+    Unrecognized(String),
+    #[error("failed to assign a timezone")]
+    TimezoneError,
+}
+
+/// Assign the local timezone, skipping forward over DST gaps
+pub(crate) fn localize(naive: NaiveDateTime) -> Option<Deadline> {
+    Local.from_local_datetime(&naive).earliest().or_else(|| {
+        Local
+            .from_local_datetime(&(naive + chrono::Duration::hours(1)))
+            .earliest()
+    })
+}
+
+/// Parse a deadline, relative forms being relative to the current time
+///
+/// See [`parse_deadline_at`] for the accepted forms
+pub fn parse_deadline(s: &str) -> Result<Deadline, DeadlineParseError> {
+    parse_deadline_at(s, now_deadline())
+}
+
+/// Parse a deadline, relative forms being relative to `now`
+///
+/// Accepted forms are absolute dates (`2022-05-01`), named days (`today`, `tomorrow`,
+/// `friday`, `next friday`, `eod`, `eow`, ...) and offsets (`+3d`, `in 2 weeks`),
+/// optionally followed by a time (`17:00`, `5pm`). Days without a time are due at midnight.
+///
+/// # Examples
+///
+/// ```
+/// use rustask::commands::task::{parse_deadline, parse_deadline_at};
+/// let now = parse_deadline("2022-05-04 10:00").unwrap();
+/// assert_eq!(
+///     parse_deadline_at("tomorrow 5pm", now).unwrap(),
+///     parse_deadline("2022-05-05 17:00").unwrap()
+/// );
+/// ```
+pub fn parse_deadline_at(s: &str, now: Deadline) -> Result<Deadline, DeadlineParseError> {
+    parse_deadline_with(s, now, &config::get().deadlines.input_formats)
+}
+
+/// Parse a deadline like [`parse_deadline_at`], trying the given `strftime` formats
+/// (of a date, or of a date and a time) before the usual forms
+pub fn parse_deadline_with(
+    s: &str,
+    now: Deadline,
+    formats: &[String],
+) -> Result<Deadline, DeadlineParseError> {
+    let unrecognized = || DeadlineParseError::Unrecognized(s.to_string());
+
+    let formatted = formats.iter().find_map(|format| {
+        NaiveDateTime::parse_from_str(s.trim(), format)
+            .ok()
+            .or_else(|| {
+                NaiveDate::parse_from_str(s.trim(), format)
+                    .ok()?
+                    .and_hms_opt(0, 0, 0)
+            })
+    });
+    if let Some(naive) = formatted {
+        return localize(naive).ok_or(DeadlineParseError::TimezoneError);
+    }
+
+    let lower = s.trim().to_lowercase();
+    let words: Vec<&str> = lower.split_whitespace().filter(|w| *w != "at").collect();
+    let now = now.naive_local();
+
+    let (day_words, time) = match words.split_last() {
+        Some((last, rest)) => match parse_time(last) {
+            Some(time) => (rest, Some(time)),
+            None => (&words[..], None),
+        },
+        None => return Err(unrecognized()),
+    };
+
+    let naive = match (day_words, time) {
+        // a time alone: its next occurrence
+        ([], Some(time)) => {
+            let today = now.date().and_time(time);
+            if today > now {
+                today
+            } else {
+                today + chrono::Duration::days(1)
+            }
+        }
+        (words, Some(time)) => parse_day(words, now)
+            .ok_or_else(unrecognized)?
+            .date()
+            .and_time(time),
+        (words, None) => parse_day(words, now).ok_or_else(unrecognized)?,
+    };
+
+    localize(naive).ok_or(DeadlineParseError::TimezoneError)
+}
+
+/// Parse a time of day: `17:00`, `5pm`, `5:30am`, `noon` or `midnight`
+fn parse_time(word: &str) -> Option<NaiveTime> {
+    match word {
+        "noon" => return NaiveTime::from_hms_opt(12, 0, 0),
+        "midnight" => return NaiveTime::from_hms_opt(0, 0, 0),
+        _ => {}
+    }
+
+    let (clock, pm) = if let Some(clock) = word.strip_suffix("am") {
+        (clock, Some(false))
+    } else if let Some(clock) = word.strip_suffix("pm") {
+        (clock, Some(true))
+    } else {
+        (word, None)
+    };
+
+    let (hour, minute) = match clock.split_once(':') {
+        Some((h, m)) if m.len() == 2 => (h.parse::<u32>().ok()?, m.parse::<u32>().ok()?),
+        None if pm.is_some() => (clock.parse::<u32>().ok()?, 0),
+        _ => return None,
+    };
+
+    let hour = match pm {
+        Some(_) if hour == 0 || hour > 12 => return None,
+        Some(false) => hour % 12,
+        Some(true) => hour % 12 + 12,
+        None => hour,
+    };
+    NaiveTime::from_hms_opt(hour, minute, 0)
+}
+
+/// Parse an offset from now: `<n><unit>` or `<n> <unit>`
+fn parse_offset(amount: &str, unit: &str, now: NaiveDateTime) -> Option<NaiveDateTime> {
+    let n = amount.parse::<u32>().ok()?;
+    let unit = unit
+        .strip_suffix('s')
+        .filter(|u| u.len() > 1)
+        .unwrap_or(unit);
+    match unit {
+        "m" | "min" | "minute" => now.checked_add_signed(chrono::Duration::try_minutes(n.into())?),
+        "h" | "hour" => now.checked_add_signed(chrono::Duration::try_hours(n.into())?),
+        "d" | "day" => now.checked_add_days(Days::new(n.into())),
+        "w" | "week" => now.checked_add_days(Days::new(7 * n as u64)),
+        "mo" | "month" => now.checked_add_months(Months::new(n)),
+        _ => None,
+    }
+}
+
+fn parse_day(words: &[&str], now: NaiveDateTime) -> Option<NaiveDateTime> {
+    let today = now.date();
+    let midnight = |date: NaiveDate| date.and_hms_opt(0, 0, 0);
+    let end_of = |date: NaiveDate| date.and_hms_opt(23, 59, 0);
+    let first_after_today = |weekday: Weekday| {
+        (1..=7)
+            .filter_map(|n| today.checked_add_days(Days::new(n)))
+            .find(|d| d.weekday() == weekday)
+    };
+
+    match words {
+        ["now"] => Some(now),
+        ["today"] => midnight(today),
+        ["tomorrow"] => midnight(today.succ_opt()?),
+        ["yesterday"] => midnight(today.pred_opt()?),
+        ["eod"] => end_of(today),
+        ["eow"] => end_of(
+            today.checked_add_days(Days::new(6 - today.weekday().num_days_from_monday() as u64))?,
+        ),
+        ["eom"] => end_of(
+            NaiveDate::from_ymd_opt(today.year(), today.month(), 1)?
+                .checked_add_months(Months::new(1))?
+                .pred_opt()?,
+        ),
+        ["next", "week"] => midnight(
+            today.checked_add_days(Days::new(7 - today.weekday().num_days_from_monday() as u64))?,
+        ),
+        ["next", "month"] => midnight(
+            NaiveDate::from_ymd_opt(today.year(), today.month(), 1)?
+                .checked_add_months(Months::new(1))?,
+        ),
+        ["next", day] | [day] if day.parse::<Weekday>().is_ok() => {
+            midnight(first_after_today(day.parse::<Weekday>().ok()?)?)
+        }
+        ["in", amount, unit] => parse_offset(amount, unit, now),
+        [offset] if offset.starts_with('+') => {
+            let offset = &offset[1..];
+            let split = offset.find(|c: char| !c.is_ascii_digit())?;
+            parse_offset(&offset[..split], &offset[split..], now)
+        }
+        [date] => midnight(NaiveDate::parse_from_str(date, "%F").ok()?),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Wednesday, 2022-05-04 10:00
+    fn now() -> Deadline {
+        at("2022-05-04 10:00")
+    }
+
+    fn at(s: &str) -> Deadline {
+        let naive = NaiveDateTime::parse_from_str(s, "%F %H:%M").unwrap();
+        localize(naive).unwrap()
+    }
+
+    fn parse(s: &str) -> Deadline {
+        parse_deadline_at(s, now()).unwrap()
+    }
+
+    #[test]
+    fn absolute() {
+        assert_eq!(parse("2022-06-01"), at("2022-06-01 00:00"));
+        assert_eq!(parse("2022-06-01 13:45"), at("2022-06-01 13:45"));
+        assert_eq!(parse("2022-06-01 at 1pm"), at("2022-06-01 13:00"));
+    }
+
+    #[test]
+    fn named_days() {
+        assert_eq!(parse("now"), now());
+        assert_eq!(parse("today"), at("2022-05-04 00:00"));
+        assert_eq!(parse("Tomorrow"), at("2022-05-05 00:00"));
+        assert_eq!(parse("yesterday"), at("2022-05-03 00:00"));
+        assert_eq!(parse("eod"), at("2022-05-04 23:59"));
+        assert_eq!(parse("eow"), at("2022-05-08 23:59"));
+        assert_eq!(parse("eom"), at("2022-05-31 23:59"));
+        assert_eq!(parse("next week"), at("2022-05-09 00:00"));
+        assert_eq!(parse("next month"), at("2022-06-01 00:00"));
+    }
+
+    #[test]
+    fn weekdays() {
+        assert_eq!(parse("friday"), at("2022-05-06 00:00"));
+        assert_eq!(parse("fri"), at("2022-05-06 00:00"));
+        assert_eq!(parse("next friday"), at("2022-05-06 00:00"));
+        // today is a wednesday: the next one is a week away
+        assert_eq!(parse("wed"), at("2022-05-11 00:00"));
+        assert_eq!(parse("monday 9:30"), at("2022-05-09 09:30"));
+    }
+
+    #[test]
+    fn offsets() {
+        assert_eq!(parse("+3d"), at("2022-05-07 10:00"));
+        assert_eq!(parse("+2w"), at("2022-05-18 10:00"));
+        assert_eq!(parse("+4h"), at("2022-05-04 14:00"));
+        assert_eq!(parse("+30m"), at("2022-05-04 10:30"));
+        assert_eq!(parse("+1mo"), at("2022-06-04 10:00"));
+        assert_eq!(parse("in 2 weeks"), at("2022-05-18 10:00"));
+        assert_eq!(parse("in 1 day"), at("2022-05-05 10:00"));
+        assert_eq!(parse("in 3 months"), at("2022-08-04 10:00"));
+        assert_eq!(parse("+3d 17:00"), at("2022-05-07 17:00"));
+    }
+
+    #[test]
+    fn huge_offsets() {
+        // u32::MAX minutes is some 8000 years away, u32::MAX hours is out of range
+        assert_eq!(parse("+4294967295m").year(), 10188);
+        for s in ["+4294967295h", "in 4294967295 hours", "+4294967295mo"] {
+            assert_eq!(
+                parse_deadline_at(s, now()),
+                Err(DeadlineParseError::Unrecognized(s.to_string())),
+                "{}",
+                s
+            );
+        }
+    }
+
+    #[test]
+    fn times() {
+        assert_eq!(parse("17:00"), at("2022-05-04 17:00"));
+        assert_eq!(parse("5pm"), at("2022-05-04 17:00"));
+        assert_eq!(parse("5:30pm"), at("2022-05-04 17:30"));
+        assert_eq!(parse("noon"), at("2022-05-04 12:00"));
+        // already past today: tomorrow
+        assert_eq!(parse("9am"), at("2022-05-05 09:00"));
+        assert_eq!(parse("12am"), at("2022-05-05 00:00"));
+        assert_eq!(parse("tomorrow 12pm"), at("2022-05-05 12:00"));
+        assert_eq!(parse("tomorrow at 5pm"), at("2022-05-05 17:00"));
+    }
+
+    #[test]
+    fn formats() {
+        let formats = ["%d/%m/%Y %H:%M".to_string(), "%d/%m/%Y".to_string()];
+        let parse = |s| parse_deadline_with(s, now(), &formats);
+        assert_eq!(parse("01/06/2022"), Ok(at("2022-06-01 00:00")));
+        assert_eq!(parse("01/06/2022 13:45"), Ok(at("2022-06-01 13:45")));
+        assert_eq!(parse("tomorrow"), Ok(at("2022-05-05 00:00")));
+        assert!(parse("2022/06/01").is_err());
+    }
+
+    #[test]
+    fn errors() {
+        for s in [
+            "",
+            "someday",
+            "25:00",
+            "13pm",
+            "+3x",
+            "in two weeks",
+            "2022-13-01",
+            "next",
+        ] {
+            assert_eq!(
+                parse_deadline_at(s, now()),
+                Err(DeadlineParseError::Unrecognized(s.to_string())),
+                "{}",
+                s
+            );
+        }
+        assert!(parse_deadline_at("soon", now())
+            .unwrap_err()
+            .to_string()
+            .contains("accepted forms"));
+    }
+}
@@ -0,0 +1,320 @@
+// dependency.rs
+//
+// follow the tasks tasks wait on, across projects
+
+use std::collections::{btree_set, BTreeMap, BTreeSet};
+use std::io::{self, Write};
+
+use crate::commands::error::RustaskError;
+use crate::commands::project::Project;
+use crate::commands::task::Task;
+
+/// A task, with where it is
+#[derive(Debug, Clone, Copy)]
+struct Node<'a> {
+    project: &'a str,
+    task: &'a Task,
+    /// Shortest prefix length which tells apart every task in the project
+    id_prefix_len: usize,
+}
+
+/// The tasks of several projects, by id, to follow their dependencies
+#[derive(Debug, Clone)]
+pub struct Graph<'a> {
+    tasks: BTreeMap<&'a str, Node<'a>>,
+}
+
+impl<'a> Graph<'a> {
+    pub fn new<I: IntoIterator<Item = &'a Project>>(projects: I) -> Graph<'a> {
+        let mut tasks = BTreeMap::new();
+        for project in projects {
+            let id_prefix_len = project.id_prefix_len();
+            for task in project.tasks() {
+                tasks.insert(
+                    task.id.as_str(),
+                    Node {
+                        project: &project.name,
+                        task,
+                        id_prefix_len,
+                    },
+                );
+            }
+        }
+        Graph { tasks }
+    }
+
+    /// The task whose id is exactly `id`, with the name of its project
+    pub fn get(&self, id: &str) -> Option<(&'a str, &'a Task)> {
+        self.tasks.get(id).map(|n| (n.project, n.task))
+    }
+
+    /// The full id of the task whose id starts with `prefix`, in any project
+    ///
+    /// Fails if no task matches or if the prefix is ambiguous
+    pub fn resolve(&self, prefix: &str) -> Result<String, RustaskError> {
+        let mut matches = self.tasks.values().filter(|n| n.task.matches_id(prefix));
+        match (matches.next(), matches.next()) {
+            (Some(node), None) => Ok(node.task.id.clone()),
+            (Some(_), Some(_)) => Err(RustaskError::AmbiguousTaskId(prefix.to_string())),
+            (None, _) => Err(RustaskError::TaskNotFound(prefix.to_string())),
+        }
+    }
+
+    /// The tasks waiting on the task whose id is exactly `id`, with the names of their
+    /// projects
+    pub fn waiting_on(&self, id: &str) -> Vec<(&'a str, &'a Task)> {
+        self.tasks
+            .values()
+            .filter(|n| n.task.depends_on.contains(id))
+            .map(|n| (n.project, n.task))
+            .collect()
+    }
+
+    /// The tasks `task` depends on which are not done yet
+    ///
+    /// Tasks which no longer exist are not waited on.
+    pub fn blockers(&self, task: &Task) -> Vec<&'a Task> {
+        task.depends_on
+            .iter()
+            .filter_map(|id| self.tasks.get(id.as_str()))
+            .map(|n| n.task)
+            .filter(|t| !t.is_done())
+            .collect()
+    }
+
+    /// A chain of dependencies leading from `task` back to it, as the ids along it
+    ///
+    /// `task` is followed as given rather than as it is in the graph, so that an edit can
+    /// be checked before it is made.
+    pub fn cycle(&self, task: &Task) -> Option<Vec<String>> {
+        let mut visited: BTreeSet<&str> = BTreeSet::new();
+        // depth first, keeping the path to the task whose dependencies are being followed
+        let mut path: Vec<&str> = vec![&task.id];
+        let mut stack: Vec<btree_set::Iter<String>> = vec![task.depends_on.iter()];
+        while let Some(dependencies) = stack.last_mut() {
+            match dependencies.next() {
+                None => {
+                    stack.pop();
+                    path.pop();
+                }
+                Some(id) if *id == task.id => {
+                    path.push(id);
+                    return Some(path.into_iter().map(str::to_string).collect());
+                }
+                Some(id) => {
+                    if let Some(node) = self.tasks.get(id.as_str()) {
+                        if visited.insert(id) {
+                            path.push(id);
+                            stack.push(node.task.depends_on.iter());
+                        }
+                    }
+                }
+            }
+        }
+        None
+    }
+
+    /// The tasks in `project` (or in any project), leaving completed ones out unless
+    /// `include_completed` is set
+    fn scoped(&self, project: Option<&str>, include_completed: bool) -> Vec<&Node<'a>> {
+        self.tasks
+            .values()
+            .filter(|n| project.is_none_or(|p| n.project == p))
+            .filter(|n| include_completed || !n.task.is_done())
+            .collect()
+    }
+
+    /// Write the tasks which wait on others and no listed task waits on, each followed
+    /// by the tasks it waits on, indented
+    pub fn write_tree<W: Write>(
+        &self,
+        out: &mut W,
+        project: Option<&str>,
+        include_completed: bool,
+    ) -> io::Result<()> {
+        let scoped = self.scoped(project, include_completed);
+        let waited_on: BTreeSet<&str> = scoped
+            .iter()
+            .flat_map(|n| n.task.depends_on.iter().map(String::as_str))
+            .collect();
+        let mut roots: Vec<&Node> = scoped
+            .into_iter()
+            .filter(|n| !n.task.depends_on.is_empty() && !waited_on.contains(n.task.id.as_str()))
+            .collect();
+        roots.sort_by_key(|n| (n.project, n.task));
+
+        for root in roots {
+            self.write_node(out, root, &mut vec![])?;
+        }
+        Ok(())
+    }
+
+    /// Write `node` and, below it, the tasks it waits on
+    ///
+    /// `path` holds the ids of the tasks waiting on it, to stop at cycles.
+    fn write_node<W: Write>(
+        &self,
+        out: &mut W,
+        node: &Node<'a>,
+        path: &mut Vec<&'a str>,
+    ) -> io::Result<()> {
+        let indent = "  ".repeat(path.len());
+        write!(
+            out,
+            "{}[{}] {}: {}",
+            indent,
+            node.task.id_prefix(node.id_prefix_len),
+            node.project,
+            node.task
+        )?;
+        if path.contains(&node.task.id.as_str()) {
+            return writeln!(out, " (cycle)");
+        }
+        writeln!(out)?;
+
+        path.push(&node.task.id);
+        for id in &node.task.depends_on {
+            match self.tasks.get(id.as_str()) {
+                Some(dependency) => self.write_node(out, dependency, path)?,
+                None => writeln!(out, "{}  [{}] (removed)", indent, id)?,
+            }
+        }
+        path.pop();
+        Ok(())
+    }
+
+    /// Write the tasks which wait on others or are waited on as a Graphviz digraph, with
+    /// an edge from each task to those waiting on it
+    ///
+    /// Completed tasks are dashed, and blocked ones grayed out.
+    pub fn write_dot<W: Write>(
+        &self,
+        out: &mut W,
+        project: Option<&str>,
+        include_completed: bool,
+    ) -> io::Result<()> {
+        let scoped = self.scoped(project, include_completed);
+        let mut nodes: BTreeMap<&str, &Node> = BTreeMap::new();
+        for node in scoped.iter().filter(|n| !n.task.depends_on.is_empty()) {
+            nodes.insert(&node.task.id, node);
+            for id in &node.task.depends_on {
+                if let Some(dependency) = self.tasks.get(id.as_str()) {
+                    nodes.insert(id, dependency);
+                }
+            }
+        }
+
+        writeln!(out, "digraph rustask {{")?;
+        writeln!(out, "    rankdir=LR;")?;
+        for (id, node) in &nodes {
+            let label = format!("{}: {}", node.project, node.task.description)
+                .replace('\\', "\\\\")
+                .replace('"', "\\\"");
+            let style = if node.task.is_done() {
+                ", style=dashed"
+            } else if !self.blockers(node.task).is_empty() {
+                ", style=filled, fillcolor=lightgray"
+            } else {
+                ""
+            };
+            writeln!(out, "    \"{}\" [label=\"{}\"{}];", id, label, style)?;
+        }
+        for (id, node) in &nodes {
+            for dependency in node.task.depends_on.iter() {
+                if nodes.contains_key(dependency.as_str()) {
+                    writeln!(out, "    \"{}\" -> \"{}\";", dependency, id)?;
+                }
+            }
+        }
+        writeln!(out, "}}")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::commands::task::{now_deadline, TaskBuilder};
+
+    /// `c` in project b waits on `b`, which waits on `a`, both in project a
+    fn projects() -> (Vec<Project>, [Task; 3]) {
+        let mut a = TaskBuilder::new("a".to_string()).build();
+        a.completed = Some(now_deadline());
+        let b = TaskBuilder::new("b".to_string())
+            .depends_on(a.id.clone())
+            .build();
+        let c = TaskBuilder::new("c \"quoted\"".to_string())
+            .depends_on(b.id.clone())
+            .build();
+
+        let mut first = Project::new("a".to_string());
+        first.push(a.clone());
+        first.push(b.clone());
+        let mut second = Project::new("b".to_string());
+        second.push(c.clone());
+        (vec![first, second], [a, b, c])
+    }
+
+    #[test]
+    fn blockers_and_cycles() {
+        let (projects, [a, b, c]) = projects();
+        let graph = Graph::new(&projects);
+        assert_eq!(graph.get(&c.id), Some(("b", &c)));
+        assert_eq!(graph.resolve(&a.id[..6]), Ok(a.id.clone()));
+        assert_eq!(
+            graph.resolve("zz"),
+            Err(RustaskError::TaskNotFound("zz".to_string()))
+        );
+
+        assert_eq!(graph.waiting_on(&b.id), vec![("b", &c)]);
+        assert_eq!(graph.blockers(&b), Vec::<&Task>::new());
+        assert_eq!(graph.blockers(&c), vec![&b]);
+        assert_eq!(graph.cycle(&c), None);
+
+        let mut looping = a.clone();
+        looping.depends_on.insert(c.id.clone());
+        assert_eq!(
+            graph.cycle(&looping),
+            Some(vec![a.id.clone(), c.id.clone(), b.id.clone(), a.id.clone()])
+        );
+    }
+
+    #[test]
+    fn tree() {
+        let (projects, [a, b, c]) = projects();
+        let graph = Graph::new(&projects);
+        let render = |project: Option<&str>, include_completed: bool| {
+            let mut out = vec![];
+            graph
+                .write_tree(&mut out, project, include_completed)
+                .unwrap();
+            String::from_utf8(out).unwrap()
+        };
+
+        let lines: Vec<String> = render(None, false).lines().map(String::from).collect();
+        assert_eq!(lines.len(), 3);
+        assert!(lines[0].starts_with(&format!("[{}] b: ", &c.id[..4])));
+        assert!(lines[1].starts_with(&format!("  [{}] a: ", &b.id[..4])));
+        assert!(lines[2].starts_with(&format!("    [{}] a: ", &a.id[..4])));
+        // without project b, nothing waits on b
+        assert!(render(Some("a"), false).starts_with(&format!("[{}] a: ", &b.id[..4])));
+    }
+
+    #[test]
+    fn dot() {
+        let (projects, [a, b, c]) = projects();
+        let graph = Graph::new(&projects);
+        let mut out = vec![];
+        graph.write_dot(&mut out, None, false).unwrap();
+        let dot = String::from_utf8(out).unwrap();
+
+        assert!(dot.starts_with("digraph rustask {\n"));
+        assert!(dot.contains(&format!("\"{}\" [label=\"a: a\", style=dashed];", a.id)));
+        assert!(dot.contains(&format!(
+            "\"{}\" [label=\"b: c \\\"quoted\\\"\", style=filled, fillcolor=lightgray];",
+            c.id
+        )));
+        assert!(dot.contains(&format!("\"{}\" -> \"{}\";", a.id, b.id)));
+        assert!(dot.contains(&format!("\"{}\" -> \"{}\";", b.id, c.id)));
+        assert!(dot.ends_with("}\n"));
+    }
+}
@@ -2,15 +2,25 @@
 //
 // define an error type
 
+use std::fmt;
 use thiserror::Error;
 
 #[derive(Error, Debug)]
 pub enum RustaskError {
-    #[error("Failed to perform IO")]
+    #[error("Failed to perform IO: {}", .0)]
     IOError(#[from] std::io::Error),
 
+    #[error("{}", .0)]
+    FileError(FileError),
+
     #[error("Cannot find task {}", .0)]
-    OutOfBounds(usize),
+    TaskNotFound(String),
+
+    #[error("Task id {} is ambiguous, use a longer prefix", .0)]
+    AmbiguousTaskId(String),
+
+    #[error("Task {} is already done", .0)]
+    TaskAlreadyDone(String),
 
     #[error("Project {} not found", .0)]
     ProjectNotFound(String),
@@ -22,19 +32,87 @@ pub enum RustaskError {
     #[error("Task file `{}` not found", .0)]
     TaskFileNotFound(String),
 
-    #[error("Failed to serialize")]
+    #[error(
+        "Could not lock task file `{}` within {:?} (is another rustask running?)",
+        .0,
+        .1
+    )]
+    LockTimeout(String, std::time::Duration),
+
+    #[error(
+        "The task file changed since `{}` was recorded, it cannot be undone or redone",
+        .0
+    )]
+    JournalConflict(String),
+
+    #[error("Failed to read or write JSON: {}", .0)]
     SerializationError(#[from] serde_json::Error),
+
+    #[error("Failed to read or write the task file: {}", .0)]
+    FormatError(String),
+
+    #[error("Failed to access the task database: {}", .0)]
+    DatabaseError(#[from] rusqlite::Error),
+
+    #[error("Could not find a task file: pass -f, set RUSTASK_TASKFILE or run `rustask init`")]
+    NoTaskFile,
+
+    #[error("Context {} is not configured, add it under [contexts] in the configuration", .0)]
+    ContextNotFound(String),
+
+    #[error("Task file `{}` already exists", .0)]
+    TaskFileExists(String),
+
+    #[error("Migrating to `{}` would lose data", .0)]
+    LossyMigration(String),
+
+    #[error("Project {} changed while being written (is another program editing it?)", .0)]
+    ChangeConflict(String),
+
+    #[error(
+        "Task file is in version {} of the format, this rustask reads up to version {}",
+        .0,
+        .1
+    )]
+    UnsupportedVersion(u32, u32),
+
+    #[error("Task {} has subtasks, remove them first", .0)]
+    TaskHasSubtasks(String),
+
+    #[error("Task {} waits on unfinished tasks: {}", .0, .1)]
+    TaskBlocked(String, String),
+
+    #[error("Dependencies would form a cycle: {}", .0)]
+    DependencyCycle(String),
+
+    #[error("A timer is already running on task {}", .0)]
+    TimerRunning(String),
+
+    #[error("No timer is running, start one with `rustask start`")]
+    NoTimerRunning,
+
+    #[error("Editor `{}` failed: {} (set $VISUAL or $EDITOR)", .0, .1)]
+    EditorFailed(String, String),
 }
 
 impl std::cmp::PartialEq for RustaskError {
     fn eq(&self, other: &RustaskError) -> bool {
         match self {
-            RustaskError::IOError(_a) => match other {
-                RustaskError::IOError(_b) => true,
+            RustaskError::IOError(_a) => matches!(other, RustaskError::IOError(_b)),
+            RustaskError::FileError(a) => match other {
+                RustaskError::FileError(b) => a == b,
+                _ => false,
+            },
+            RustaskError::TaskNotFound(a) => match other {
+                RustaskError::TaskNotFound(b) => a == b,
+                _ => false,
+            },
+            RustaskError::AmbiguousTaskId(a) => match other {
+                RustaskError::AmbiguousTaskId(b) => a == b,
                 _ => false,
             },
-            RustaskError::OutOfBounds(a) => match other {
-                RustaskError::OutOfBounds(b) => a == b,
+            RustaskError::TaskAlreadyDone(a) => match other {
+                RustaskError::TaskAlreadyDone(b) => a == b,
                 _ => false,
             },
             RustaskError::TaskFileNotFound(a) => match other {
@@ -49,10 +127,129 @@ impl std::cmp::PartialEq for RustaskError {
                 RustaskError::ProjectNotFound(b) => a == b,
                 _ => false,
             },
-            RustaskError::SerializationError(_a) => match other {
-                RustaskError::SerializationError(_b) => true,
+            RustaskError::LockTimeout(a, t) => match other {
+                RustaskError::LockTimeout(b, u) => a == b && t == u,
+                _ => false,
+            },
+            RustaskError::JournalConflict(a) => match other {
+                RustaskError::JournalConflict(b) => a == b,
+                _ => false,
+            },
+            RustaskError::SerializationError(_a) => {
+                matches!(other, RustaskError::SerializationError(_b))
+            }
+            RustaskError::FormatError(a) => match other {
+                RustaskError::FormatError(b) => a == b,
+                _ => false,
+            },
+            RustaskError::DatabaseError(_a) => matches!(other, RustaskError::DatabaseError(_b)),
+            RustaskError::NoTaskFile => matches!(other, RustaskError::NoTaskFile),
+            RustaskError::ContextNotFound(a) => match other {
+                RustaskError::ContextNotFound(b) => a == b,
+                _ => false,
+            },
+            RustaskError::TaskFileExists(a) => match other {
+                RustaskError::TaskFileExists(b) => a == b,
+                _ => false,
+            },
+            RustaskError::LossyMigration(a) => match other {
+                RustaskError::LossyMigration(b) => a == b,
+                _ => false,
+            },
+            RustaskError::ChangeConflict(a) => match other {
+                RustaskError::ChangeConflict(b) => a == b,
+                _ => false,
+            },
+            RustaskError::UnsupportedVersion(a, c) => match other {
+                RustaskError::UnsupportedVersion(b, d) => a == b && c == d,
+                _ => false,
+            },
+            RustaskError::TaskHasSubtasks(a) => match other {
+                RustaskError::TaskHasSubtasks(b) => a == b,
+                _ => false,
+            },
+            RustaskError::TaskBlocked(a, c) => match other {
+                RustaskError::TaskBlocked(b, d) => a == b && c == d,
+                _ => false,
+            },
+            RustaskError::DependencyCycle(a) => match other {
+                RustaskError::DependencyCycle(b) => a == b,
+                _ => false,
+            },
+            RustaskError::TimerRunning(a) => match other {
+                RustaskError::TimerRunning(b) => a == b,
+                _ => false,
+            },
+            RustaskError::NoTimerRunning => matches!(other, RustaskError::NoTimerRunning),
+            RustaskError::EditorFailed(a, c) => match other {
+                RustaskError::EditorFailed(b, d) => a == b && c == d,
                 _ => false,
             },
         }
     }
 }
+
+/// A failure to read or write a file, with what is known of where it happened and why
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FileError {
+    pub path: String,
+    /// What was attempted, e.g. "read the task file"
+    pub operation: String,
+    /// Line and column (both from 1) of a syntax or format error
+    pub position: Option<(usize, usize)>,
+    pub message: String,
+    /// How the problem may be fixed
+    pub suggestion: Option<String>,
+}
+
+impl FileError {
+    /// The line and column (both from 1) of the byte at `offset` in `contents`
+    pub fn position_of(contents: &[u8], offset: usize) -> (usize, usize) {
+        let before = &contents[..offset.min(contents.len())];
+        let line = before.iter().filter(|b| **b == b'\n').count() + 1;
+        let line_start = before
+            .iter()
+            .rposition(|b| *b == b'\n')
+            .map_or(0, |p| p + 1);
+        let column = String::from_utf8_lossy(&before[line_start..])
+            .chars()
+            .count()
+            + 1;
+        (line, column)
+    }
+}
+
+impl fmt::Display for FileError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Failed to {} `{}`", self.operation, self.path)?;
+        if let Some((line, column)) = self.position {
+            write!(f, " at line {}, column {}", line, column)?;
+        }
+        write!(f, ": {}", self.message)?;
+        if let Some(suggestion) = &self.suggestion {
+            write!(f, "\nhint: {}", suggestion)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn file_error_display() {
+        let error = FileError {
+            path: "tasks.json".to_string(),
+            operation: "read the task file".to_string(),
+            position: Some(FileError::position_of(b"[\n  {x", 5)),
+            message: "key must be a string".to_string(),
+            suggestion: Some("run `rustask repair`".to_string()),
+        };
+        assert_eq!(
+            RustaskError::FileError(error).to_string(),
+            "Failed to read the task file `tasks.json` at line 2, column 4: \
+             key must be a string\nhint: run `rustask repair`"
+        );
+    }
+}
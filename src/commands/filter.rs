@@ -0,0 +1,160 @@
+// filter.rs
+//
+// pick which tasks are listed
+
+use crate::commands::query::Expr;
+use crate::commands::task::{Deadline, Priority, Task};
+
+/// Which tasks to list
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct Filter {
+    /// Also list completed tasks
+    pub include_completed: bool,
+    /// Only list tasks with every one of these tags
+    pub tags: Vec<String>,
+    /// Only list tasks with none of these tags
+    pub without_tags: Vec<String>,
+    /// Only list tasks satisfying this expression
+    pub query: Option<Expr>,
+    /// Leave out tasks waiting on unfinished ones, which only a task store can tell
+    pub hide_blocked: bool,
+}
+
+impl Filter {
+    /// Whether `task`, in `project`, is to be listed
+    pub fn matches(&self, project: &str, task: &Task) -> bool {
+        (self.include_completed || !task.is_done())
+            && self.tags.iter().all(|t| task.has_tag(t))
+            && !self.without_tags.iter().any(|t| task.has_tag(t))
+            && self.query.as_ref().is_none_or(|q| q.matches(project, task))
+    }
+
+    /// The tasks to load to list the matching ones, in `project` or in all of them
+    pub fn scope(&self, project: Option<&str>) -> Scope {
+        let mut scope = Scope {
+            project: project.map(str::to_string),
+            include_completed: self.include_completed,
+            ..Scope::default()
+        };
+        if let Some(query) = &self.query {
+            query.narrow(&mut scope);
+        }
+        scope
+    }
+}
+
+/// The tasks a listing may need, as bounds a backend can look up quickly
+///
+/// Backends may load more tasks than the scope holds, but never fewer: the filter is
+/// still applied to whatever they load.
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct Scope {
+    /// Only tasks in this project
+    pub project: Option<String>,
+    /// Also completed tasks
+    pub include_completed: bool,
+    /// Only tasks at least this urgent (tasks without a priority are normal)
+    pub priority: Option<Priority>,
+    /// Only tasks due at or after this time
+    pub due_after: Option<Deadline>,
+    /// Only tasks due at or before this time
+    pub due_before: Option<Deadline>,
+}
+
+impl Scope {
+    /// Whether `task`, in a project within the scope, is within the scope
+    pub fn matches(&self, task: &Task) -> bool {
+        (self.include_completed || !task.is_done())
+            && self
+                .priority
+                .as_ref()
+                .is_none_or(|p| task.effective_priority() <= p)
+            && self
+                .due_after
+                .is_none_or(|after| task.deadline.is_some_and(|d| d >= after))
+            && self
+                .due_before
+                .is_none_or(|before| task.deadline.is_some_and(|d| d <= before))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::commands::query::parse_query;
+    use crate::commands::task::{now_deadline, TaskBuilder};
+
+    #[test]
+    fn default_hides_completed() {
+        let mut task = TaskBuilder::new("task".to_string()).build();
+        assert!(Filter::default().matches("proj", &task));
+        task.completed = Some(now_deadline());
+        assert!(!Filter::default().matches("proj", &task));
+        assert!(Filter {
+            include_completed: true,
+            ..Filter::default()
+        }
+        .matches("proj", &task));
+    }
+
+    #[test]
+    fn tags() {
+        let task = TaskBuilder::new("task".to_string())
+            .tag("work".to_string())
+            .tag("@review".to_string())
+            .build();
+        let filter = |tags: &[&str], without_tags: &[&str]| Filter {
+            tags: tags.iter().map(|t| t.to_string()).collect(),
+            without_tags: without_tags.iter().map(|t| t.to_string()).collect(),
+            ..Filter::default()
+        };
+
+        assert!(filter(&["work"], &[]).matches("proj", &task));
+        assert!(filter(&["work", "@review"], &["@blocked"]).matches("proj", &task));
+        assert!(!filter(&["work", "home"], &[]).matches("proj", &task));
+        assert!(!filter(&[], &["@review"]).matches("proj", &task));
+    }
+
+    #[test]
+    fn query() {
+        let task = TaskBuilder::new("task".to_string())
+            .tag("work".to_string())
+            .build();
+        let filter = |q: &str| Filter {
+            query: Some(parse_query(q).unwrap()),
+            ..Filter::default()
+        };
+
+        assert!(filter("project=proj and tag:work").matches("proj", &task));
+        assert!(!filter("project=other").matches("proj", &task));
+    }
+
+    #[test]
+    fn scope() {
+        let filter = |q: &str| Filter {
+            query: Some(parse_query(q).unwrap()),
+            ..Filter::default()
+        };
+        let deadline = now_deadline();
+        let urgent = TaskBuilder::new("urgent".to_string())
+            .priority(Priority::Urgent)
+            .deadline(deadline)
+            .build();
+        let plain = TaskBuilder::new("plain".to_string()).build();
+
+        let scope = filter("priority>=high and (tag:work or due<1d) and project=a").scope(None);
+        assert_eq!(scope.project.as_deref(), Some("a"));
+        assert_eq!(scope.priority, Some(Priority::High));
+        assert_eq!(scope.due_before, None);
+        assert!(scope.matches(&urgent));
+        assert!(!scope.matches(&plain));
+
+        let scope = filter("due>=0d and not priority=low").scope(Some("b"));
+        assert_eq!(scope.project.as_deref(), Some("b"));
+        assert_eq!(scope.priority, None);
+        assert!(scope.due_after.is_some());
+        assert!(!scope.matches(&plain));
+
+        assert_eq!(Filter::default().scope(None), Scope::default());
+    }
+}
@@ -0,0 +1,163 @@
+// journal.rs
+//
+// record mutations to undo and redo them
+
+use serde::{Deserialize, Serialize};
+
+use crate::commands::change::{self, Change};
+use crate::commands::task::Deadline;
+
+/// Number of operations which can be undone
+pub const JOURNAL_LEN: usize = 20;
+
+/// A committed mutation, as the changes it made to the task file
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(deny_unknown_fields)]
+pub struct Entry {
+    pub time: Deadline,
+    pub operation: String,
+    changes: Vec<Change>,
+}
+
+impl Entry {
+    /// The changes which redo the operation
+    pub fn changes(&self) -> &[Change] {
+        &self.changes
+    }
+
+    /// The changes which undo the operation
+    pub fn inverse(&self) -> Vec<Change> {
+        change::inverse(&self.changes)
+    }
+}
+
+/// Operations which can be undone, and those undone which can be redone
+#[derive(Debug, Default, Serialize, Deserialize, PartialEq, Eq)]
+pub struct Journal {
+    /// oldest first
+    done: Vec<Entry>,
+    /// most recently undone last
+    undone: Vec<Entry>,
+}
+
+impl Journal {
+    /// Record a new operation; this forgets the operations which could be redone
+    pub fn record(&mut self, time: Deadline, operation: String, changes: Vec<Change>) {
+        self.done.push(Entry {
+            time,
+            operation,
+            changes,
+        });
+        if self.done.len() > JOURNAL_LEN {
+            self.done.drain(..self.done.len() - JOURNAL_LEN);
+        }
+        self.undone.clear();
+    }
+
+    /// The operation [`Journal::undo`] would undo
+    pub fn last_done(&self) -> Option<&Entry> {
+        self.done.last()
+    }
+
+    /// The operation [`Journal::redo`] would redo
+    pub fn last_undone(&self) -> Option<&Entry> {
+        self.undone.last()
+    }
+
+    /// Mark the last operation as undone, returning it
+    pub fn undo(&mut self) -> Option<&Entry> {
+        let entry = self.done.pop()?;
+        self.undone.push(entry);
+        self.undone.last()
+    }
+
+    /// Mark the last undone operation as done again, returning it
+    pub fn redo(&mut self) -> Option<&Entry> {
+        let entry = self.undone.pop()?;
+        self.done.push(entry);
+        self.done.last()
+    }
+
+    /// The operations which can be undone, most recent first
+    pub fn entries(&self) -> impl Iterator<Item = &Entry> {
+        self.done.iter().rev()
+    }
+}
+
+/// The journal of earlier versions, which kept whole copies of the task file
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+#[allow(dead_code)]
+struct LegacyJournal {
+    done: Vec<LegacyEntry>,
+    undone: Vec<LegacyEntry>,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+#[allow(dead_code)]
+struct LegacyEntry {
+    time: Deadline,
+    operation: String,
+    before: Vec<serde_json::Value>,
+    after: Vec<serde_json::Value>,
+}
+
+/// Whether `contents` is a journal written by an earlier version, which cannot be replayed
+pub fn is_legacy(contents: &[u8]) -> bool {
+    serde_json::from_slice::<LegacyJournal>(contents).is_ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::commands::task::{now_deadline, TaskBuilder};
+
+    fn changes(description: &str) -> Vec<Change> {
+        vec![Change::Task {
+            project: "proj".to_string(),
+            before: None,
+            after: Some(TaskBuilder::new(description.to_string()).build()),
+        }]
+    }
+
+    #[test]
+    fn undo_redo() {
+        let add = changes("first");
+        let mut journal = Journal::default();
+        journal.record(now_deadline(), "add".to_string(), add.clone());
+
+        assert_eq!(journal.last_done().unwrap().changes(), &add);
+        assert_eq!(journal.undo().unwrap().inverse(), change::inverse(&add));
+        assert_eq!(journal.undo(), None);
+        assert_eq!(journal.last_undone().unwrap().operation, "add");
+        assert_eq!(journal.redo().unwrap().changes(), &add);
+        assert_eq!(journal.redo(), None);
+        assert_eq!(journal.entries().count(), 1);
+    }
+
+    #[test]
+    fn record_forgets_redo() {
+        let mut journal = Journal::default();
+        journal.record(now_deadline(), "edit".to_string(), changes("first"));
+        journal.undo().unwrap();
+        journal.record(now_deadline(), "add".to_string(), changes("second"));
+
+        assert_eq!(journal.redo(), None);
+        assert_eq!(journal.entries().next().unwrap().operation, "add");
+    }
+
+    #[test]
+    fn bounded() {
+        let mut journal = Journal::default();
+        for n in 0..JOURNAL_LEN + 5 {
+            journal.record(now_deadline(), format!("op{}", n), vec![]);
+        }
+
+        assert_eq!(journal.entries().count(), JOURNAL_LEN);
+        assert_eq!(
+            journal.entries().next().unwrap().operation,
+            format!("op{}", JOURNAL_LEN + 4)
+        );
+    }
+}
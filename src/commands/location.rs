@@ -0,0 +1,172 @@
+// location.rs
+//
+// find the task file to use
+
+use std::env;
+use std::fmt;
+use std::path::{Path, PathBuf};
+
+use crate::commands::error::RustaskError;
+
+/// The name of a task file kept with a repository (or any directory tree)
+pub const LOCAL_FILE: &str = ".rustask.json";
+
+/// The name of the default task file, in the data directory
+pub const DEFAULT_FILE: &str = "tasks.json";
+
+/// Why a task file is the one in use
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Source {
+    /// Passed with `-f`
+    Flag,
+    /// The task file of the context selected with `--context`
+    Context(String),
+    /// Set in `RUSTASK_TASKFILE`
+    Environment,
+    /// A `.rustask.json` in the given directory, the current one or one of its parents
+    Local(PathBuf),
+    /// The default task file, in the data directory
+    Default,
+}
+
+impl fmt::Display for Source {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Source::Flag => write!(f, "passed with -f"),
+            Source::Context(name) => write!(f, "the task file of context {}", name),
+            Source::Environment => write!(f, "set by RUSTASK_TASKFILE"),
+            Source::Local(dir) => write!(
+                f,
+                "found in {}, walking up from the current directory",
+                dir.display()
+            ),
+            Source::Default => write!(
+                f,
+                "the default task file, as no {} was found from the current directory",
+                LOCAL_FILE
+            ),
+        }
+    }
+}
+
+/// A task file and why it is the one in use
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Location {
+    pub path: PathBuf,
+    pub source: Source,
+}
+
+/// The closest `.rustask.json` in `dir` or one of its parents
+pub fn find_local(dir: &Path) -> Option<PathBuf> {
+    dir.ancestors()
+        .map(|d| d.join(LOCAL_FILE))
+        .find(|path| path.is_file())
+}
+
+/// The directory rustask keeps its data in: `$XDG_DATA_HOME/rustask`, or
+/// `~/.local/share/rustask` if that is not set
+pub fn data_dir() -> Option<PathBuf> {
+    let data_home = env::var_os("XDG_DATA_HOME")
+        .map(PathBuf::from)
+        .filter(|path| path.is_absolute())
+        .or_else(|| env::var_os("HOME").map(|home| Path::new(&home).join(".local/share")))?;
+    Some(data_home.join("rustask"))
+}
+
+/// Pick the task file to use
+///
+/// In order: the file passed with `-f` (`flag`), the one of the context selected with
+/// `--context` (its name and file), the one set in `RUSTASK_TASKFILE` (`variable`), the
+/// closest `.rustask.json` walking up from `dir`, and the default one in `data_dir`.
+pub fn locate(
+    flag: Option<&str>,
+    context: Option<(String, PathBuf)>,
+    variable: Option<String>,
+    dir: &Path,
+    data_dir: Option<PathBuf>,
+) -> Result<Location, RustaskError> {
+    if let Some(path) = flag {
+        return Ok(Location {
+            path: PathBuf::from(path),
+            source: Source::Flag,
+        });
+    }
+    if let Some((name, path)) = context {
+        return Ok(Location {
+            path,
+            source: Source::Context(name),
+        });
+    }
+    if let Some(path) = variable.filter(|v| !v.is_empty()) {
+        return Ok(Location {
+            path: PathBuf::from(path),
+            source: Source::Environment,
+        });
+    }
+    if let Some(path) = find_local(dir) {
+        let dir = path.parent().unwrap_or(dir).to_path_buf();
+        return Ok(Location {
+            path,
+            source: Source::Local(dir),
+        });
+    }
+    match data_dir {
+        Some(data_dir) => Ok(Location {
+            path: data_dir.join(DEFAULT_FILE),
+            source: Source::Default,
+        }),
+        None => Err(RustaskError::NoTaskFile),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+
+    #[test]
+    fn precedence() {
+        let root = env::current_dir().unwrap().join("test_file_location");
+        let nested = root.join("a/b");
+        fs::create_dir_all(&nested).unwrap();
+        let data_dir = Some(root.join("data"));
+
+        let default = locate(None, None, None, &nested, data_dir.clone()).unwrap();
+        assert_eq!(default.path, root.join("data").join(DEFAULT_FILE));
+        assert_eq!(default.source, Source::Default);
+        assert_eq!(
+            locate(None, None, None, &nested, None),
+            Err(RustaskError::NoTaskFile)
+        );
+
+        fs::write(root.join(LOCAL_FILE), "[]").unwrap();
+        let local = locate(None, None, None, &nested, data_dir.clone()).unwrap();
+        assert_eq!(local.path, root.join(LOCAL_FILE));
+        assert_eq!(local.source, Source::Local(root.clone()));
+
+        let variable = locate(None, None, Some("env.json".to_string()), &nested, None).unwrap();
+        assert_eq!(variable.path, PathBuf::from("env.json"));
+        assert_eq!(variable.source, Source::Environment);
+
+        let team = Some(("team".to_string(), PathBuf::from("team.json")));
+        let context = locate(
+            None,
+            team.clone(),
+            Some("env.json".to_string()),
+            &nested,
+            None,
+        );
+        assert_eq!(context.unwrap().source, Source::Context("team".to_string()));
+
+        let flag = locate(
+            Some("flag.json"),
+            team,
+            Some("env.json".to_string()),
+            &nested,
+            None,
+        );
+        assert_eq!(flag.unwrap().source, Source::Flag);
+
+        fs::remove_dir_all(root).unwrap();
+    }
+}
@@ -0,0 +1,735 @@
+// output.rs
+//
+// render listed tasks for people and for scripts
+
+use chrono::SecondsFormat;
+use serde::Serialize;
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt;
+use std::io::{self, Write};
+use std::str::FromStr;
+
+use crate::commands::config;
+use crate::commands::dependency::Graph;
+use crate::commands::project::{Progress, Summary};
+use crate::commands::task::{now_deadline, Deadline, Priority, Task};
+use crate::commands::timesheet::Effort;
+
+/// A listed project, with the tasks picked from it
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Listing {
+    /// The context the project is in, when listing across contexts
+    pub context: Option<String>,
+    pub project: Summary,
+    /// The picked tasks, each with the annotation shown after it in text output
+    pub tasks: Vec<(Task, String)>,
+    /// The progress of the tasks of the project with subtasks, listed or not, by id
+    pub progress: BTreeMap<String, Progress>,
+    /// The ids of the listed tasks waiting on unfinished ones
+    pub blocked: BTreeSet<String>,
+}
+
+impl Listing {
+    /// Order the tasks as an outline, each listed subtask right after its parent and
+    /// the subtasks before it
+    ///
+    /// Tasks whose parent is not listed are kept in the order they were in.
+    pub fn outline(mut self) -> Listing {
+        let listed: BTreeSet<String> = self.tasks.iter().map(|(t, _)| t.id.clone()).collect();
+        let is_root = |t: &Task| t.parent.as_ref().is_none_or(|p| !listed.contains(p));
+
+        let mut remaining: Vec<Option<(Task, String)>> = std::mem::take(&mut self.tasks)
+            .into_iter()
+            .map(Some)
+            .collect();
+        // positions of the tasks still to be placed, last first
+        let mut stack: Vec<usize> = (0..remaining.len())
+            .rev()
+            .filter(|i| remaining[*i].as_ref().is_some_and(|(t, _)| is_root(t)))
+            .collect();
+        while let Some(i) = stack.pop() {
+            let Some((task, note)) = remaining[i].take() else {
+                continue;
+            };
+            stack.extend((0..remaining.len()).rev().filter(|j| {
+                remaining[*j]
+                    .as_ref()
+                    .is_some_and(|(t, _)| t.parent.as_ref() == Some(&task.id))
+            }));
+            self.tasks.push((task, note));
+        }
+        // tasks nested in a cycle have no root to be listed under
+        self.tasks.extend(remaining.into_iter().flatten());
+        self
+    }
+
+    /// How deep each task is nested under the other listed tasks
+    pub fn depths(&self) -> Vec<usize> {
+        let parents: BTreeMap<&str, Option<&str>> = self
+            .tasks
+            .iter()
+            .map(|(t, _)| (t.id.as_str(), t.parent.as_deref()))
+            .collect();
+        self.tasks
+            .iter()
+            .map(|(t, _)| {
+                let mut depth = 0;
+                let mut parent = t.parent.as_deref();
+                while let Some(p) = parent.filter(|p| parents.contains_key(p)) {
+                    depth += 1;
+                    // tasks nested in a cycle
+                    if depth == parents.len() {
+                        break;
+                    }
+                    parent = parents[p];
+                }
+                depth
+            })
+            .collect()
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Format {
+    /// Colored, for people
+    Text,
+    /// A single array of records
+    Json,
+    /// A record per line
+    Ndjson,
+    /// A header line and a record per line, quoted as in RFC 4180
+    Csv,
+    /// A header line and a record per line; tabs, newlines and backslashes in fields are
+    /// escaped as `\t`, `\n` and `\\`
+    Tsv,
+}
+
+#[derive(thiserror::Error, Debug, PartialEq, Eq)]
+#[error("unknown format `{}`: expected text, json, ndjson, csv or tsv", .0)]
+pub struct FormatParseError(String);
+
+impl FromStr for Format {
+    type Err = FormatParseError;
+    fn from_str(s: &str) -> Result<Self, FormatParseError> {
+        match s.to_lowercase().as_str() {
+            "text" => Ok(Format::Text),
+            "json" => Ok(Format::Json),
+            "ndjson" | "jsonl" => Ok(Format::Ndjson),
+            "csv" => Ok(Format::Csv),
+            "tsv" => Ok(Format::Tsv),
+            _ => Err(FormatParseError(s.to_string())),
+        }
+    }
+}
+
+/// The state of a task, as computed when listing
+#[derive(Debug, Clone, Copy, Serialize, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum Status {
+    Pending,
+    /// Pending, with its deadline past
+    Overdue,
+    Done,
+}
+
+impl Status {
+    pub fn of(task: &Task, now: Deadline) -> Status {
+        match (task.completed, task.deadline) {
+            (Some(_), _) => Status::Done,
+            (None, Some(deadline)) if deadline < now => Status::Overdue,
+            (None, _) => Status::Pending,
+        }
+    }
+}
+
+impl fmt::Display for Status {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Status::Pending => write!(f, "pending"),
+            Status::Overdue => write!(f, "overdue"),
+            Status::Done => write!(f, "done"),
+        }
+    }
+}
+
+/// A task as written for scripts
+///
+/// The fields, in order, are:
+/// - `project`: the name of the project holding the task
+/// - `id`: the full id of the task
+/// - `description`
+/// - `priority`: `urgent`, `high`, `normal`, `low`, `note`, or null (empty) if unset
+/// - `deadline`: ISO-8601 (RFC 3339) with the local offset, or null (empty) if unset
+/// - `status`: `pending`, `overdue` or `done`
+/// - `completed`: ISO-8601 (RFC 3339) completion time, or null (empty) if pending
+/// - `tags`: an array of tags, or in CSV and TSV the tags separated by spaces
+/// - `context`: the context of the task file, or null (empty) if not listing across
+///   contexts
+/// - `parent`: the id of the task this one is a subtask of, or null (empty) if none
+/// - `depends_on`: an array of the ids of the tasks this one waits on, or in CSV and TSV
+///   the ids separated by spaces
+/// - `blocked`: whether the task waits on unfinished ones, `true` or `false`
+/// - `estimate`: how many minutes the task is expected to take, or null (empty) if unset
+/// - `spent`: how many minutes were spent on the task, a running timer included
+///
+/// New fields are only ever added at the end.
+#[derive(Debug, Clone, Serialize, PartialEq, Eq)]
+pub struct Record<'a> {
+    pub project: &'a str,
+    pub id: &'a str,
+    pub description: &'a str,
+    pub priority: Option<String>,
+    pub deadline: Option<String>,
+    pub status: Status,
+    pub completed: Option<String>,
+    pub tags: Vec<&'a str>,
+    pub context: Option<&'a str>,
+    pub parent: Option<&'a str>,
+    pub depends_on: Vec<&'a str>,
+    pub blocked: bool,
+    pub estimate: Option<u32>,
+    pub spent: u32,
+}
+
+const HEADER: [&str; 14] = [
+    "project",
+    "id",
+    "description",
+    "priority",
+    "deadline",
+    "status",
+    "completed",
+    "tags",
+    "context",
+    "parent",
+    "depends_on",
+    "blocked",
+    "estimate",
+    "spent",
+];
+
+fn iso8601(deadline: &Deadline) -> String {
+    deadline.to_rfc3339_opts(SecondsFormat::Secs, false)
+}
+
+impl<'a> Record<'a> {
+    pub fn new(project: &'a str, task: &'a Task, now: Deadline) -> Record<'a> {
+        Record {
+            project,
+            id: &task.id,
+            description: &task.description,
+            priority: task.priority.as_ref().map(Priority::to_string),
+            deadline: task.deadline.as_ref().map(iso8601),
+            status: Status::of(task, now),
+            completed: task.completed.as_ref().map(iso8601),
+            tags: task.tags.iter().map(String::as_str).collect(),
+            context: None,
+            parent: task.parent.as_deref(),
+            depends_on: task.depends_on.iter().map(String::as_str).collect(),
+            blocked: false,
+            estimate: task.estimate.as_ref().map(Effort::as_minutes),
+            spent: task.spent(now).as_minutes(),
+        }
+    }
+
+    /// The record, of a task waiting on unfinished ones or not
+    pub fn blocked(mut self, blocked: bool) -> Record<'a> {
+        self.blocked = blocked;
+        self
+    }
+
+    /// The record, in the given context
+    pub fn context(mut self, context: Option<&'a str>) -> Record<'a> {
+        self.context = context;
+        self
+    }
+
+    fn fields(&self) -> [String; 14] {
+        [
+            self.project.to_string(),
+            self.id.to_string(),
+            self.description.to_string(),
+            self.priority.clone().unwrap_or_default(),
+            self.deadline.clone().unwrap_or_default(),
+            self.status.to_string(),
+            self.completed.clone().unwrap_or_default(),
+            self.tags.join(" "),
+            self.context.unwrap_or_default().to_string(),
+            self.parent.unwrap_or_default().to_string(),
+            self.depends_on.join(" "),
+            self.blocked.to_string(),
+            self.estimate.map(|e| e.to_string()).unwrap_or_default(),
+            self.spent.to_string(),
+        ]
+    }
+}
+
+fn csv_field(field: &str) -> String {
+    if field.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", field.replace('"', "\"\""))
+    } else {
+        field.to_string()
+    }
+}
+
+fn tsv_field(field: &str) -> String {
+    field
+        .replace('\\', "\\\\")
+        .replace('\t', "\\t")
+        .replace('\n', "\\n")
+        .replace('\r', "\\r")
+}
+
+/// Write the listings in the given format
+pub fn write<W: Write>(out: &mut W, listings: &[Listing], format: Format) -> io::Result<()> {
+    let now = now_deadline();
+    let records = || {
+        listings.iter().flat_map(move |l| {
+            l.tasks.iter().map(move |(t, _)| {
+                Record::new(&l.project.name, t, now)
+                    .context(l.context.as_deref())
+                    .blocked(l.blocked.contains(&t.id))
+            })
+        })
+    };
+
+    match format {
+        Format::Text => write_text(out, listings),
+        Format::Json => {
+            serde_json::to_writer_pretty(&mut *out, &records().collect::<Vec<_>>())?;
+            writeln!(out)
+        }
+        Format::Ndjson => {
+            for record in records() {
+                serde_json::to_writer(&mut *out, &record)?;
+                writeln!(out)?;
+            }
+            Ok(())
+        }
+        Format::Csv | Format::Tsv => {
+            let (escape, separator): (fn(&str) -> String, &str) = match format {
+                Format::Csv => (csv_field, ","),
+                _ => (tsv_field, "\t"),
+            };
+            writeln!(out, "{}", HEADER.join(separator))?;
+            for record in records() {
+                let fields: Vec<String> = record.fields().iter().map(|f| escape(f)).collect();
+                writeln!(out, "{}", fields.join(separator))?;
+            }
+            Ok(())
+        }
+    }
+}
+
+fn write_text<W: Write>(out: &mut W, listings: &[Listing]) -> io::Result<()> {
+    for (i, listing) in listings.iter().enumerate() {
+        if i > 0 {
+            writeln!(out)?;
+        }
+        let prefix_len = listing.project.id_prefix_len;
+        if let Some(context) = &listing.context {
+            write!(out, "[{}] ", context)?;
+        }
+        writeln!(out, "{}", listing.project)?;
+        for ((t, note), depth) in listing.tasks.iter().zip(listing.depths()) {
+            write!(
+                out,
+                "{}[{}]: {}",
+                "  ".repeat(depth),
+                t.id_prefix(prefix_len),
+                t
+            )?;
+            if let Some(progress) = listing.progress.get(&t.id) {
+                write!(out, " [{}]", progress)?;
+            }
+            if listing.blocked.contains(&t.id) {
+                write!(out, " {}", config::get().colors.blocked.paint("(blocked)"))?;
+            }
+            writeln!(out, "{}", note)?;
+        }
+    }
+    Ok(())
+}
+
+/// Write listings of completed tasks, as text
+pub fn write_completed<W: Write>(out: &mut W, listings: &[Listing]) -> io::Result<()> {
+    for (i, listing) in listings.iter().enumerate() {
+        if i > 0 {
+            writeln!(out)?;
+        }
+        let prefix_len = listing.project.id_prefix_len;
+        let count = listing.tasks.len();
+        writeln!(
+            out,
+            "{}: {} completed {}",
+            listing.project.name,
+            count,
+            if count == 1 { "task" } else { "tasks" }
+        )?;
+        for (t, _) in &listing.tasks {
+            writeln!(out, "[{}]: {}", t.id_prefix(prefix_len), t)?;
+        }
+    }
+    Ok(())
+}
+
+/// Write a single task with every field it has, its annotations last, as text
+///
+/// The tasks it is a subtask of or waits on are looked up in `graph`.
+pub fn write_task<W: Write>(
+    out: &mut W,
+    project: &str,
+    task: &Task,
+    graph: &Graph,
+) -> io::Result<()> {
+    let config = config::get();
+    let format = &config.deadlines.output_format;
+    let now = now_deadline();
+    let describe = |id: &String| match graph.get(id) {
+        Some((project, t)) => format!("{} ({}: {})", id, project, t.description),
+        None => format!("{} (removed)", id),
+    };
+    let or_none = |field: Option<String>| field.unwrap_or_else(|| "-".to_string());
+
+    let mut status = Status::of(task, now).to_string();
+    if !task.is_done() && !graph.blockers(task).is_empty() {
+        status.push_str(", blocked");
+    }
+    let mut spent = task.spent(now).to_string();
+    if task.is_running() {
+        spent.push_str(", running");
+    }
+    let fields = [
+        ("id", task.id.clone()),
+        ("project", project.to_string()),
+        ("description", task.description.clone()),
+        ("status", status),
+        (
+            "priority",
+            or_none(task.priority.as_ref().map(Priority::to_string)),
+        ),
+        (
+            "deadline",
+            or_none(task.deadline.map(|d| d.format(format).to_string())),
+        ),
+        (
+            "completed",
+            or_none(task.completed.map(|c| c.format(format).to_string())),
+        ),
+        (
+            "repeats",
+            or_none(task.recurrence.as_ref().map(|r| r.to_string())),
+        ),
+        (
+            "tags",
+            or_none((!task.tags.is_empty()).then(|| {
+                task.tags
+                    .iter()
+                    .map(|t| format!("+{}", t))
+                    .collect::<Vec<_>>()
+                    .join(" ")
+            })),
+        ),
+        ("parent", or_none(task.parent.as_ref().map(describe))),
+        ("estimate", or_none(task.estimate.map(|e| e.to_string()))),
+        ("spent", spent),
+    ];
+    for (name, value) in fields {
+        writeln!(out, "{:<12} {}", format!("{}:", name), value)?;
+    }
+
+    write!(out, "{:<12}", "depends on:")?;
+    if task.depends_on.is_empty() {
+        writeln!(out, " -")?;
+    }
+    for (i, id) in task.depends_on.iter().enumerate() {
+        let indent = if i == 0 { "" } else { "            " };
+        writeln!(out, "{} {}", indent, describe(id))?;
+    }
+
+    if task.annotations.is_empty() {
+        writeln!(out, "{:<12} -", "notes:")?;
+    } else {
+        writeln!(out, "notes:")?;
+    }
+    for annotation in &task.annotations {
+        writeln!(out, "  {}", annotation.created.format(format))?;
+        for line in annotation.text.lines() {
+            writeln!(out, "    {}", line)?;
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::commands::project::Project;
+    use crate::commands::task::{parse_deadline, TaskBuilder};
+
+    fn listings() -> Vec<Listing> {
+        let mut task = TaskBuilder::new("say \"hi\", then\tleave".to_string())
+            .priority(Priority::High)
+            .deadline(parse_deadline("2000-01-01 10:00").unwrap())
+            .tag("b".to_string())
+            .tag("a".to_string())
+            .estimate(Effort::minutes(90))
+            .build();
+        task.id = "0123456789abcdef".to_string();
+        let plain = TaskBuilder::new("plain".to_string()).build();
+
+        let mut project = Project::new("proj".to_string());
+        project.push(task.clone());
+        project.push(plain.clone());
+        vec![Listing {
+            context: None,
+            project: project.summary(),
+            tasks: vec![(task, String::new()), (plain, String::new())],
+            progress: BTreeMap::new(),
+            blocked: BTreeSet::new(),
+        }]
+    }
+
+    fn render(format: Format) -> String {
+        let mut out = vec![];
+        write(&mut out, &listings(), format).unwrap();
+        String::from_utf8(out).unwrap()
+    }
+
+    #[test]
+    fn parse_format() {
+        assert_eq!("JSON".parse::<Format>(), Ok(Format::Json));
+        assert_eq!(
+            "xml".parse::<Format>(),
+            Err(FormatParseError("xml".to_string()))
+        );
+    }
+
+    #[test]
+    fn status() {
+        let mut task = TaskBuilder::new("task".to_string()).build();
+        let now = now_deadline();
+        assert_eq!(Status::of(&task, now), Status::Pending);
+        task.deadline = Some(now - chrono::Duration::hours(1));
+        assert_eq!(Status::of(&task, now), Status::Overdue);
+        task.completed = Some(now);
+        assert_eq!(Status::of(&task, now), Status::Done);
+    }
+
+    #[test]
+    fn json() {
+        let records: Vec<serde_json::Value> = serde_json::from_str(&render(Format::Json)).unwrap();
+        assert_eq!(records.len(), 2);
+        assert_eq!(records[0]["project"], "proj");
+        assert_eq!(records[0]["id"], "0123456789abcdef");
+        assert_eq!(records[0]["priority"], "high");
+        assert_eq!(records[0]["status"], "overdue");
+        assert_eq!(records[0]["tags"], serde_json::json!(["a", "b"]));
+        assert!(records[0]["deadline"]
+            .as_str()
+            .unwrap()
+            .starts_with("2000-01-01T10:00:00"));
+        assert_eq!(records[1]["priority"], serde_json::Value::Null);
+        assert_eq!(records[1]["deadline"], serde_json::Value::Null);
+        assert_eq!(records[1]["context"], serde_json::Value::Null);
+        assert_eq!(records[0]["estimate"], 90);
+        assert_eq!(records[1]["estimate"], serde_json::Value::Null);
+        assert_eq!(records[1]["spent"], 0);
+    }
+
+    #[test]
+    fn contexts() {
+        let mut listings = listings();
+        listings[0].context = Some("team".to_string());
+
+        let mut out = vec![];
+        write(&mut out, &listings, Format::Text).unwrap();
+        let text = String::from_utf8(out).unwrap();
+        assert!(text.starts_with("[team] proj: 2 tasks\n"), "{}", text);
+
+        let mut out = vec![];
+        write(&mut out, &listings, Format::Csv).unwrap();
+        let csv = String::from_utf8(out).unwrap();
+        assert!(csv.lines().skip(1).all(|line| line.contains(",team,")));
+    }
+
+    #[test]
+    fn ndjson() {
+        let out = render(Format::Ndjson);
+        let lines: Vec<&str> = out.lines().collect();
+        assert_eq!(lines.len(), 2);
+        for line in lines {
+            serde_json::from_str::<serde_json::Value>(line).unwrap();
+        }
+    }
+
+    #[test]
+    fn csv() {
+        let out = render(Format::Csv);
+        let lines: Vec<&str> = out.lines().collect();
+        assert_eq!(
+            lines[0],
+            "project,id,description,priority,deadline,status,completed,tags,context,parent,\
+             depends_on,blocked,estimate,spent"
+        );
+        assert!(lines[1].starts_with(
+            "proj,0123456789abcdef,\"say \"\"hi\"\", then\tleave\",high,2000-01-01T10:00:00"
+        ));
+        assert!(lines[1].ends_with(",overdue,,a b,,,,false,90,0"));
+        assert!(lines[2].ends_with(",plain,,,pending,,,,,,false,,0"));
+    }
+
+    #[test]
+    fn tsv() {
+        let out = render(Format::Tsv);
+        let lines: Vec<&str> = out.lines().collect();
+        assert_eq!(lines[0].split('\t').count(), HEADER.len());
+        assert!(lines[1].starts_with("proj\t0123456789abcdef\tsay \"hi\", then\\tleave\thigh\t"));
+        assert_eq!(lines[1].split('\t').count(), HEADER.len());
+    }
+
+    #[test]
+    fn subtasks() {
+        let parent = TaskBuilder::new("parent".to_string()).build();
+        let child = TaskBuilder::new("child".to_string())
+            .parent(parent.id.clone())
+            .build();
+        let grandchild = TaskBuilder::new("grandchild".to_string())
+            .parent(child.id.clone())
+            .build();
+        let orphan = TaskBuilder::new("orphan".to_string())
+            .parent("0000000000000000".to_string())
+            .build();
+        let other = TaskBuilder::new("other".to_string()).build();
+
+        let mut project = Project::new("proj".to_string());
+        for t in [&parent, &child, &grandchild, &orphan, &other] {
+            project.push(t.clone());
+        }
+        let listing = Listing {
+            context: None,
+            project: project.summary(),
+            tasks: [&grandchild, &other, &child, &orphan, &parent]
+                .into_iter()
+                .map(|t| (t.clone(), String::new()))
+                .collect(),
+            progress: project.progress(),
+            blocked: BTreeSet::new(),
+        }
+        .outline();
+        let order: Vec<&str> = listing
+            .tasks
+            .iter()
+            .map(|(t, _)| t.description.as_str())
+            .collect();
+        assert_eq!(
+            order,
+            vec!["other", "orphan", "parent", "child", "grandchild"]
+        );
+        assert_eq!(listing.depths(), vec![0, 0, 0, 1, 2]);
+
+        let mut out = vec![];
+        write(&mut out, std::slice::from_ref(&listing), Format::Text).unwrap();
+        let text = String::from_utf8(out).unwrap();
+        let lines: Vec<&str> = text.lines().collect();
+        let prefix_len = listing.project.id_prefix_len;
+        assert!(lines[3].starts_with(&format!("[{}]: ", &parent.id[..prefix_len])));
+        assert!(lines[3].ends_with(" [0/1]"));
+        assert!(lines[4].starts_with(&format!("  [{}]: child", &child.id[..prefix_len])));
+        assert!(lines[5].starts_with(&format!(
+            "    [{}]: grandchild",
+            &grandchild.id[..prefix_len]
+        )));
+
+        let mut out = vec![];
+        write(&mut out, &[listing], Format::Csv).unwrap();
+        let csv = String::from_utf8(out).unwrap();
+        assert!(csv
+            .lines()
+            .nth(4)
+            .unwrap()
+            .ends_with(&format!(",{},,false,,0", parent.id)));
+    }
+
+    #[test]
+    fn short_ids() {
+        let mut listings = listings();
+        listings[0].tasks[0].0.id = "ab".to_string();
+        listings[0].tasks[1].0.id = "\u{e9}t\u{e9}".to_string();
+
+        let mut out = vec![];
+        write(&mut out, &listings, Format::Text).unwrap();
+        let text = String::from_utf8(out).unwrap();
+        assert!(text.contains("[ab]: "));
+        assert!(text.contains("[\u{e9}t\u{e9}]: plain"));
+
+        let mut out = vec![];
+        write_completed(&mut out, &listings).unwrap();
+        assert!(String::from_utf8(out).unwrap().contains("[ab]: "));
+    }
+
+    #[test]
+    fn blocked() {
+        let mut listings = listings();
+        let (first, _) = &listings[0].tasks[0];
+        let id = first.id.clone();
+        listings[0].tasks[1].0.depends_on.insert(id.clone());
+        let plain = listings[0].tasks[1].0.id.clone();
+        listings[0].blocked.insert(plain);
+
+        let mut out = vec![];
+        write(&mut out, &listings, Format::Text).unwrap();
+        let text = String::from_utf8(out).unwrap();
+        assert!(text.lines().nth(2).unwrap().contains("(blocked)"));
+        assert!(!text.lines().nth(1).unwrap().contains("(blocked)"));
+
+        let mut out = vec![];
+        write(&mut out, &listings, Format::Json).unwrap();
+        let records: Vec<serde_json::Value> = serde_json::from_slice(&out).unwrap();
+        assert_eq!(records[0]["blocked"], false);
+        assert_eq!(records[1]["blocked"], true);
+        assert_eq!(records[1]["depends_on"], serde_json::json!([id]));
+    }
+
+    #[test]
+    fn show() {
+        use crate::commands::annotation::Annotation;
+
+        let listings = listings();
+        let (first, _) = &listings[0].tasks[0];
+        let mut second = listings[0].tasks[1].0.clone();
+        second.depends_on.insert(first.id.clone());
+        second.depends_on.insert("ffffffffffffffff".to_string());
+        second.annotations.push(Annotation::new(
+            "called them\nno answer".to_string(),
+            parse_deadline("2022-05-01 10:30").unwrap(),
+        ));
+        let mut project = Project::new("proj".to_string());
+        project.push(first.clone());
+        project.push(second.clone());
+        let projects = [project];
+        let graph = Graph::new(&projects);
+
+        let mut out = vec![];
+        write_task(&mut out, "proj", first, &graph).unwrap();
+        let text = String::from_utf8(out).unwrap();
+        let lines: Vec<&str> = text.lines().collect();
+        assert_eq!(lines[0], "id:          0123456789abcdef");
+        assert!(lines.contains(&"status:      overdue"));
+        assert!(lines.contains(&"deadline:    2000-01-01 10:00"));
+        assert!(lines.contains(&"tags:        +a +b"));
+        assert!(lines.contains(&"estimate:    1h30m"));
+        assert!(lines.contains(&"depends on:  -"));
+        assert_eq!(lines.last(), Some(&"notes:       -"));
+
+        let mut out = vec![];
+        write_task(&mut out, "proj", &second, &graph).unwrap();
+        let text = String::from_utf8(out).unwrap();
+        assert!(text.contains("status:      pending, blocked\n"));
+        assert!(text.contains(&format!(
+            "depends on:  {} (proj: say \"hi\", then\tleave)\n             ffffffffffffffff (removed)\n",
+            first.id
+        )));
+        assert!(text.ends_with("notes:\n  2022-05-01 10:30\n    called them\n    no answer\n"));
+    }
+}
@@ -3,12 +3,13 @@
 // define project type
 //
 use crate::commands::error::RustaskError;
-use crate::commands::task::Task;
+use crate::commands::task::{self, Task};
 
 use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
 use std::fmt;
 
-#[derive(Debug, Serialize, Deserialize, PartialOrd, Ord, PartialEq, Eq)]
+#[derive(Debug, Clone, Serialize, Deserialize, PartialOrd, Ord, PartialEq, Eq)]
 pub struct Project {
     pub name: String,
     tasks: Vec<Task>,
@@ -31,24 +32,119 @@ impl Project {
         self.tasks.sort();
     }
 
-    pub fn remove(&mut self, id: usize) -> Result<Task, RustaskError> {
-        if id >= self.tasks.len() {
-            return Err(RustaskError::OutOfBounds(id));
+    /// Find the position of the task whose id starts with `id`
+    ///
+    /// Fails if no task matches or if the prefix is ambiguous
+    fn find(&self, id: &str) -> Result<usize, RustaskError> {
+        let mut matches = self
+            .tasks
+            .iter()
+            .enumerate()
+            .filter(|(_, t)| t.matches_id(id))
+            .map(|(idx, _)| idx);
+
+        match (matches.next(), matches.next()) {
+            (Some(idx), None) => Ok(idx),
+            (Some(_), Some(_)) => Err(RustaskError::AmbiguousTaskId(id.to_string())),
+            (None, _) => Err(RustaskError::TaskNotFound(id.to_string())),
         }
-        Ok(self.tasks.remove(id))
     }
 
-    pub fn edit<F>(&mut self, id: usize, transform: F) -> Result<(), RustaskError>
+    /// The task whose id starts with `id`
+    pub fn task(&self, id: &str) -> Result<&Task, RustaskError> {
+        Ok(&self.tasks[self.find(id)?])
+    }
+
+    /// The task whose id is exactly `id`
+    pub fn get(&self, id: &str) -> Option<&Task> {
+        self.tasks.iter().find(|t| t.id == id)
+    }
+
+    pub fn remove(&mut self, id: &str) -> Result<Task, RustaskError> {
+        let idx = self.find(id)?;
+        Ok(self.tasks.remove(idx))
+    }
+
+    pub fn edit<F>(&mut self, id: &str, transform: F) -> Result<(), RustaskError>
     where
         F: FnOnce(&mut Task),
     {
-        if id >= self.tasks.len() {
-            return Err(RustaskError::OutOfBounds(id));
+        let idx = self.find(id)?;
+        transform(&mut self.tasks[idx]);
+        self.tasks.sort();
+
+        Ok(())
+    }
+
+    /// Order the tasks again, as tasks without a priority sort by the configured default
+    pub fn sort(&mut self) {
+        self.tasks.sort();
+    }
+
+    /// Give an id to the tasks stored before task ids existed
+    ///
+    /// Returns whether any task was changed
+    pub fn assign_missing_ids(&mut self) -> bool {
+        let mut changed = false;
+        for (position, task) in self.tasks.iter_mut().enumerate() {
+            if task.id.is_empty() {
+                task.id = task::legacy_id(&self.name, position, &task.description);
+                changed = true;
+            }
         }
 
-        self.tasks.get_mut(id).map(|task_ref| transform(task_ref));
+        changed
+    }
 
-        Ok(())
+    /// Give fresh ids to the tasks whose ids are set but are not valid task ids
+    ///
+    /// Returns the ids replaced, with their replacements.
+    pub fn replace_invalid_ids(&mut self) -> Vec<(String, String)> {
+        let mut replaced = vec![];
+        for task in self.tasks.iter_mut() {
+            if !task.id.is_empty() && !task::is_valid_id(&task.id) {
+                let id = task::new_id();
+                replaced.push((std::mem::replace(&mut task.id, id.clone()), id));
+            }
+        }
+        self.tasks.sort();
+        replaced
+    }
+
+    /// Make the tasks naming a replaced id, as their parent or as a dependency, name its
+    /// replacement
+    pub fn follow_replaced_ids(&mut self, replaced: &BTreeMap<String, String>) {
+        for task in self.tasks.iter_mut() {
+            if let Some(id) = task.parent.as_ref().and_then(|p| replaced.get(p)) {
+                task.parent = Some(id.clone());
+            }
+            task.depends_on = std::mem::take(&mut task.depends_on)
+                .into_iter()
+                .map(|d| replaced.get(&d).cloned().unwrap_or(d))
+                .collect();
+        }
+    }
+
+    /// Shortest prefix length which tells apart every task in the project (at least 4)
+    pub fn id_prefix_len(&self) -> usize {
+        id_prefix_len(self.tasks.iter().map(|t| t.id.as_str()))
+    }
+
+    /// Keep only the tasks for which `keep` holds
+    pub fn retain<F>(&mut self, keep: F)
+    where
+        F: FnMut(&Task) -> bool,
+    {
+        self.tasks.retain(keep);
+    }
+
+    /// The project as shown above its listed tasks
+    pub fn summary(&self) -> Summary {
+        Summary {
+            name: self.name.clone(),
+            pending: self.pending_len(),
+            id_prefix_len: self.id_prefix_len(),
+        }
     }
 
     pub fn tasks(&self) -> &Vec<Task> {
@@ -58,24 +154,131 @@ impl Project {
     pub fn len(&self) -> usize {
         self.tasks.len()
     }
+
+    pub fn is_empty(&self) -> bool {
+        self.tasks.is_empty()
+    }
+
+    /// Number of tasks not yet completed
+    pub fn pending_len(&self) -> usize {
+        self.tasks.iter().filter(|t| !t.is_done()).count()
+    }
+
+    /// The direct subtasks of the task whose id is exactly `id`
+    pub fn subtasks<'a>(&'a self, id: &'a str) -> impl Iterator<Item = &'a Task> {
+        self.tasks
+            .iter()
+            .filter(move |t| t.parent.as_deref() == Some(id))
+    }
+
+    /// The subtasks of the task whose id is exactly `id`, at any depth, each after its parent
+    pub fn descendants(&self, id: &str) -> Vec<&Task> {
+        // the subtasks of a task, last first
+        let subtasks = |id: &str| -> Vec<&Task> {
+            self.tasks
+                .iter()
+                .rev()
+                .filter(|t| t.parent.as_deref() == Some(id))
+                .collect()
+        };
+        let mut descendants: Vec<&Task> = vec![];
+        let mut stack = subtasks(id);
+        while let Some(task) = stack.pop() {
+            // a malformed file may nest tasks in a cycle
+            if task.id == id || descendants.iter().any(|d| d.id == task.id) {
+                continue;
+            }
+            descendants.push(task);
+            stack.extend(subtasks(&task.id));
+        }
+        descendants
+    }
+
+    /// The progress of every task with subtasks, by id
+    pub fn progress(&self) -> BTreeMap<String, Progress> {
+        let mut progress: BTreeMap<String, Progress> = BTreeMap::new();
+        for task in &self.tasks {
+            if let Some(parent) = &task.parent {
+                progress.entry(parent.clone()).or_default().count(task);
+            }
+        }
+        progress
+    }
 }
 
-impl fmt::Display for Project {
+/// How many of the direct subtasks of a task are completed
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Progress {
+    pub done: usize,
+    pub total: usize,
+}
+
+impl Progress {
+    /// Account for a subtask
+    pub fn count(&mut self, subtask: &Task) {
+        self.total += 1;
+        if subtask.is_done() {
+            self.done += 1;
+        }
+    }
+}
+
+impl fmt::Display for Progress {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}/{}", self.done, self.total)
+    }
+}
+
+/// Shortest prefix length which tells apart every one of `ids` (at least 4)
+pub fn id_prefix_len<'a, I>(ids: I) -> usize
+where
+    I: IntoIterator<Item = &'a str>,
+{
+    const MIN_PREFIX_LEN: usize = 4;
+
+    let mut ids: Vec<&str> = ids.into_iter().collect();
+    ids.sort_unstable();
+    ids.windows(2)
+        .map(|pair| {
+            pair[0]
+                .bytes()
+                .zip(pair[1].bytes())
+                .take_while(|(a, b)| a == b)
+                .count()
+                + 1
+        })
+        .fold(MIN_PREFIX_LEN, usize::max)
+        .min(task::ID_LEN)
+}
+
+/// What is shown of a project above its listed tasks, which may be only some of them
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Summary {
+    pub name: String,
+    /// Number of tasks not yet completed, listed or not
+    pub pending: usize,
+    /// Shortest prefix length which tells apart every task in the project
+    pub id_prefix_len: usize,
+}
+
+impl fmt::Display for Summary {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         write!(
             f,
             "{}: {} {}",
             self.name,
-            self.tasks.len(),
-            if self.tasks.len() == 1 {
-                "task"
-            } else {
-                "tasks"
-            }
+            self.pending,
+            if self.pending == 1 { "task" } else { "tasks" }
         )
     }
 }
 
+impl fmt::Display for Project {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.summary())
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -109,10 +312,13 @@ mod tests {
     }
 
     #[test]
-    fn remove_out_of_bounds() {
+    fn remove_not_found() {
         let mut p = Project::new(String::from("project"));
 
-        assert_eq!(p.remove(0).unwrap_err(), RustaskError::OutOfBounds(0));
+        assert_eq!(
+            p.remove("abcd").unwrap_err(),
+            RustaskError::TaskNotFound("abcd".to_string())
+        );
     }
 
     #[test]
@@ -121,16 +327,37 @@ mod tests {
         let task = TaskBuilder::new("task".to_string()).build();
         p.push(task.clone());
 
-        assert_eq!(p.remove(0).unwrap(), task);
+        assert_eq!(p.remove(&task.id[..4]).unwrap(), task);
+        assert!(p.is_empty());
     }
 
     #[test]
-    fn edit_out_of_bounds() {
+    fn remove_ambiguous() {
+        let mut p = Project::new(String::from("project"));
+        let mut first = TaskBuilder::new("first".to_string()).build();
+        first.id = "abcd000000000000".to_string();
+        let mut second = TaskBuilder::new("second".to_string()).build();
+        second.id = "abce000000000000".to_string();
+        p.push(first.clone());
+        p.push(second);
+
+        assert_eq!(
+            p.remove("abc").unwrap_err(),
+            RustaskError::AmbiguousTaskId("abc".to_string())
+        );
+        assert_eq!(p.remove("abcd").unwrap(), first);
+    }
+
+    #[test]
+    fn edit_not_found() {
         let mut p = Project::new(String::from("project"));
         let task = TaskBuilder::new("task".to_string()).build();
         p.push(task);
 
-        assert_eq!(p.edit(1, |_| {}).unwrap_err(), RustaskError::OutOfBounds(1));
+        assert_eq!(
+            p.edit("xyz", |_| {}).unwrap_err(),
+            RustaskError::TaskNotFound("xyz".to_string())
+        );
     }
 
     #[test]
@@ -139,10 +366,109 @@ mod tests {
         let task = TaskBuilder::new("task".to_string()).build();
         p.push(task.clone());
 
-        assert_eq!(p.edit(0, |_| {}), Ok(()));
+        assert_eq!(p.edit(&task.id, |_| {}), Ok(()));
         assert_eq!(p.tasks[0], task);
     }
 
+    #[test]
+    fn id_prefix_len() {
+        let mut p = Project::new(String::from("project"));
+        assert_eq!(p.id_prefix_len(), 4);
+
+        let mut first = TaskBuilder::new("first".to_string()).build();
+        first.id = "abcdef0000000000".to_string();
+        let mut second = TaskBuilder::new("second".to_string()).build();
+        second.id = "abcdef1000000000".to_string();
+        p.push(first);
+        assert_eq!(p.id_prefix_len(), 4);
+        p.push(second);
+        assert_eq!(p.id_prefix_len(), 7);
+    }
+
+    #[test]
+    fn assign_missing_ids() {
+        let mut p = Project::new(String::from("project"));
+        let mut task = TaskBuilder::new("task".to_string()).build();
+        task.id = String::new();
+        p.push(task);
+
+        assert!(p.assign_missing_ids());
+        assert_eq!(p.tasks[0].id, task::legacy_id("project", 0, "task"));
+        assert!(!p.assign_missing_ids());
+    }
+
+    #[test]
+    fn replace_invalid_ids() {
+        let mut p = Project::new(String::from("project"));
+        let mut bad = TaskBuilder::new("bad".to_string()).build();
+        bad.id = "ab".to_string();
+        let child = TaskBuilder::new("child".to_string())
+            .parent("ab".to_string())
+            .depends_on("ab".to_string())
+            .build();
+        p.push(bad);
+        p.push(child.clone());
+
+        let replaced = p.replace_invalid_ids();
+        assert_eq!(replaced.len(), 1);
+        let (old, new) = replaced[0].clone();
+        assert_eq!(old, "ab");
+        assert!(task::is_valid_id(&new));
+        assert!(p.replace_invalid_ids().is_empty());
+
+        p.follow_replaced_ids(&replaced.into_iter().collect());
+        let child = p.get(&child.id).unwrap();
+        assert_eq!(child.parent, Some(new.clone()));
+        assert_eq!(child.depends_on, [new].into());
+    }
+
+    #[test]
+    fn display_counts_pending() {
+        let mut p = Project::new(String::from("project"));
+        let mut done = TaskBuilder::new("done".to_string()).build();
+        done.completed = Some(task::now_deadline());
+        p.push(done);
+        p.push(TaskBuilder::new("task".to_string()).build());
+
+        assert_eq!(p.len(), 2);
+        assert_eq!(p.pending_len(), 1);
+        assert_eq!(format!("{}", p), "project: 1 task");
+    }
+
+    #[test]
+    fn subtasks() {
+        let mut p = Project::new(String::from("project"));
+        let parent = TaskBuilder::new("parent".to_string()).build();
+        let child = TaskBuilder::new("child".to_string())
+            .parent(parent.id.clone())
+            .build();
+        let mut grandchild = TaskBuilder::new("grandchild".to_string())
+            .parent(child.id.clone())
+            .build();
+        grandchild.completed = Some(task::now_deadline());
+        let other = TaskBuilder::new("other".to_string())
+            .parent(parent.id.clone())
+            .build();
+        for t in [&parent, &child, &grandchild, &other] {
+            p.push(t.clone());
+        }
+
+        assert_eq!(p.subtasks(&parent.id).count(), 2);
+        let descendants: Vec<&str> = p
+            .descendants(&parent.id)
+            .iter()
+            .map(|t| t.description.as_str())
+            .collect();
+        assert_eq!(descendants.len(), 3);
+        let child_at = descendants.iter().position(|d| *d == "child").unwrap();
+        assert_eq!(descendants[child_at + 1], "grandchild");
+
+        let progress = p.progress();
+        assert_eq!(progress[&parent.id], Progress { done: 0, total: 2 });
+        assert_eq!(progress[&child.id].to_string(), "1/1");
+        assert!(!progress.contains_key(&other.id));
+    }
+
     #[test]
     fn tasks() {
         let mut p = Project::new(String::from("project"));
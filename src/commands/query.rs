@@ -0,0 +1,611 @@
+// query.rs
+//
+// parse and evaluate filter expressions over tasks
+
+use std::fmt;
+
+use crate::commands::filter::Scope;
+use crate::commands::task::{now_deadline, parse_deadline_at, Deadline, Priority, Task};
+
+/// A task attribute a query can test
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Field {
+    Priority,
+    Due,
+    Project,
+    Description,
+    Tag,
+    Status,
+    Id,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Op {
+    Eq,
+    Ne,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+    /// Glob match, with `*` and `?` wildcards
+    Match,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Value {
+    Priority(Priority),
+    Deadline(Deadline),
+    Text(String),
+}
+
+/// A parsed filter expression
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Expr {
+    Or(Box<Expr>, Box<Expr>),
+    And(Box<Expr>, Box<Expr>),
+    Not(Box<Expr>),
+    Compare(Field, Op, Value),
+}
+
+const FIELDS: &str = "priority, due, project, description, tag, status or id";
+
+/// Where and why a query failed to parse
+#[derive(thiserror::Error, Debug, PartialEq, Eq)]
+pub struct QueryParseError {
+    pub query: String,
+    /// 1-based, in characters
+    pub column: usize,
+    pub message: String,
+}
+
+impl fmt::Display for QueryParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(
+            f,
+            "failed to parse query at column {}: {}",
+            self.column, self.message
+        )?;
+        writeln!(f, "  {}", self.query)?;
+        write!(f, "  {:>width$}", "^", width = self.column)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    Word(String),
+    Str(String),
+    Op(Op),
+    Open,
+    Close,
+}
+
+/// Higher for more urgent priorities
+fn rank(priority: &Priority) -> u8 {
+    match priority {
+        Priority::Urgent => 4,
+        Priority::High => 3,
+        Priority::Normal => 2,
+        Priority::Low => 1,
+        Priority::Note => 0,
+    }
+}
+
+/// Parse a filter expression, relative deadlines being relative to the current time
+///
+/// See [`parse_query_at`] for the syntax
+pub fn parse_query(s: &str) -> Result<Expr, QueryParseError> {
+    parse_query_at(s, now_deadline())
+}
+
+/// Parse a filter expression, relative deadlines being relative to `now`
+///
+/// A query is made of conditions `<field> <op> <value>` joined by `and`, `or` and `not`
+/// (in increasing order of precedence) and grouped by parentheses.
+///
+/// - `priority` compares with a priority, more urgent being greater (`priority>=high`)
+/// - `due` compares with a deadline or an offset from now (`due<7d`, `due<=friday`);
+///   tasks without a deadline never match
+/// - `project`, `description`, `tag` and `id` compare with text, `~` matching a glob
+///   (`project~"work*"`); `tag:x` holds if the task has the tag and `id:x` takes prefixes
+/// - `status` is either `pending` or `done`
+///
+/// `:` is a synonym for `=`. Values with spaces can be quoted.
+///
+/// # Examples
+///
+/// ```
+/// use rustask::commands::query::parse_query;
+/// assert!(parse_query("priority>=high and not tag:blocked").is_ok());
+/// assert_eq!(parse_query("priority>=").unwrap_err().column, 11);
+/// ```
+pub fn parse_query_at(s: &str, now: Deadline) -> Result<Expr, QueryParseError> {
+    let error = |column: usize, message: String| QueryParseError {
+        query: s.to_string(),
+        column,
+        message,
+    };
+
+    let tokens = tokenize(s).map_err(|(column, message)| error(column, message))?;
+    let mut parser = Parser {
+        tokens,
+        pos: 0,
+        end: s.chars().count() + 1,
+        now,
+    };
+    let expr = parser
+        .or()
+        .map_err(|(column, message)| error(column, message))?;
+    match parser.tokens.get(parser.pos) {
+        None => Ok(expr),
+        Some((column, _)) => Err(error(
+            *column,
+            "expected `and`, `or` or the end of the query".to_string(),
+        )),
+    }
+}
+
+fn tokenize(s: &str) -> Result<Vec<(usize, Token)>, (usize, String)> {
+    let chars: Vec<char> = s.chars().collect();
+    let is_special = |c: char| c.is_whitespace() || "()\"=!<>~:".contains(c);
+
+    let mut tokens = vec![];
+    let mut i = 0;
+    while i < chars.len() {
+        let column = i + 1;
+        let next = chars.get(i + 1).copied();
+        let (token, len) = match chars[i] {
+            c if c.is_whitespace() => {
+                i += 1;
+                continue;
+            }
+            '(' => (Token::Open, 1),
+            ')' => (Token::Close, 1),
+            '"' => {
+                let len = chars[i + 1..]
+                    .iter()
+                    .position(|c| *c == '"')
+                    .ok_or((column, "unterminated string".to_string()))?;
+                (
+                    Token::Str(chars[i + 1..i + 1 + len].iter().collect()),
+                    len + 2,
+                )
+            }
+            '=' if next == Some('=') => (Token::Op(Op::Eq), 2),
+            '=' | ':' => (Token::Op(Op::Eq), 1),
+            '!' if next == Some('=') => (Token::Op(Op::Ne), 2),
+            '!' => return Err((column, "expected `!=` (negate with `not`)".to_string())),
+            '<' if next == Some('=') => (Token::Op(Op::Le), 2),
+            '<' => (Token::Op(Op::Lt), 1),
+            '>' if next == Some('=') => (Token::Op(Op::Ge), 2),
+            '>' => (Token::Op(Op::Gt), 1),
+            '~' => (Token::Op(Op::Match), 1),
+            _ => {
+                let len = chars[i..]
+                    .iter()
+                    .position(|c| is_special(*c))
+                    .unwrap_or(chars.len() - i);
+                (Token::Word(chars[i..i + len].iter().collect()), len)
+            }
+        };
+        tokens.push((column, token));
+        i += len;
+    }
+    Ok(tokens)
+}
+
+struct Parser {
+    tokens: Vec<(usize, Token)>,
+    pos: usize,
+    /// column right past the end of the query
+    end: usize,
+    now: Deadline,
+}
+
+type ParseResult<T> = Result<T, (usize, String)>;
+
+impl Parser {
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.pos).map(|(_, t)| t)
+    }
+
+    fn column(&self) -> usize {
+        self.tokens.get(self.pos).map_or(self.end, |(c, _)| *c)
+    }
+
+    fn next(&mut self) -> Option<Token> {
+        let token = self.tokens.get(self.pos).map(|(_, t)| t.clone());
+        self.pos += 1;
+        token
+    }
+
+    fn keyword(&mut self, keyword: &str) -> bool {
+        match self.peek() {
+            Some(Token::Word(w)) if w.eq_ignore_ascii_case(keyword) => {
+                self.pos += 1;
+                true
+            }
+            _ => false,
+        }
+    }
+
+    fn or(&mut self) -> ParseResult<Expr> {
+        let mut expr = self.and()?;
+        while self.keyword("or") {
+            expr = Expr::Or(Box::new(expr), Box::new(self.and()?));
+        }
+        Ok(expr)
+    }
+
+    fn and(&mut self) -> ParseResult<Expr> {
+        let mut expr = self.not()?;
+        while self.keyword("and") {
+            expr = Expr::And(Box::new(expr), Box::new(self.not()?));
+        }
+        Ok(expr)
+    }
+
+    fn not(&mut self) -> ParseResult<Expr> {
+        if self.keyword("not") {
+            Ok(Expr::Not(Box::new(self.not()?)))
+        } else {
+            self.primary()
+        }
+    }
+
+    fn primary(&mut self) -> ParseResult<Expr> {
+        let column = self.column();
+        match self.next() {
+            Some(Token::Open) => {
+                let expr = self.or()?;
+                let column = self.column();
+                match self.next() {
+                    Some(Token::Close) => Ok(expr),
+                    _ => Err((column, "expected `)`".to_string())),
+                }
+            }
+            Some(Token::Word(word)) => self.compare(column, &word),
+            _ => Err((column, format!("expected a condition on {}", FIELDS))),
+        }
+    }
+
+    fn compare(&mut self, column: usize, word: &str) -> ParseResult<Expr> {
+        let field = match word.to_lowercase().as_str() {
+            "priority" | "pri" => Field::Priority,
+            "due" | "deadline" => Field::Due,
+            "project" => Field::Project,
+            "description" | "desc" => Field::Description,
+            "tag" => Field::Tag,
+            "status" => Field::Status,
+            "id" => Field::Id,
+            _ => {
+                return Err((
+                    column,
+                    format!("unknown field `{}`; expected {}", word, FIELDS),
+                ))
+            }
+        };
+
+        let op_column = self.column();
+        let op = match self.next() {
+            Some(Token::Op(op)) => op,
+            _ => {
+                return Err((
+                    op_column,
+                    format!(
+                        "expected an operator (=, !=, <, <=, >, >=, ~) after `{}`",
+                        word
+                    ),
+                ))
+            }
+        };
+
+        let ordering = matches!(op, Op::Lt | Op::Le | Op::Gt | Op::Ge);
+        let unsupported = match field {
+            Field::Priority => op == Op::Match,
+            Field::Due => !ordering,
+            Field::Status => !matches!(op, Op::Eq | Op::Ne),
+            _ => ordering,
+        };
+        if unsupported {
+            return Err((op_column, format!("`{}` cannot be compared this way", word)));
+        }
+
+        let value_column = self.column();
+        let text = match self.next() {
+            Some(Token::Word(w)) | Some(Token::Str(w)) => w,
+            _ => return Err((value_column, "expected a value".to_string())),
+        };
+        let value = match field {
+            Field::Priority => Value::Priority(text.parse::<Priority>().map_err(|_| {
+                (
+                    value_column,
+                    "expected a priority (urgent, high, normal, low or note)".to_string(),
+                )
+            })?),
+            Field::Due => Value::Deadline(self.deadline(&text).ok_or((
+                value_column,
+                format!("expected a deadline or an offset (e.g. 7d), got `{}`", text),
+            ))?),
+            Field::Status => match text.to_lowercase().as_str() {
+                "pending" | "done" => Value::Text(text.to_lowercase()),
+                _ => {
+                    return Err((
+                        value_column,
+                        "expected a status (pending or done)".to_string(),
+                    ))
+                }
+            },
+            _ => Value::Text(text),
+        };
+        Ok(Expr::Compare(field, op, value))
+    }
+
+    /// A deadline, or an offset from now (`7d` meaning `+7d`)
+    fn deadline(&self, text: &str) -> Option<Deadline> {
+        parse_deadline_at(text, self.now).ok().or_else(|| {
+            text.starts_with(|c: char| c.is_ascii_digit())
+                .then(|| parse_deadline_at(&format!("+{}", text), self.now).ok())
+                .flatten()
+        })
+    }
+}
+
+/// Whether `text` matches `pattern`, where `*` matches any run of characters and `?` any
+/// single character
+fn glob(pattern: &str, text: &str) -> bool {
+    let pattern: Vec<char> = pattern.chars().collect();
+    let text: Vec<char> = text.chars().collect();
+
+    let (mut p, mut t) = (0, 0);
+    // the last `*` seen, and the position in the text it was tried at
+    let mut backtrack = None;
+    while t < text.len() {
+        match pattern.get(p) {
+            Some('*') => {
+                backtrack = Some((p, t));
+                p += 1;
+            }
+            Some(c) if *c == '?' || *c == text[t] => {
+                p += 1;
+                t += 1;
+            }
+            _ => match backtrack {
+                Some((star, tried)) => {
+                    p = star + 1;
+                    t = tried + 1;
+                    backtrack = Some((star, tried + 1));
+                }
+                None => return false,
+            },
+        }
+    }
+    pattern[p..].iter().all(|c| *c == '*')
+}
+
+fn compare<T: PartialOrd>(op: Op, lhs: T, rhs: T) -> bool {
+    match op {
+        Op::Eq => lhs == rhs,
+        Op::Ne => lhs != rhs,
+        Op::Lt => lhs < rhs,
+        Op::Le => lhs <= rhs,
+        Op::Gt => lhs > rhs,
+        Op::Ge => lhs >= rhs,
+        Op::Match => false,
+    }
+}
+
+fn compare_text(op: Op, text: &str, value: &str) -> bool {
+    match op {
+        Op::Match => glob(value, text),
+        op => compare(op, text, value),
+    }
+}
+
+impl Expr {
+    /// Whether `task`, in `project`, satisfies the expression
+    pub fn matches(&self, project: &str, task: &Task) -> bool {
+        match self {
+            Expr::Or(a, b) => a.matches(project, task) || b.matches(project, task),
+            Expr::And(a, b) => a.matches(project, task) && b.matches(project, task),
+            Expr::Not(e) => !e.matches(project, task),
+            Expr::Compare(field, op, value) => match (field, value) {
+                (Field::Priority, Value::Priority(p)) => {
+                    compare(*op, rank(task.effective_priority()), rank(p))
+                }
+                (Field::Due, Value::Deadline(d)) => task
+                    .deadline
+                    .is_some_and(|deadline| compare(*op, deadline, *d)),
+                (Field::Project, Value::Text(t)) => compare_text(*op, project, t),
+                (Field::Description, Value::Text(t)) => compare_text(*op, &task.description, t),
+                (Field::Tag, Value::Text(t)) => match op {
+                    Op::Eq => task.has_tag(t),
+                    Op::Ne => !task.has_tag(t),
+                    _ => task.tags.iter().any(|tag| glob(t, tag)),
+                },
+                (Field::Status, Value::Text(t)) => {
+                    let status = if task.is_done() { "done" } else { "pending" };
+                    compare(*op, status, t.as_str())
+                }
+                (Field::Id, Value::Text(t)) => match op {
+                    Op::Eq => task.matches_id(t),
+                    Op::Ne => !task.matches_id(t),
+                    _ => glob(&t.to_lowercase(), &task.id),
+                },
+                _ => false,
+            },
+        }
+    }
+
+    /// Tighten `scope` with the bounds every task satisfying the expression is within
+    ///
+    /// Only the comparisons joined by `and` at the top of the expression are looked at.
+    pub fn narrow(&self, scope: &mut Scope) {
+        match self {
+            Expr::And(a, b) => {
+                a.narrow(scope);
+                b.narrow(scope);
+            }
+            Expr::Compare(Field::Priority, op @ (Op::Eq | Op::Gt | Op::Ge), Value::Priority(p)) => {
+                // a priority higher than the least urgent one is at least as urgent as it
+                let p = match (op, p) {
+                    (Op::Gt, Priority::Note) => Priority::Low,
+                    (Op::Gt, Priority::Low) => Priority::Normal,
+                    (Op::Gt, Priority::Normal) => Priority::High,
+                    (Op::Gt, _) => Priority::Urgent,
+                    (_, p) => p.clone(),
+                };
+                if scope.priority.as_ref().is_none_or(|current| p < *current) {
+                    scope.priority = Some(p);
+                }
+            }
+            Expr::Compare(Field::Due, op, Value::Deadline(d)) => {
+                if matches!(op, Op::Eq | Op::Lt | Op::Le) && scope.due_before.is_none_or(|b| *d < b)
+                {
+                    scope.due_before = Some(*d);
+                }
+                if matches!(op, Op::Eq | Op::Gt | Op::Ge) && scope.due_after.is_none_or(|a| *d > a)
+                {
+                    scope.due_after = Some(*d);
+                }
+            }
+            Expr::Compare(Field::Project, Op::Eq, Value::Text(name)) if scope.project.is_none() => {
+                scope.project = Some(name.clone());
+            }
+            _ => {}
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::commands::task::{parse_deadline, TaskBuilder};
+
+    fn now() -> Deadline {
+        parse_deadline("2022-05-04 10:00").unwrap()
+    }
+
+    fn parse(s: &str) -> Expr {
+        parse_query_at(s, now()).unwrap()
+    }
+
+    fn column(s: &str) -> usize {
+        parse_query_at(s, now()).unwrap_err().column
+    }
+
+    fn task() -> (&'static str, Task) {
+        let task = TaskBuilder::new("write the report".to_string())
+            .priority(Priority::High)
+            .deadline(parse_deadline("2022-05-06 17:00").unwrap())
+            .tag("@review".to_string())
+            .build();
+        ("work-reports", task)
+    }
+
+    #[test]
+    fn precedence() {
+        let cmp = |s: &str| Expr::Compare(Field::Tag, Op::Eq, Value::Text(s.to_string()));
+        assert_eq!(
+            parse("tag:a or not tag:b and tag:c"),
+            Expr::Or(
+                Box::new(cmp("a")),
+                Box::new(Expr::And(
+                    Box::new(Expr::Not(Box::new(cmp("b")))),
+                    Box::new(cmp("c"))
+                ))
+            )
+        );
+        assert_eq!(
+            parse("(tag:a or tag:b) and tag:c"),
+            Expr::And(
+                Box::new(Expr::Or(Box::new(cmp("a")), Box::new(cmp("b")))),
+                Box::new(cmp("c"))
+            )
+        );
+    }
+
+    #[test]
+    fn values() {
+        assert_eq!(
+            parse("due<7d"),
+            Expr::Compare(
+                Field::Due,
+                Op::Lt,
+                Value::Deadline(parse_deadline("2022-05-11 10:00").unwrap())
+            )
+        );
+        assert_eq!(
+            parse("priority >= High"),
+            Expr::Compare(Field::Priority, Op::Ge, Value::Priority(Priority::High))
+        );
+        assert_eq!(
+            parse("desc~\"the *\""),
+            Expr::Compare(
+                Field::Description,
+                Op::Match,
+                Value::Text("the *".to_string())
+            )
+        );
+    }
+
+    #[test]
+    fn evaluate() {
+        let (project, task) = task();
+        let matches = |s: &str| parse(s).matches(project, &task);
+
+        assert!(matches(
+            "priority>=high and due<7d and not tag:blocked and project~\"work*\""
+        ));
+        assert!(matches("priority>normal and priority<=high"));
+        assert!(!matches("priority=urgent"));
+        assert!(matches("due<=2022-05-07 and due>tomorrow"));
+        assert!(!matches("due<1d"));
+        assert!(matches("tag~@rev* and tag!=home"));
+        assert!(matches("description~*report and status=pending"));
+        assert!(matches(&format!("id:{}", &task.id[..4])));
+        assert!(!matches("project=work"));
+
+        let undated = TaskBuilder::new("undated".to_string()).build();
+        assert!(!parse("due<7d").matches(project, &undated));
+        assert!(parse("not due<7d").matches(project, &undated));
+        assert!(parse("priority=normal").matches(project, &undated));
+    }
+
+    #[test]
+    fn glob_patterns() {
+        assert!(glob("work*", "work"));
+        assert!(glob("work*", "workshop"));
+        assert!(glob("*shop", "workshop"));
+        assert!(glob("w?rk*p", "workshop"));
+        assert!(glob("*o*o*", "workshop"));
+        assert!(!glob("work", "workshop"));
+        assert!(!glob("*x*", "workshop"));
+    }
+
+    #[test]
+    fn error_columns() {
+        assert_eq!(column("priority>="), 11);
+        assert_eq!(column("priority>=highest"), 11);
+        assert_eq!(column("tag:a and colour=red"), 11);
+        assert_eq!(column("tag:a and"), 10);
+        assert_eq!(column("(tag:a or tag:b"), 16);
+        assert_eq!(column("tag:a tag:b"), 7);
+        assert_eq!(column("project~\"work"), 9);
+        assert_eq!(column("priority~high"), 9);
+        assert_eq!(column("due=7d"), 4);
+        assert_eq!(column("due<someday"), 5);
+        assert_eq!(column("project"), 8);
+        assert_eq!(column("!tag:a"), 1);
+    }
+
+    #[test]
+    fn error_display() {
+        let err = parse_query_at("priority>=highest", now()).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "failed to parse query at column 11: \
+             expected a priority (urgent, high, normal, low or note)\n  \
+             priority>=highest\n            ^"
+        );
+    }
+}
@@ -0,0 +1,234 @@
+// recurrence.rs
+//
+// define when recurring tasks come back
+
+use chrono::{Datelike, Days, Months, Weekday};
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::str::FromStr;
+
+use crate::commands::deadline::localize;
+use crate::commands::task::Deadline;
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+pub enum Rule {
+    Daily,
+    Weekly,
+    /// Same day of the following month, clamped to the end of shorter months
+    Monthly,
+    EveryNDays(u32),
+    /// Next of the given days of the week
+    Weekdays(Vec<Weekday>),
+}
+
+/// How a task repeats once it is done
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+pub struct Recurrence {
+    pub rule: Rule,
+    /// No occurrence is created past this date
+    pub until: Option<Deadline>,
+}
+
+impl Rule {
+    /// The occurrence right after `deadline`, at the same time of day
+    pub fn after(&self, deadline: Deadline) -> Option<Deadline> {
+        let naive = deadline.naive_local();
+        let next = match self {
+            Rule::Daily => naive.checked_add_days(Days::new(1)),
+            Rule::Weekly => naive.checked_add_days(Days::new(7)),
+            Rule::Monthly => naive.checked_add_months(Months::new(1)),
+            Rule::EveryNDays(n) => naive.checked_add_days(Days::new((*n).max(1) as u64)),
+            Rule::Weekdays(days) => (1..=7)
+                .filter_map(|n| naive.checked_add_days(Days::new(n)))
+                .find(|d| days.contains(&d.weekday())),
+        }?;
+        localize(next)
+    }
+}
+
+impl Recurrence {
+    pub fn new(rule: Rule) -> Recurrence {
+        Recurrence { rule, until: None }
+    }
+
+    pub fn until(mut self, until: Deadline) -> Recurrence {
+        self.until = Some(until);
+        self
+    }
+
+    /// The deadline of the occurrence following one due at `deadline` and done at `now`
+    ///
+    /// Occurrences which would already be overdue at `now` are skipped.
+    /// Yields `None` once the recurrence is over.
+    pub fn next(&self, deadline: Deadline, now: Deadline) -> Option<Deadline> {
+        let mut next = self.rule.after(deadline)?;
+        while next <= now {
+            next = self.rule.after(next)?;
+        }
+
+        match self.until {
+            Some(until) if next > until => None,
+            _ => Some(next),
+        }
+    }
+}
+
+#[derive(thiserror::Error, Debug, PartialEq)]
+#[error(
+    "failed to parse `{}` as a recurrence: expected daily, weekly, monthly, \
+     every <n> days, <n>d or a list of weekdays (e.g. mon,thu)",
+    .0
+)]
+pub struct RecurrenceParseError(String);
+
+impl FromStr for Rule {
+    type Err = RecurrenceParseError;
+    fn from_str(s: &str) -> Result<Self, RecurrenceParseError> {
+        let err = || RecurrenceParseError(s.to_string());
+        let lower = s.trim().to_lowercase();
+
+        let every_n = |n: &str| match n.trim().parse::<u32>() {
+            Ok(n) if n > 0 => Ok(Rule::EveryNDays(n)),
+            _ => Err(err()),
+        };
+
+        match lower.as_str() {
+            "daily" | "every day" => Ok(Rule::Daily),
+            "weekly" | "every week" => Ok(Rule::Weekly),
+            "monthly" | "every month" => Ok(Rule::Monthly),
+            _ => {
+                if let Some(n) = lower
+                    .strip_prefix("every ")
+                    .and_then(|r| r.strip_suffix(" days").or_else(|| r.strip_suffix(" day")))
+                {
+                    every_n(n)
+                } else if let Some(n) = lower
+                    .strip_suffix('d')
+                    .filter(|n| n.chars().all(|c| c.is_ascii_digit()))
+                {
+                    every_n(n)
+                } else {
+                    let mut days = lower
+                        .split(',')
+                        .map(|d| d.trim().parse::<Weekday>().map_err(|_| err()))
+                        .collect::<Result<Vec<_>, _>>()?;
+                    days.sort_by_key(|d| d.num_days_from_monday());
+                    days.dedup();
+                    Ok(Rule::Weekdays(days))
+                }
+            }
+        }
+    }
+}
+
+impl fmt::Display for Rule {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Rule::Daily => write!(f, "daily"),
+            Rule::Weekly => write!(f, "weekly"),
+            Rule::Monthly => write!(f, "monthly"),
+            Rule::EveryNDays(n) => write!(f, "every {} days", n),
+            Rule::Weekdays(days) => write!(
+                f,
+                "every {}",
+                days.iter()
+                    .map(|d| d.to_string())
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            ),
+        }
+    }
+}
+
+impl fmt::Display for Recurrence {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.until {
+            Some(until) => write!(f, "{} until {}", self.rule, until.format("%F")),
+            None => write!(f, "{}", self.rule),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::commands::task::parse_deadline;
+
+    fn at(s: &str) -> Deadline {
+        parse_deadline(s).unwrap()
+    }
+
+    #[test]
+    fn parse() {
+        assert_eq!("daily".parse::<Rule>(), Ok(Rule::Daily));
+        assert_eq!("Weekly".parse::<Rule>(), Ok(Rule::Weekly));
+        assert_eq!("monthly".parse::<Rule>(), Ok(Rule::Monthly));
+        assert_eq!("every 3 days".parse::<Rule>(), Ok(Rule::EveryNDays(3)));
+        assert_eq!("10d".parse::<Rule>(), Ok(Rule::EveryNDays(10)));
+        assert_eq!(
+            "fri, mon,monday".parse::<Rule>(),
+            Ok(Rule::Weekdays(vec![Weekday::Mon, Weekday::Fri]))
+        );
+        assert_eq!(
+            "wed".parse::<Rule>(),
+            Ok(Rule::Weekdays(vec![Weekday::Wed]))
+        );
+        assert!("0d".parse::<Rule>().is_err());
+        assert!("sometimes".parse::<Rule>().is_err());
+    }
+
+    #[test]
+    fn after() {
+        let deadline = at("2022-01-31 09:00");
+        assert_eq!(Rule::Daily.after(deadline), Some(at("2022-02-01 09:00")));
+        assert_eq!(Rule::Weekly.after(deadline), Some(at("2022-02-07 09:00")));
+        assert_eq!(Rule::Monthly.after(deadline), Some(at("2022-02-28 09:00")));
+        assert_eq!(
+            Rule::EveryNDays(3).after(deadline),
+            Some(at("2022-02-03 09:00"))
+        );
+        // 2022-01-31 is a monday
+        assert_eq!(
+            Rule::Weekdays(vec![Weekday::Mon, Weekday::Thu]).after(deadline),
+            Some(at("2022-02-03 09:00"))
+        );
+        assert_eq!(
+            Rule::Weekdays(vec![Weekday::Mon]).after(deadline),
+            Some(at("2022-02-07 09:00"))
+        );
+    }
+
+    #[test]
+    fn next_skips_past_occurrences() {
+        let recurrence = Recurrence::new(Rule::Weekly);
+        assert_eq!(
+            recurrence.next(at("2022-01-03"), at("2022-01-02")),
+            Some(at("2022-01-10"))
+        );
+        assert_eq!(
+            recurrence.next(at("2022-01-03"), at("2022-01-20")),
+            Some(at("2022-01-24"))
+        );
+    }
+
+    #[test]
+    fn next_until() {
+        let recurrence = Recurrence::new(Rule::Daily).until(at("2022-01-05"));
+        assert_eq!(
+            recurrence.next(at("2022-01-04"), at("2022-01-04")),
+            Some(at("2022-01-05"))
+        );
+        assert_eq!(recurrence.next(at("2022-01-05"), at("2022-01-05")), None);
+    }
+
+    #[test]
+    fn display() {
+        assert_eq!(Rule::EveryNDays(2).to_string(), "every 2 days");
+        assert_eq!(
+            Recurrence::new(Rule::Weekdays(vec![Weekday::Mon, Weekday::Fri]))
+                .until(at("2022-03-01"))
+                .to_string(),
+            "every Mon, Fri until 2022-03-01"
+        );
+    }
+}
@@ -0,0 +1,230 @@
+// repair.rs
+//
+// salvage what can be read from corrupt task files
+
+use serde_json::Value;
+use std::collections::BTreeMap;
+
+use crate::commands::project::Project;
+use crate::commands::task::Task;
+
+/// What could be read from a task file
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct Salvage {
+    pub projects: Vec<Project>,
+    /// What had to be left out, one line each
+    pub dropped: Vec<String>,
+    /// The tasks given new ids, one line each
+    pub renamed: Vec<String>,
+}
+
+impl Salvage {
+    /// Number of tasks kept
+    pub fn tasks(&self) -> usize {
+        self.projects.iter().map(Project::len).sum()
+    }
+
+    /// Give fresh ids to the tasks whose ids are not valid task ids, which cannot be
+    /// told apart by prefix, along with the subtasks and dependencies naming them
+    pub fn replace_invalid_ids(&mut self) {
+        let mut replaced = BTreeMap::new();
+        for project in self.projects.iter_mut() {
+            for (old, new) in project.replace_invalid_ids() {
+                self.renamed
+                    .push(format!("task {} in {} to {}", old, project.name, new));
+                replaced.insert(old, new);
+            }
+        }
+        if !replaced.is_empty() {
+            for project in self.projects.iter_mut() {
+                project.follow_replaced_ids(&replaced);
+            }
+        }
+    }
+}
+
+/// Keep the projects and tasks of `document`, in any version of the format, which can
+/// be read on their own
+///
+/// Projects appearing more than once are merged; tasks whose id is taken are dropped.
+pub fn salvage(document: Value) -> Salvage {
+    let mut dropped = vec![];
+    let projects = match document {
+        Value::Array(projects) => projects,
+        Value::Object(mut fields) => match fields.remove("projects") {
+            Some(Value::Array(projects)) => projects,
+            None => vec![],
+            Some(_) => {
+                dropped.push("the projects: they are not a list".to_string());
+                vec![]
+            }
+        },
+        _ => {
+            dropped.push("everything: the file holds no projects".to_string());
+            vec![]
+        }
+    };
+
+    let mut kept: BTreeMap<String, Project> = BTreeMap::new();
+    for (i, project) in projects.into_iter().enumerate() {
+        let name = match project.get("name").and_then(Value::as_str) {
+            Some(name) => name.to_string(),
+            None => {
+                dropped.push(format!("project #{}: it has no name", i + 1));
+                continue;
+            }
+        };
+        let tasks = match project.get("tasks") {
+            Some(Value::Array(tasks)) => tasks.clone(),
+            None => vec![],
+            Some(_) => {
+                dropped.push(format!("the tasks of {}: they are not a list", name));
+                vec![]
+            }
+        };
+
+        let target = kept
+            .entry(name.clone())
+            .or_insert_with(|| Project::new(name.clone()));
+        for (j, task) in tasks.into_iter().enumerate() {
+            match serde_json::from_value::<Task>(task) {
+                Ok(task) if !task.id.is_empty() && target.get(&task.id).is_some() => dropped.push(
+                    format!("task #{} of {}: its id {} is taken", j + 1, name, task.id),
+                ),
+                Ok(task) => target.push(task),
+                Err(e) => dropped.push(format!("task #{} of {}: {}", j + 1, name, e)),
+            }
+        }
+    }
+
+    let mut projects: Vec<Project> = kept.into_values().collect();
+    for p in projects.iter_mut() {
+        p.assign_missing_ids();
+    }
+    Salvage {
+        projects,
+        dropped,
+        renamed: vec![],
+    }
+}
+
+/// Read as much as possible of a truncated or corrupt JSON document
+///
+/// The document is cut after the last list element which is an object (a project or a
+/// task) ending before the first syntax error, and the lists and objects still open
+/// there are closed. Returns the document and the length of the part of `contents` kept.
+pub fn recover_json(contents: &[u8]) -> Option<(Value, usize)> {
+    let error = match serde_json::from_slice::<Value>(contents) {
+        Ok(document) => return Some((document, contents.len())),
+        Err(e) => e,
+    };
+    let error_offset = contents
+        .split_inclusive(|b| *b == b'\n')
+        .take(error.line().saturating_sub(1))
+        .map(<[u8]>::len)
+        .sum::<usize>()
+        + error.column();
+
+    let mut cuts = vec![];
+    let mut open = vec![];
+    let mut in_string = false;
+    let mut escaped = false;
+    for (i, b) in contents[..error_offset.min(contents.len())]
+        .iter()
+        .enumerate()
+    {
+        if in_string {
+            match b {
+                _ if escaped => escaped = false,
+                b'\\' => escaped = true,
+                b'"' => in_string = false,
+                _ => {}
+            }
+            continue;
+        }
+        match b {
+            b'"' => in_string = true,
+            b'{' | b'[' => open.push(*b),
+            b'}' | b']' => {
+                open.pop();
+                if *b == b'}' && open.last() == Some(&b'[') {
+                    cuts.push((i + 1, open.clone()));
+                }
+            }
+            _ => {}
+        }
+    }
+
+    cuts.into_iter().rev().find_map(|(cut, open)| {
+        let mut text = contents[..cut].to_vec();
+        text.extend(
+            open.iter()
+                .rev()
+                .map(|b| if *b == b'{' { b'}' } else { b']' }),
+        );
+        serde_json::from_slice(&text)
+            .ok()
+            .map(|document| (document, cut))
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::commands::task::TaskBuilder;
+    use serde_json::json;
+
+    #[test]
+    fn salvage_items() {
+        let task = TaskBuilder::new("task".to_string()).build();
+        let document = json!({
+            "version": 2,
+            "projects": [
+                { "name": "a", "tasks": [task, { "description": 3 }] },
+                { "tasks": [] },
+                { "name": "a", "tasks": [task, { "description": "legacy", "priority": null, "deadline": null }] },
+                { "name": "b", "tasks": "none" },
+            ],
+        });
+
+        let salvage = salvage(document);
+        assert_eq!(
+            salvage
+                .projects
+                .iter()
+                .map(|p| (p.name.as_str(), p.len()))
+                .collect::<Vec<_>>(),
+            vec![("a", 2), ("b", 0)]
+        );
+        assert_eq!(salvage.tasks(), 2);
+        assert_eq!(salvage.dropped.len(), 4);
+        assert!(salvage.dropped[0].starts_with("task #2 of a: "));
+        assert_eq!(salvage.dropped[1], "project #2: it has no name");
+        assert_eq!(
+            salvage.dropped[2],
+            format!("task #1 of a: its id {} is taken", task.id)
+        );
+        assert_eq!(salvage.dropped[3], "the tasks of b: they are not a list");
+    }
+
+    #[test]
+    fn recover_truncated() {
+        let first = TaskBuilder::new("first \"}]\" task".to_string()).build();
+        let second = TaskBuilder::new("second".to_string()).build();
+        let document = json!({
+            "version": 2,
+            "projects": [{ "name": "a", "tasks": [first, second] }],
+        });
+        let text = serde_json::to_string_pretty(&document).unwrap();
+
+        // cut in the middle of the second task
+        let cut = text.find("second").unwrap();
+        let (recovered, kept) = recover_json(&text.as_bytes()[..cut]).unwrap();
+        assert!(kept < cut);
+        let salvage = salvage(recovered);
+        assert_eq!(salvage.projects[0].tasks(), &vec![first]);
+
+        assert_eq!(recover_json(text.as_bytes()), Some((document, text.len())));
+        assert_eq!(recover_json(b"{\"version\": 2, \"proj"), None);
+    }
+}
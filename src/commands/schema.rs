@@ -0,0 +1,437 @@
+// schema.rs
+//
+// version the task file format and upgrade older files
+
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use std::collections::BTreeSet;
+
+use crate::commands::dependency::Graph;
+use crate::commands::error::RustaskError;
+use crate::commands::project::Project;
+use crate::commands::task;
+
+/// The version of the task file format written by this rustask
+///
+/// 1. the projects alone, as a list (in TOML, under a `projects` key)
+/// 2. the projects under `projects`, next to the `version`
+/// 3. tasks may be subtasks of others, by their `parent`
+/// 4. tasks may wait on others, listed in `depends_on`
+/// 5. tasks may have an `estimate` and the `time` spent on them
+/// 6. tasks may have `annotations`
+pub const CURRENT_VERSION: u32 = 6;
+
+/// Upgrade a document from the version it is indexed by (plus one) to the next one
+type Migration = fn(Value) -> Result<Value, String>;
+
+const MIGRATIONS: [Migration; CURRENT_VERSION as usize - 1] = [
+    add_version,
+    add_subtasks,
+    add_dependencies,
+    add_time_tracking,
+    add_annotations,
+];
+
+/// A task file, as stored
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+pub struct Document {
+    pub version: u32,
+    pub projects: Vec<Project>,
+}
+
+impl Document {
+    /// A document of the current version
+    pub fn new(projects: Vec<Project>) -> Document {
+        Document {
+            version: CURRENT_VERSION,
+            projects,
+        }
+    }
+}
+
+/// 1 to 2: keep the projects next to a version
+fn add_version(document: Value) -> Result<Value, String> {
+    match document {
+        Value::Array(projects) => Ok(json!({ "version": 2, "projects": projects })),
+        Value::Object(mut fields) => {
+            fields.insert("version".to_string(), json!(2));
+            fields.entry("projects").or_insert_with(|| json!([]));
+            Ok(Value::Object(fields))
+        }
+        _ => Err("expected a list of projects".to_string()),
+    }
+}
+
+/// Set the version of a document whose contents need no change
+///
+/// The version changes nonetheless, so that older rustasks refuse files using what it
+/// adds rather than drop it.
+fn bump(document: Value, version: u32) -> Result<Value, String> {
+    match document {
+        Value::Object(mut fields) => {
+            fields.insert("version".to_string(), json!(version));
+            Ok(Value::Object(fields))
+        }
+        _ => Err("expected a version and projects".to_string()),
+    }
+}
+
+/// 2 to 3: no task of an older file is a subtask
+fn add_subtasks(document: Value) -> Result<Value, String> {
+    bump(document, 3)
+}
+
+/// 3 to 4: no task of an older file waits on others
+fn add_dependencies(document: Value) -> Result<Value, String> {
+    bump(document, 4)
+}
+
+/// 4 to 5: no time was tracked against the tasks of an older file
+fn add_time_tracking(document: Value) -> Result<Value, String> {
+    bump(document, 5)
+}
+
+/// 5 to 6: no task of an older file has annotations
+fn add_annotations(document: Value) -> Result<Value, String> {
+    bump(document, 6)
+}
+
+/// The version of the task file format `document`, whatever its version, is in
+pub fn version_of(document: &Value) -> Result<u32, RustaskError> {
+    match document.get("version") {
+        None if document.is_array() || document.is_object() => Ok(1),
+        Some(v) => match v.as_u64().and_then(|v| u32::try_from(v).ok()) {
+            Some(v) if v > 0 => Ok(v),
+            _ => Err(RustaskError::FormatError(format!(
+                "invalid version `{}`",
+                v
+            ))),
+        },
+        None => Err(RustaskError::FormatError(
+            "expected a list of projects".to_string(),
+        )),
+    }
+}
+
+/// Read `document`, upgrading it from older versions of the format
+///
+/// Files from newer versions of rustask are refused, rather than read with whatever
+/// they added dropped.
+pub fn upgrade(mut document: Value) -> Result<Document, RustaskError> {
+    let mut version = version_of(&document)?;
+    if version > CURRENT_VERSION {
+        return Err(RustaskError::UnsupportedVersion(version, CURRENT_VERSION));
+    }
+
+    while version < CURRENT_VERSION {
+        document = MIGRATIONS[version as usize - 1](document).map_err(|e| {
+            RustaskError::FormatError(format!("upgrading from version {}: {}", version, e))
+        })?;
+        version += 1;
+    }
+    serde_json::from_value(document).map_err(|e| RustaskError::FormatError(e.to_string()))
+}
+
+/// Problems in `projects` which loading them does not catch
+///
+/// Each problem is described in a line of its own.
+pub fn validate(projects: &[Project]) -> Vec<String> {
+    let graph = Graph::new(projects);
+    let mut problems = vec![];
+    let mut names = BTreeSet::new();
+    let mut running = vec![];
+    for project in projects {
+        if project.name.trim().is_empty() {
+            problems.push("a project has an empty name".to_string());
+        }
+        if !names.insert(project.name.as_str()) {
+            problems.push(format!("project {} appears more than once", project.name));
+        }
+
+        let mut ids = BTreeSet::new();
+        for t in project.tasks() {
+            if !task::is_valid_id(&t.id) {
+                problems.push(format!(
+                    "task {} in {} has an id which is not {} lowercase hexadecimal digits",
+                    t.id,
+                    project.name,
+                    task::ID_LEN
+                ));
+            }
+            if !ids.insert(t.id.as_str()) {
+                problems.push(format!(
+                    "task id {} appears more than once in {}",
+                    t.id, project.name
+                ));
+            }
+            if t.description.trim().is_empty() {
+                problems.push(format!(
+                    "task {} in {} has an empty description",
+                    t.id, project.name
+                ));
+            }
+            if let Some(parent) = &t.parent {
+                if project.get(parent).is_none() {
+                    problems.push(format!(
+                        "task {} in {} is a subtask of {}, which is not in {}",
+                        t.id, project.name, parent, project.name
+                    ));
+                } else if is_own_ancestor(project, t) {
+                    problems.push(format!(
+                        "task {} in {} is a subtask of itself",
+                        t.id, project.name
+                    ));
+                }
+            }
+            for id in &t.depends_on {
+                if graph.get(id).is_none() {
+                    problems.push(format!(
+                        "task {} in {} depends on {}, which does not exist",
+                        t.id, project.name, id
+                    ));
+                }
+            }
+            if let Some(cycle) = graph.cycle(t) {
+                problems.push(format!(
+                    "task {} in {} depends on itself: {}",
+                    t.id,
+                    project.name,
+                    cycle.join(" -> ")
+                ));
+            }
+            if let (Some(deadline), Some(until)) =
+                (t.deadline, t.recurrence.as_ref().and_then(|r| r.until))
+            {
+                if until < deadline {
+                    problems.push(format!(
+                        "task {} in {} recurs until before its deadline",
+                        t.id, project.name
+                    ));
+                }
+            }
+            if t.time
+                .iter()
+                .any(|i| i.end.is_some_and(|end| end < i.start))
+            {
+                problems.push(format!(
+                    "task {} in {} has time which ends before it starts",
+                    t.id, project.name
+                ));
+            }
+            let open = t.time.iter().filter(|i| i.is_running()).count();
+            if open > 1 || (open == 1 && !t.is_running()) {
+                problems.push(format!(
+                    "task {} in {} has a timer running which is not its last time",
+                    t.id, project.name
+                ));
+            }
+            if open > 0 {
+                running.push(t.id.as_str());
+            }
+        }
+    }
+    if running.len() > 1 {
+        problems.push(format!(
+            "timers are running on several tasks: {}",
+            running.join(", ")
+        ));
+    }
+    problems
+}
+
+/// Whether following the parents of `task` leads back to it
+fn is_own_ancestor(project: &Project, task: &task::Task) -> bool {
+    let mut parent = task.parent.as_deref();
+    for _ in 0..project.len() {
+        match parent.and_then(|id| project.get(id)) {
+            Some(p) if p.id == task.id => return true,
+            Some(p) => parent = p.parent.as_deref(),
+            None => return false,
+        }
+    }
+    false
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::commands::task::TaskBuilder;
+
+    #[test]
+    fn versions() {
+        assert_eq!(version_of(&json!([])), Ok(1));
+        assert_eq!(version_of(&json!({ "projects": [] })), Ok(1));
+        assert_eq!(version_of(&json!({ "version": 2, "projects": [] })), Ok(2));
+        assert!(version_of(&json!({ "version": "2" })).is_err());
+        assert!(version_of(&json!({ "version": 0 })).is_err());
+        assert!(version_of(&json!(3)).is_err());
+    }
+
+    #[test]
+    fn upgrade_old_versions() {
+        let projects = json!([{
+            "name": "proj",
+            "tasks": [{ "description": "task", "priority": "High", "deadline": null }],
+        }]);
+        let document = upgrade(projects.clone()).unwrap();
+        assert_eq!(document.version, CURRENT_VERSION);
+        assert_eq!(document.projects[0].tasks()[0].description, "task");
+
+        let toml_like = json!({ "projects": projects });
+        assert_eq!(upgrade(toml_like).unwrap(), document);
+        assert_eq!(
+            upgrade(serde_json::to_value(&document).unwrap()).unwrap(),
+            document
+        );
+    }
+
+    #[test]
+    fn upgrade_errors() {
+        assert_eq!(
+            upgrade(json!({ "version": CURRENT_VERSION + 1, "projects": [] })).unwrap_err(),
+            RustaskError::UnsupportedVersion(CURRENT_VERSION + 1, CURRENT_VERSION)
+        );
+        assert!(matches!(
+            upgrade(json!([{ "name": "proj", "tasks": [{ "priority": "Whenever" }] }])),
+            Err(RustaskError::FormatError(_))
+        ));
+    }
+
+    #[test]
+    fn validation() {
+        let mut proj = Project::new("proj".to_string());
+        let task = TaskBuilder::new("task".to_string()).build();
+        proj.push(task.clone());
+        assert_eq!(validate(&[proj.clone()]), Vec::<String>::new());
+
+        let mut bad = task.clone();
+        bad.id = "XYZ".to_string();
+        bad.description = " ".to_string();
+        proj.push(bad);
+        proj.push(task.clone());
+        let problems = validate(&[proj.clone(), proj]);
+        assert_eq!(
+            problems,
+            vec![
+                "task XYZ in proj has an id which is not 16 lowercase hexadecimal digits"
+                    .to_string(),
+                "task XYZ in proj has an empty description".to_string(),
+                format!("task id {} appears more than once in proj", task.id),
+                "project proj appears more than once".to_string(),
+                "task XYZ in proj has an id which is not 16 lowercase hexadecimal digits"
+                    .to_string(),
+                "task XYZ in proj has an empty description".to_string(),
+                format!("task id {} appears more than once in proj", task.id),
+            ]
+        );
+    }
+
+    #[test]
+    fn subtask_validation() {
+        let mut proj = Project::new("proj".to_string());
+        let mut first = TaskBuilder::new("first".to_string()).build();
+        let second = TaskBuilder::new("second".to_string())
+            .parent(first.id.clone())
+            .build();
+        proj.push(first.clone());
+        proj.push(second.clone());
+        assert_eq!(validate(&[proj]), Vec::<String>::new());
+
+        first.parent = Some(second.id.clone());
+        let mut orphan = TaskBuilder::new("orphan".to_string())
+            .parent("0000000000000000".to_string())
+            .build();
+        orphan.id = "ffffffffffffffff".to_string();
+        let mut proj = Project::new("proj".to_string());
+        proj.push(first.clone());
+        proj.push(second.clone());
+        proj.push(orphan);
+        let problems = validate(&[proj]);
+        assert_eq!(problems.len(), 3);
+        assert!(problems.contains(&format!("task {} in proj is a subtask of itself", first.id)));
+        assert!(problems.contains(
+            &"task ffffffffffffffff in proj is a subtask of 0000000000000000, which is not in proj"
+                .to_string()
+        ));
+    }
+
+    #[test]
+    fn dependency_validation() {
+        let first = TaskBuilder::new("first".to_string())
+            .depends_on("0000000000000000".to_string())
+            .build();
+        let mut second = TaskBuilder::new("second".to_string())
+            .depends_on(first.id.clone())
+            .build();
+        second.id = "ffffffffffffffff".to_string();
+        let mut a = Project::new("a".to_string());
+        a.push(first.clone());
+        let mut b = Project::new("b".to_string());
+        b.push(second.clone());
+        assert_eq!(
+            validate(&[a.clone(), b.clone()]),
+            vec![format!(
+                "task {} in a depends on 0000000000000000, which does not exist",
+                first.id
+            )]
+        );
+
+        a.edit(&first.id, |t| {
+            t.depends_on = [second.id.clone()].into();
+        })
+        .unwrap();
+        assert_eq!(
+            validate(&[a, b]),
+            vec![
+                format!(
+                    "task {} in a depends on itself: {} -> ffffffffffffffff -> {}",
+                    first.id, first.id, first.id
+                ),
+                format!(
+                    "task ffffffffffffffff in b depends on itself: ffffffffffffffff -> {} -> ffffffffffffffff",
+                    first.id
+                ),
+            ]
+        );
+    }
+
+    #[test]
+    fn time_validation() {
+        use crate::commands::task::parse_deadline;
+        use crate::commands::timesheet::Interval;
+
+        let at = |s| parse_deadline(s).unwrap();
+        let mut first = TaskBuilder::new("first".to_string()).build();
+        first.time = vec![
+            Interval::starting(at("2022-05-01 10:00")),
+            Interval {
+                start: at("2022-05-01 12:00"),
+                end: Some(at("2022-05-01 11:00")),
+            },
+        ];
+        let mut second = TaskBuilder::new("second".to_string()).build();
+        second.time = vec![Interval::starting(at("2022-05-02 10:00"))];
+        let mut proj = Project::new("proj".to_string());
+        proj.push(second.clone());
+        assert_eq!(validate(&[proj.clone()]), Vec::<String>::new());
+
+        proj.push(first.clone());
+        assert_eq!(
+            validate(&[proj]),
+            vec![
+                format!(
+                    "task {} in proj has time which ends before it starts",
+                    first.id
+                ),
+                format!(
+                    "task {} in proj has a timer running which is not its last time",
+                    first.id
+                ),
+                format!(
+                    "timers are running on several tasks: {}, {}",
+                    first.id, second.id
+                ),
+            ]
+        );
+    }
+}
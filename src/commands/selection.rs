@@ -0,0 +1,271 @@
+// selection.rs
+//
+// decide which tasks are listed
+
+use chrono::{Local, NaiveDate};
+use rand::rngs::StdRng;
+use rand::{thread_rng, Rng, SeedableRng};
+use serde::Deserialize;
+use std::fmt;
+
+use crate::commands::config;
+use crate::commands::task::{self, fnv1a, Priority, Task};
+
+/// Probability of listing a task of each priority, before accounting for its deadline
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Probabilities {
+    pub urgent: f64,
+    pub high: f64,
+    pub normal: f64,
+    pub low: f64,
+    pub note: f64,
+}
+
+impl Default for Probabilities {
+    fn default() -> Probabilities {
+        Probabilities {
+            urgent: 1.0,
+            high: 1.0,
+            normal: 1. / 3.,
+            low: 1. / 5.,
+            note: 1. / 8.,
+        }
+    }
+}
+
+impl Probabilities {
+    pub fn get(&self, priority: &Priority) -> f64 {
+        match priority {
+            Priority::Urgent => self.urgent,
+            Priority::High => self.high,
+            Priority::Normal => self.normal,
+            Priority::Low => self.low,
+            Priority::Note => self.note,
+        }
+    }
+
+    pub fn set(&mut self, priority: &Priority, probability: f64) {
+        let p = match priority {
+            Priority::Urgent => &mut self.urgent,
+            Priority::High => &mut self.high,
+            Priority::Normal => &mut self.normal,
+            Priority::Low => &mut self.low,
+            Priority::Note => &mut self.note,
+        };
+        *p = probability;
+    }
+}
+
+#[derive(thiserror::Error, Debug, PartialEq)]
+#[error("expected <priority>=<probability between 0 and 1>, got `{}`", .0)]
+pub struct ProbabilityParseError(String);
+
+/// Parse a `<priority>=<probability>` pair, e.g. `normal=0.5`
+pub fn parse_probability(s: &str) -> Result<(Priority, f64), ProbabilityParseError> {
+    let err = || ProbabilityParseError(s.to_string());
+    let (priority, probability) = s.split_once('=').ok_or_else(err)?;
+    let priority = priority.trim().parse::<Priority>().map_err(|_| err())?;
+    let probability = probability.trim().parse::<f64>().map_err(|_| err())?;
+    if !(0.0..=1.0).contains(&probability) {
+        return Err(err());
+    }
+    Ok((priority, probability))
+}
+
+/// How tasks are picked for listing
+#[derive(Debug, Clone, PartialEq)]
+pub struct Selection {
+    pub probabilities: Probabilities,
+    /// The priority of tasks without one
+    pub default_priority: Priority,
+    /// Tasks are drawn from a fresh random source if there is no seed
+    pub seed: Option<u64>,
+}
+
+impl Selection {
+    /// Pick tasks at random: every call may pick differently
+    pub fn random() -> Selection {
+        Selection {
+            probabilities: config::get().probabilities.clone(),
+            default_priority: task::default_priority().clone(),
+            seed: None,
+        }
+    }
+
+    /// Pick tasks reproducibly: the same seed always picks the same tasks
+    pub fn seeded(seed: u64) -> Selection {
+        Selection {
+            probabilities: config::get().probabilities.clone(),
+            default_priority: task::default_priority().clone(),
+            seed: Some(seed),
+        }
+    }
+
+    /// Pick tasks seeded by today's date, so the list is stable within a day
+    ///
+    /// As deadlines approach a task may show up during the day, but it never disappears
+    pub fn daily() -> Selection {
+        Selection::seeded(date_seed(Local::now().date_naive()))
+    }
+
+    pub fn probabilities(mut self, probabilities: Probabilities) -> Selection {
+        self.probabilities = probabilities;
+        self
+    }
+
+    pub fn default_priority(mut self, priority: Priority) -> Selection {
+        self.default_priority = priority;
+        self
+    }
+}
+
+/// Seed derived from a date
+pub fn date_seed(date: NaiveDate) -> u64 {
+    fnv1a(date.format("%F").to_string().bytes())
+}
+
+/// Why a task was (or was not) picked
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Choice {
+    pub chosen: bool,
+    /// Probability given by the priority of the task
+    pub priority: f64,
+    /// Probability added by a near deadline (see [`Task::deadline_near`])
+    pub deadline: f64,
+}
+
+impl Choice {
+    /// Probability of picking the task
+    pub fn probability(&self) -> f64 {
+        (self.priority + self.deadline).clamp(0.0, 1.0)
+    }
+}
+
+impl fmt::Display for Choice {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{:.0}% chance: {:.0}% priority + {:.0}% deadline",
+            self.probability() * 100.0,
+            self.priority * 100.0,
+            self.deadline * 100.0
+        )
+    }
+}
+
+impl Task {
+    /// Whether to choose this task or not, and why
+    ///
+    /// With a seeded selection, each task gets its own random draw derived from the seed
+    /// and its id, so the choice does not depend on the other tasks being listed.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use rustask::commands::selection::Selection;
+    /// use rustask::commands::task::*;
+    /// let task = TaskBuilder::new("task".to_string()).priority(Priority::Low).build();
+    /// let choice = task.choose_with(&Selection::seeded(42));
+    /// assert_eq!(choice.priority, 0.2);
+    /// assert_eq!(choice, task.choose_with(&Selection::seeded(42)));
+    /// ```
+    pub fn choose_with(&self, selection: &Selection) -> Choice {
+        let mut choice = Choice {
+            chosen: true,
+            priority: selection
+                .probabilities
+                .get(self.priority_or(&selection.default_priority)),
+            deadline: self.deadline_near(),
+        };
+
+        let probability = choice.probability();
+        if probability < 1.0 {
+            choice.chosen = match selection.seed {
+                Some(seed) => {
+                    StdRng::seed_from_u64(seed ^ fnv1a(self.id.bytes())).gen_bool(probability)
+                }
+                None => thread_rng().gen_bool(probability),
+            };
+        }
+        choice
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::commands::task::TaskBuilder;
+
+    fn tasks(priority: Priority) -> Vec<Task> {
+        (0..100)
+            .map(|n| {
+                TaskBuilder::new(format!("task{}", n))
+                    .priority(priority.clone())
+                    .build()
+            })
+            .collect()
+    }
+
+    #[test]
+    fn seeded_is_stable() {
+        let selection = Selection::seeded(date_seed(NaiveDate::from_ymd_opt(2022, 5, 1).unwrap()));
+        let tasks = tasks(Priority::Normal);
+        let first: Vec<bool> = tasks
+            .iter()
+            .map(|t| t.choose_with(&selection).chosen)
+            .collect();
+        let second: Vec<bool> = tasks
+            .iter()
+            .map(|t| t.choose_with(&selection).chosen)
+            .collect();
+
+        assert_eq!(first, second);
+        assert!(first.iter().any(|c| *c));
+        assert!(first.iter().any(|c| !*c));
+    }
+
+    #[test]
+    fn probabilities_are_configurable() {
+        let mut probabilities = Probabilities::default();
+        probabilities.set(&Priority::Note, 1.0);
+        probabilities.set(&Priority::Urgent, 0.0);
+        let selection = Selection::seeded(7).probabilities(probabilities);
+
+        assert!(tasks(Priority::Note)
+            .iter()
+            .all(|t| t.choose_with(&selection).chosen));
+        assert!(tasks(Priority::Urgent)
+            .iter()
+            .all(|t| !t.choose_with(&selection).chosen));
+    }
+
+    #[test]
+    fn deadline_contributes() {
+        let mut probabilities = Probabilities::default();
+        probabilities.set(&Priority::Low, 0.0);
+        let selection = Selection::seeded(7).probabilities(probabilities);
+        let task = TaskBuilder::new("overdue".to_string())
+            .priority(Priority::Low)
+            .deadline(crate::commands::task::now_deadline() - chrono::Duration::days(1))
+            .build();
+
+        let choice = task.choose_with(&selection);
+        assert_eq!(choice.priority, 0.0);
+        assert_eq!(choice.deadline, 1.0);
+        assert!(choice.chosen);
+        assert_eq!(
+            format!("{}", choice),
+            "100% chance: 0% priority + 100% deadline"
+        );
+    }
+
+    #[test]
+    fn parse() {
+        assert_eq!(parse_probability("normal=0.5"), Ok((Priority::Normal, 0.5)));
+        assert_eq!(parse_probability("Note = 1"), Ok((Priority::Note, 1.0)));
+        assert!(parse_probability("normal").is_err());
+        assert!(parse_probability("normal=2").is_err());
+        assert!(parse_probability("whatever=0.5").is_err());
+    }
+}
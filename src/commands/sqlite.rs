@@ -0,0 +1,736 @@
+// sqlite.rs
+//
+// store projects in an embedded SQLite database
+
+use rusqlite::types::Value;
+use rusqlite::{params, params_from_iter, Connection, OptionalExtension, Transaction};
+use std::collections::{BTreeMap, BTreeSet};
+use std::path::{Path, PathBuf};
+
+use crate::commands::backend::Storage;
+use crate::commands::change::Change;
+use crate::commands::error::RustaskError;
+use crate::commands::filter::Scope;
+use crate::commands::project::{self, Progress, Project, Summary};
+use crate::commands::repair::Salvage;
+use crate::commands::task::{self, Task};
+
+/// Every task is kept whole, as JSON, in `data`; the other columns of `tasks` are copies
+/// of its fields, to query on.
+/// Tasks are looked up by project through the primary key.
+const SCHEMA: &str = "
+    PRAGMA foreign_keys = ON;
+    CREATE TABLE IF NOT EXISTS projects (
+        name TEXT PRIMARY KEY NOT NULL
+    );
+    CREATE TABLE IF NOT EXISTS tasks (
+        project TEXT NOT NULL REFERENCES projects (name)
+            ON UPDATE CASCADE ON DELETE CASCADE,
+        id TEXT NOT NULL,
+        -- 0 (urgent) to 4 (note), none for tasks without a priority
+        priority INTEGER,
+        -- unix timestamps
+        deadline INTEGER,
+        completed INTEGER,
+        -- the id of the task this one is a subtask of, in the same project
+        parent TEXT,
+        data TEXT NOT NULL,
+        PRIMARY KEY (project, id)
+    );
+    CREATE INDEX IF NOT EXISTS tasks_pending ON tasks (completed, project);
+    CREATE INDEX IF NOT EXISTS tasks_priority ON tasks (priority);
+    CREATE INDEX IF NOT EXISTS tasks_deadline ON tasks (deadline);
+";
+
+/// The version of the schema, kept in `PRAGMA user_version`
+///
+/// 1. tasks without a priority have none in `priority`, no indexes (`user_version` unset)
+/// 2. the indexes of `SCHEMA`
+/// 3. the `parent` of subtasks, with an index of its own
+/// 4. tasks may wait on others, kept in `data` alone
+/// 5. tasks may have an estimate and the time spent on them, kept in `data` alone
+/// 6. tasks may have annotations, kept in `data` alone
+/// 7. tasks without a priority have none in `priority` again, as the default is configured
+const SCHEMA_VERSION: u32 = 7;
+
+pub struct SqliteStorage {
+    path: PathBuf,
+}
+
+impl SqliteStorage {
+    pub fn new(path: &Path) -> SqliteStorage {
+        SqliteStorage {
+            path: path.to_path_buf(),
+        }
+    }
+
+    /// Open the database, creating or upgrading its schema as needed
+    fn connect(&self) -> Result<Connection, RustaskError> {
+        let conn = Connection::open(&self.path)?;
+        let version = user_version(&conn)?;
+        if version > SCHEMA_VERSION {
+            return Err(RustaskError::UnsupportedVersion(version, SCHEMA_VERSION));
+        }
+
+        conn.execute_batch(SCHEMA)?;
+        if version < SCHEMA_VERSION {
+            // tables created before subtasks have no parent column, and no task is one
+            let has_parent: bool = conn.query_row(
+                "SELECT COUNT(*) > 0 FROM pragma_table_info('tasks') WHERE name = 'parent'",
+                [],
+                |row| row.get(0),
+            )?;
+            if !has_parent {
+                conn.execute_batch("ALTER TABLE tasks ADD COLUMN parent TEXT")?;
+            }
+            // from versions 2 to 6, tasks without a priority were kept as normal ones
+            let priority_required: bool = conn.query_row(
+                "SELECT COUNT(*) > 0 FROM pragma_table_info('tasks')
+                 WHERE name = 'priority' AND \"notnull\"",
+                [],
+                |row| row.get(0),
+            )?;
+            if priority_required {
+                // recreate the table as in the schema; its indexes go with the old one
+                conn.execute_batch(&format!(
+                    "BEGIN;
+                     DROP INDEX IF EXISTS tasks_pending;
+                     DROP INDEX IF EXISTS tasks_priority;
+                     DROP INDEX IF EXISTS tasks_deadline;
+                     DROP INDEX IF EXISTS tasks_parent;
+                     ALTER TABLE tasks RENAME TO old_tasks;
+                     {}
+                     INSERT INTO tasks (project, id, priority, deadline, completed, parent, data)
+                         SELECT project, id, priority, deadline, completed, parent, data
+                         FROM old_tasks;
+                     DROP TABLE old_tasks;
+                     COMMIT;",
+                    SCHEMA
+                ))?;
+            }
+            conn.execute_batch(
+                "UPDATE tasks SET priority = NULL WHERE json_extract(data, '$.priority') IS NULL",
+            )?;
+            conn.execute_batch(
+                "CREATE INDEX IF NOT EXISTS tasks_parent ON tasks (project, parent)",
+            )?;
+            conn.execute_batch(&format!("PRAGMA user_version = {}", SCHEMA_VERSION))?;
+        }
+        Ok(conn)
+    }
+}
+
+fn user_version(conn: &Connection) -> Result<u32, RustaskError> {
+    Ok(conn.query_row("PRAGMA user_version", [], |row| row.get(0))?)
+}
+
+fn insert_project(tx: &Transaction, name: &str) -> Result<usize, RustaskError> {
+    Ok(tx.execute(
+        "INSERT INTO projects (name) VALUES (?1) ON CONFLICT DO NOTHING",
+        params![name],
+    )?)
+}
+
+fn insert_task(tx: &Transaction, project: &str, task: &Task) -> Result<usize, RustaskError> {
+    Ok(tx.execute(
+        "INSERT INTO tasks (project, id, priority, deadline, completed, parent, data)
+         VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7) ON CONFLICT DO NOTHING",
+        params![
+            project,
+            task.id,
+            task.priority.clone().map(|p| p as u8),
+            task.deadline.map(|d| d.timestamp()),
+            task.completed.map(|c| c.timestamp()),
+            task.parent,
+            serde_json::to_string(task)?,
+        ],
+    )?)
+}
+
+fn update_task(
+    tx: &Transaction,
+    project: &str,
+    id: &str,
+    task: &Task,
+) -> Result<usize, RustaskError> {
+    Ok(tx.execute(
+        "UPDATE tasks
+         SET id = ?3, priority = ?4, deadline = ?5, completed = ?6, parent = ?7, data = ?8
+         WHERE project = ?1 AND id = ?2",
+        params![
+            project,
+            id,
+            task.id,
+            task.priority.clone().map(|p| p as u8),
+            task.deadline.map(|d| d.timestamp()),
+            task.completed.map(|c| c.timestamp()),
+            task.parent,
+            serde_json::to_string(task)?,
+        ],
+    )?)
+}
+
+/// Write a single change, touching only the rows it names
+///
+/// The rows are not compared with what the change replaces, only checked to exist
+/// (or not to): under the exclusive lock nothing else writes to the database.
+fn apply_change(tx: &Transaction, change: &Change) -> Result<(), RustaskError> {
+    let changed = match change {
+        Change::Project {
+            before: None,
+            after: None,
+        } => return Ok(()),
+        Change::Project {
+            before: None,
+            after: Some(name),
+        } => insert_project(tx, name)?,
+        Change::Project {
+            before: Some(name),
+            after: None,
+        } => tx.execute(
+            "DELETE FROM projects
+             WHERE name = ?1 AND NOT EXISTS (SELECT 1 FROM tasks WHERE project = ?1)",
+            params![name],
+        )?,
+        Change::Project {
+            before: Some(old),
+            after: Some(new),
+        } => tx.execute(
+            "UPDATE OR IGNORE projects SET name = ?2 WHERE name = ?1",
+            params![old, new],
+        )?,
+        Change::Task {
+            before: None,
+            after: None,
+            ..
+        } => return Ok(()),
+        Change::Task {
+            project,
+            before: None,
+            after: Some(task),
+        } => insert_task(tx, project, task)?,
+        Change::Task {
+            project,
+            before: Some(task),
+            after: None,
+        } => tx.execute(
+            "DELETE FROM tasks WHERE project = ?1 AND id = ?2",
+            params![project, task.id],
+        )?,
+        Change::Task {
+            project,
+            before: Some(old),
+            after: Some(new),
+        } => update_task(tx, project, &old.id, new)?,
+    };
+
+    if changed == 1 {
+        Ok(())
+    } else {
+        Err(RustaskError::ChangeConflict(change.projects().join(", ")))
+    }
+}
+
+impl Storage for SqliteStorage {
+    fn load(&self) -> Result<Vec<Project>, RustaskError> {
+        let conn = self.connect()?;
+        let mut projects: BTreeMap<String, Project> = BTreeMap::new();
+
+        let mut names = conn.prepare("SELECT name FROM projects")?;
+        for name in names.query_map([], |row| row.get::<_, String>(0))? {
+            let name = name?;
+            projects.insert(name.clone(), Project::new(name));
+        }
+
+        let mut tasks = conn.prepare("SELECT project, data FROM tasks")?;
+        let rows = tasks.query_map([], |row| {
+            Ok((row.get::<_, String>(0)?, row.get::<_, String>(1)?))
+        })?;
+        for row in rows {
+            let (project, data) = row?;
+            let task: Task = serde_json::from_str(&data)?;
+            projects
+                .entry(project.clone())
+                .or_insert_with(|| Project::new(project))
+                .push(task);
+        }
+
+        let mut projects: Vec<Project> = projects.into_values().collect();
+        projects.sort();
+        Ok(projects)
+    }
+
+    fn store(&self, projects: &[Project]) -> Result<(), RustaskError> {
+        let mut conn = self.connect()?;
+        let tx = conn.transaction()?;
+        tx.execute("DELETE FROM tasks", [])?;
+        tx.execute("DELETE FROM projects", [])?;
+        for project in projects {
+            insert_project(&tx, &project.name)?;
+            for task in project.tasks() {
+                insert_task(&tx, &project.name, task)?;
+            }
+        }
+        tx.commit()?;
+        Ok(())
+    }
+
+    fn version(&self) -> Result<u32, RustaskError> {
+        // connecting would upgrade the schema
+        let conn = Connection::open(&self.path)?;
+        let tables: usize = conn.query_row(
+            "SELECT COUNT(*) FROM sqlite_master WHERE type = 'table' AND name = 'tasks'",
+            [],
+            |row| row.get(0),
+        )?;
+        Ok(match user_version(&conn)? {
+            0 if tables == 0 => SCHEMA_VERSION,
+            0 => 1,
+            version => version,
+        })
+    }
+
+    fn current_version(&self) -> u32 {
+        SCHEMA_VERSION
+    }
+
+    fn salvage(&self) -> Result<Salvage, RustaskError> {
+        let conn = self.connect()?;
+        let mut salvage = Salvage::default();
+        let mut projects: BTreeMap<String, Project> = BTreeMap::new();
+
+        let mut names = conn.prepare("SELECT name FROM projects")?;
+        for name in names.query_map([], |row| row.get::<_, String>(0))? {
+            let name = name?;
+            projects.insert(name.clone(), Project::new(name));
+        }
+
+        let mut tasks = conn.prepare("SELECT project, id, data FROM tasks")?;
+        let rows = tasks.query_map([], |row| {
+            Ok((
+                row.get::<_, String>(0)?,
+                row.get::<_, String>(1)?,
+                row.get::<_, String>(2)?,
+            ))
+        })?;
+        for row in rows {
+            let (project, id, data) = row?;
+            match serde_json::from_str::<Task>(&data) {
+                Ok(task) => projects
+                    .entry(project.clone())
+                    .or_insert_with(|| Project::new(project))
+                    .push(task),
+                Err(e) => salvage
+                    .dropped
+                    .push(format!("task {} of {}: {}", id, project, e)),
+            }
+        }
+
+        salvage.projects = projects.into_values().collect();
+        salvage.projects.sort();
+        Ok(salvage)
+    }
+
+    fn load_project(&self, name: &str) -> Result<Option<Project>, RustaskError> {
+        let conn = self.connect()?;
+        let exists = conn
+            .query_row(
+                "SELECT 1 FROM projects WHERE name = ?1",
+                params![name],
+                |_| Ok(()),
+            )
+            .optional()?;
+        if exists.is_none() {
+            return Ok(None);
+        }
+
+        let mut project = Project::new(name.to_string());
+        let mut tasks = conn.prepare("SELECT data FROM tasks WHERE project = ?1")?;
+        for data in tasks.query_map(params![name], |row| row.get::<_, String>(0))? {
+            project.push(serde_json::from_str(&data?)?);
+        }
+        Ok(Some(project))
+    }
+
+    fn query(&self, scope: &Scope) -> Result<Vec<(Summary, Vec<Task>)>, RustaskError> {
+        let conn = self.connect()?;
+        let project = scope.project.clone().map(Value::from);
+        let in_project = |column: &str| match &project {
+            Some(_) => format!("{} = ?1", column),
+            None => "?1 IS NULL".to_string(),
+        };
+
+        let mut projects: BTreeMap<String, (Summary, Vec<String>, Vec<Task>)> = BTreeMap::new();
+        let mut names = conn.prepare(&format!(
+            "SELECT name FROM projects WHERE {}",
+            in_project("name")
+        ))?;
+        for name in names.query_map([&project], |row| row.get::<_, String>(0))? {
+            let name = name?;
+            let summary = Summary {
+                name: name.clone(),
+                pending: 0,
+                id_prefix_len: 0,
+            };
+            projects.insert(name, (summary, vec![], vec![]));
+        }
+
+        // the summaries only need the primary key and the pending index
+        let mut ids = conn.prepare(&format!(
+            "SELECT project, id FROM tasks WHERE {}",
+            in_project("project")
+        ))?;
+        let rows = ids.query_map([&project], |row| {
+            Ok((row.get::<_, String>(0)?, row.get::<_, String>(1)?))
+        })?;
+        for row in rows {
+            let (name, id) = row?;
+            if let Some((_, ids, _)) = projects.get_mut(&name) {
+                ids.push(id);
+            }
+        }
+        let mut pending = conn.prepare(&format!(
+            "SELECT project, COUNT(*) FROM tasks WHERE completed IS NULL AND {} GROUP BY project",
+            in_project("project")
+        ))?;
+        let rows = pending.query_map([&project], |row| {
+            Ok((row.get::<_, String>(0)?, row.get::<_, usize>(1)?))
+        })?;
+        for row in rows {
+            let (name, count) = row?;
+            if let Some((summary, _, _)) = projects.get_mut(&name) {
+                summary.pending = count;
+            }
+        }
+
+        let mut conditions = vec![in_project("project")];
+        let mut values = vec![project.clone().unwrap_or(Value::Null)];
+        if !scope.include_completed {
+            conditions.push("completed IS NULL".to_string());
+        }
+        if let Some(priority) = &scope.priority {
+            values.push(Value::from(task::default_priority().clone() as u8));
+            values.push(Value::from(priority.clone() as u8));
+            conditions.push(format!(
+                "COALESCE(priority, ?{}) <= ?{}",
+                values.len() - 1,
+                values.len()
+            ));
+        }
+        // timestamps are rounded down to the second, which keeps the bounds inclusive
+        if let Some(after) = scope.due_after {
+            values.push(Value::from(after.timestamp()));
+            conditions.push(format!("deadline >= ?{}", values.len()));
+        }
+        if let Some(before) = scope.due_before {
+            values.push(Value::from(before.timestamp()));
+            conditions.push(format!("deadline <= ?{}", values.len()));
+        }
+        let mut tasks = conn.prepare(&format!(
+            "SELECT project, data FROM tasks WHERE {}",
+            conditions.join(" AND ")
+        ))?;
+        let rows = tasks.query_map(params_from_iter(values.iter()), |row| {
+            Ok((row.get::<_, String>(0)?, row.get::<_, String>(1)?))
+        })?;
+        for row in rows {
+            let (name, data) = row?;
+            if let Some((_, _, tasks)) = projects.get_mut(&name) {
+                tasks.push(serde_json::from_str(&data)?);
+            }
+        }
+
+        Ok(projects
+            .into_values()
+            .map(|(mut summary, ids, mut tasks)| {
+                summary.id_prefix_len = project::id_prefix_len(ids.iter().map(String::as_str));
+                tasks.sort();
+                (summary, tasks)
+            })
+            .collect())
+    }
+
+    fn progress(
+        &self,
+        project: Option<&str>,
+    ) -> Result<BTreeMap<String, BTreeMap<String, Progress>>, RustaskError> {
+        let conn = self.connect()?;
+        let mut statement = conn.prepare(
+            "SELECT project, parent, COUNT(*), COUNT(completed) FROM tasks
+             WHERE parent IS NOT NULL AND (?1 IS NULL OR project = ?1)
+             GROUP BY project, parent",
+        )?;
+        let rows = statement.query_map(params![project], |row| {
+            Ok((
+                row.get::<_, String>(0)?,
+                row.get::<_, String>(1)?,
+                Progress {
+                    total: row.get(2)?,
+                    done: row.get(3)?,
+                },
+            ))
+        })?;
+
+        let mut progress: BTreeMap<String, BTreeMap<String, Progress>> = BTreeMap::new();
+        for row in rows {
+            let (name, parent, counts) = row?;
+            progress.entry(name).or_default().insert(parent, counts);
+        }
+        Ok(progress)
+    }
+
+    fn pending(&self, ids: &BTreeSet<String>) -> Result<BTreeSet<String>, RustaskError> {
+        let conn = self.connect()?;
+        let mut statement = conn.prepare(
+            "SELECT id FROM tasks
+             WHERE completed IS NULL AND id IN (SELECT value FROM json_each(?1))",
+        )?;
+        let rows = statement.query_map(params![serde_json::to_string(ids)?], |row| {
+            row.get::<_, String>(0)
+        })?;
+        Ok(rows.collect::<Result<_, _>>()?)
+    }
+
+    fn apply(&self, changes: &[Change]) -> Result<(), RustaskError> {
+        let mut conn = self.connect()?;
+        let tx = conn.transaction()?;
+        for change in changes {
+            apply_change(&tx, change)?;
+        }
+        tx.commit()?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::commands::task::{now_deadline, Priority, TaskBuilder};
+    use std::fs;
+
+    fn task(description: &str, priority: Priority) -> Task {
+        TaskBuilder::new(description.to_string())
+            .priority(priority)
+            .build()
+    }
+
+    #[test]
+    fn apply_changes() {
+        let path = Path::new("test_file_sqlite_apply.db");
+        let storage = SqliteStorage::new(path);
+        let first = task("first", Priority::High);
+        let second = task("second", Priority::Low);
+        storage
+            .apply(&[
+                Change::Project {
+                    before: None,
+                    after: Some("proj".to_string()),
+                },
+                Change::Task {
+                    project: "proj".to_string(),
+                    before: None,
+                    after: Some(first.clone()),
+                },
+                Change::Task {
+                    project: "proj".to_string(),
+                    before: None,
+                    after: Some(second.clone()),
+                },
+            ])
+            .unwrap();
+
+        let mut done = first.clone();
+        done.completed = Some(now_deadline());
+        storage
+            .apply(&[
+                Change::Task {
+                    project: "proj".to_string(),
+                    before: Some(first.clone()),
+                    after: Some(done.clone()),
+                },
+                Change::Project {
+                    before: Some("proj".to_string()),
+                    after: Some("renamed".to_string()),
+                },
+            ])
+            .unwrap();
+        let project = storage.load_project("renamed").unwrap().unwrap();
+        assert_eq!(project.tasks(), &vec![done, second.clone()]);
+        assert_eq!(storage.load_project("proj").unwrap(), None);
+
+        // nothing is written when one of the changes does not apply
+        let before = storage.load().unwrap();
+        assert_eq!(
+            storage
+                .apply(&[
+                    Change::Task {
+                        project: "renamed".to_string(),
+                        before: Some(second.clone()),
+                        after: None,
+                    },
+                    Change::Task {
+                        project: "renamed".to_string(),
+                        before: Some(second),
+                        after: None,
+                    },
+                ])
+                .unwrap_err(),
+            RustaskError::ChangeConflict("renamed".to_string())
+        );
+        assert_eq!(storage.load().unwrap(), before);
+        fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn query_scope() {
+        let path = Path::new("test_file_sqlite_query.db");
+        let storage = SqliteStorage::new(path);
+        let urgent = task("urgent", Priority::Urgent);
+        let low = task("low", Priority::Low);
+        let mut done = task("done", Priority::Urgent);
+        done.completed = Some(now_deadline());
+        let mut a = Project::new("a".to_string());
+        a.push(urgent.clone());
+        a.push(low.clone());
+        a.push(done.clone());
+        storage
+            .store(&[a.clone(), Project::new("b".to_string())])
+            .unwrap();
+
+        let listed = storage.query(&Scope::default()).unwrap();
+        assert_eq!(listed.len(), 2);
+        assert_eq!(listed[0].0, a.summary());
+        assert_eq!(listed[0].1, vec![urgent.clone(), low]);
+        assert_eq!(listed[1].1, vec![]);
+
+        let scope = Scope {
+            project: Some("a".to_string()),
+            include_completed: true,
+            priority: Some(Priority::High),
+            ..Scope::default()
+        };
+        let listed = storage.query(&scope).unwrap();
+        assert_eq!(listed.len(), 1);
+        assert_eq!(listed[0].0.pending, 2);
+        assert_eq!(listed[0].1, vec![done, urgent]);
+        fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn subtasks() {
+        let path = Path::new("test_file_sqlite_subtasks.db");
+        // a database from before subtasks
+        Connection::open(path)
+            .unwrap()
+            .execute_batch(
+                "CREATE TABLE projects (name TEXT PRIMARY KEY NOT NULL);
+                 CREATE TABLE tasks (
+                     project TEXT NOT NULL REFERENCES projects (name)
+                         ON UPDATE CASCADE ON DELETE CASCADE,
+                     id TEXT NOT NULL,
+                     priority INTEGER NOT NULL,
+                     deadline INTEGER,
+                     completed INTEGER,
+                     data TEXT NOT NULL,
+                     PRIMARY KEY (project, id)
+                 );
+                 PRAGMA user_version = 2;",
+            )
+            .unwrap();
+        let storage = SqliteStorage::new(path);
+        assert_eq!(storage.version().unwrap(), 2);
+
+        let parent = task("parent", Priority::High);
+        let mut done = TaskBuilder::new("done".to_string())
+            .parent(parent.id.clone())
+            .build();
+        done.completed = Some(now_deadline());
+        let pending = TaskBuilder::new("pending".to_string())
+            .parent(parent.id.clone())
+            .build();
+        let mut a = Project::new("a".to_string());
+        for t in [&parent, &done, &pending] {
+            a.push(t.clone());
+        }
+        storage
+            .store(&[a.clone(), Project::new("b".to_string())])
+            .unwrap();
+        assert_eq!(storage.version().unwrap(), SCHEMA_VERSION);
+        assert_eq!(storage.load().unwrap()[0], a);
+
+        let progress = storage.progress(None).unwrap();
+        assert_eq!(progress.len(), 1);
+        assert_eq!(progress["a"][&parent.id], Progress { done: 1, total: 2 });
+        assert_eq!(storage.progress(Some("b")).unwrap().len(), 0);
+
+        let ids = [parent.id.clone(), done.id.clone(), "nope".to_string()].into();
+        assert_eq!(storage.pending(&ids).unwrap(), [parent.id.clone()].into());
+        fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn default_priority() {
+        let path = Path::new("test_file_sqlite_default_priority.db");
+        // a database which kept tasks without a priority as normal ones
+        let unset = TaskBuilder::new("unset".to_string()).build();
+        let conn = Connection::open(path).unwrap();
+        conn.execute_batch(
+            "CREATE TABLE projects (name TEXT PRIMARY KEY NOT NULL);
+             CREATE TABLE tasks (
+                 project TEXT NOT NULL REFERENCES projects (name)
+                     ON UPDATE CASCADE ON DELETE CASCADE,
+                 id TEXT NOT NULL,
+                 priority INTEGER NOT NULL,
+                 deadline INTEGER,
+                 completed INTEGER,
+                 parent TEXT,
+                 data TEXT NOT NULL,
+                 PRIMARY KEY (project, id)
+             );
+             CREATE INDEX tasks_priority ON tasks (priority);
+             INSERT INTO projects (name) VALUES ('a');
+             PRAGMA user_version = 6;",
+        )
+        .unwrap();
+        conn.execute(
+            "INSERT INTO tasks (project, id, priority, data) VALUES ('a', ?1, 2, ?2)",
+            params![unset.id, serde_json::to_string(&unset).unwrap()],
+        )
+        .unwrap();
+        drop(conn);
+
+        let storage = SqliteStorage::new(path);
+        let high = task("high", Priority::High);
+        storage
+            .apply(&[Change::Task {
+                project: "a".to_string(),
+                before: None,
+                after: Some(high.clone()),
+            }])
+            .unwrap();
+        assert_eq!(storage.version().unwrap(), SCHEMA_VERSION);
+        let priority: Option<u8> = Connection::open(path)
+            .unwrap()
+            .query_row(
+                "SELECT priority FROM tasks WHERE id = ?1",
+                [&unset.id],
+                |row| row.get(0),
+            )
+            .unwrap();
+        assert_eq!(priority, None);
+
+        let scope = |priority| Scope {
+            priority: Some(priority),
+            ..Scope::default()
+        };
+        assert_eq!(
+            storage.query(&scope(Priority::Normal)).unwrap()[0].1,
+            vec![high.clone(), unset]
+        );
+        assert_eq!(
+            storage.query(&scope(Priority::High)).unwrap()[0].1,
+            vec![high]
+        );
+        fs::remove_file(path).unwrap();
+    }
+}
@@ -1,32 +1,241 @@
 // storage.rs
 //
-// store a list of tasks in a file
+// write files safely and lock them
 
-use std::{fs, path::Path};
+use std::{
+    ffi::OsString,
+    fs,
+    io::{self, BufWriter, Write},
+    path::{Path, PathBuf},
+    thread,
+    time::{Duration, Instant},
+};
 
-use crate::commands::error::RustaskError;
-use crate::commands::project::Project;
-use serde_json;
+use crate::commands::error::{FileError, RustaskError};
+use crate::commands::journal::{self, Journal};
 
-pub fn store_tasks(path: &Path, tasks: &Vec<Project>) -> Result<(), RustaskError> {
-    let f = fs::File::create(path)?;
-    serde_json::to_writer(f, tasks).unwrap();
+/// Number of previous versions kept next to the task file (`<file>.bak.1` is the newest)
+const BACKUP_COUNT: usize = 3;
+
+/// `path` with `suffix` appended to its file name
+fn with_suffix(path: &Path, suffix: &str) -> PathBuf {
+    let mut name = OsString::from(path.as_os_str());
+    name.push(suffix);
+    PathBuf::from(name)
+}
+
+/// An IO error on the file at `path`, with a suggestion for the usual causes
+pub fn io_error(path: &Path, operation: &str, error: io::Error) -> RustaskError {
+    let suggestion = match error.kind() {
+        io::ErrorKind::NotFound => Some(format!(
+            "check that `{}` and its directory exist",
+            path.display()
+        )),
+        io::ErrorKind::PermissionDenied => Some(format!(
+            "check the permissions of `{}` and of its directory",
+            path.display()
+        )),
+        _ => None,
+    };
+    RustaskError::FileError(FileError {
+        path: path.display().to_string(),
+        operation: operation.to_string(),
+        position: None,
+        message: error.to_string(),
+        suggestion,
+    })
+}
+
+/// Give the IO errors of `result`, on the file at `path`, the context of the operation
+fn in_context<T>(
+    path: &Path,
+    operation: &str,
+    result: Result<T, RustaskError>,
+) -> Result<T, RustaskError> {
+    result.map_err(|e| match e {
+        RustaskError::IOError(e) => io_error(path, operation, e),
+        e => e,
+    })
+}
+
+pub fn backup_path(path: &Path, n: usize) -> PathBuf {
+    with_suffix(path, &format!(".bak.{}", n))
+}
+
+/// Shift the backups by one and make the current task file the newest backup
+fn rotate_backups(path: &Path) -> Result<(), RustaskError> {
+    if !path.exists() {
+        return Ok(());
+    }
+
+    for n in (1..BACKUP_COUNT).rev() {
+        let older = backup_path(path, n);
+        if older.exists() {
+            fs::rename(&older, backup_path(path, n + 1))?;
+        }
+    }
+
+    // a hard link keeps the current contents alive once the new file is renamed over it;
+    // fall back to a copy on filesystems without links
+    let newest = backup_path(path, 1);
+    if newest.exists() {
+        fs::remove_file(&newest)?;
+    }
+    if fs::hard_link(path, &newest).is_err() {
+        fs::copy(path, &newest)?;
+    }
     Ok(())
 }
 
-pub fn load_tasks(path: &Path) -> Result<Vec<Project>, RustaskError> {
-    let f = fs::File::open(path)?;
-    let mut v: Vec<Project> = serde_json::from_reader(f)?;
-    v.sort();
-    Ok(v)
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LockMode {
+    /// Many readers may hold the lock at once
+    Shared,
+    /// A single writer holds the lock
+    Exclusive,
+}
+
+/// Advisory lock over a task file, released when dropped
+///
+/// The lock is taken on a `<file>.lock` sidecar rather than on the task file itself,
+/// because storing renames a new file over the task file.
+#[derive(Debug)]
+pub struct FileLock {
+    _file: fs::File,
+}
+
+/// Lock the task file at `path`, waiting up to `timeout` for other holders to release it
+pub fn lock(path: &Path, mode: LockMode, timeout: Duration) -> Result<FileLock, RustaskError> {
+    const MAX_BACKOFF: Duration = Duration::from_millis(100);
+
+    let lock_path = with_suffix(path, ".lock");
+    let file = fs::OpenOptions::new()
+        .create(true)
+        .truncate(false)
+        .write(true)
+        .open(&lock_path)
+        .map_err(|e| io_error(&lock_path, "lock", e))?;
+
+    let start = Instant::now();
+    let mut backoff = Duration::from_millis(1);
+    loop {
+        let attempt = match mode {
+            LockMode::Shared => file.try_lock_shared(),
+            LockMode::Exclusive => file.try_lock(),
+        };
+        match attempt {
+            Ok(()) => return Ok(FileLock { _file: file }),
+            Err(fs::TryLockError::Error(e)) => return Err(io_error(&lock_path, "lock", e)),
+            Err(fs::TryLockError::WouldBlock) => {}
+        }
+
+        let elapsed = start.elapsed();
+        if elapsed >= timeout {
+            return Err(RustaskError::LockTimeout(
+                path.display().to_string(),
+                timeout,
+            ));
+        }
+        thread::sleep(backoff.min(timeout - elapsed));
+        backoff = (backoff * 2).min(MAX_BACKOFF);
+    }
+}
+
+/// Make a rename in `dir` durable
+#[cfg(unix)]
+fn sync_dir(dir: &Path) -> Result<(), RustaskError> {
+    fs::File::open(dir)?.sync_all()?;
+    Ok(())
+}
+
+#[cfg(not(unix))]
+fn sync_dir(_dir: &Path) -> Result<(), RustaskError> {
+    Ok(())
+}
+
+fn write_synced(path: &Path, contents: &[u8]) -> Result<(), RustaskError> {
+    let mut writer = BufWriter::new(fs::File::create(path)?);
+    writer.write_all(contents)?;
+    writer.flush()?;
+    writer.get_ref().sync_all()?;
+    Ok(())
+}
+
+/// Replace the file at `path` with `contents`
+///
+/// The contents are written to a temporary file in the same directory, which is synced
+/// and then renamed over `path`: a crash leaves either the old or the new file,
+/// never a truncated one.
+/// With `backup`, the previous versions of the file are kept next to it.
+pub fn store_atomic(path: &Path, contents: &[u8], backup: bool) -> Result<(), RustaskError> {
+    let tmp_path = with_suffix(path, &format!(".tmp.{}", std::process::id()));
+    if let Err(e) = write_synced(&tmp_path, contents) {
+        let _ = fs::remove_file(&tmp_path);
+        return in_context(&tmp_path, "write", Err(e));
+    }
+
+    if backup {
+        in_context(path, "back up", rotate_backups(path))?;
+    }
+    fs::rename(&tmp_path, path).map_err(|e| io_error(path, "replace", e))?;
+
+    match path.parent() {
+        Some(dir) if !dir.as_os_str().is_empty() => in_context(dir, "sync", sync_dir(dir)),
+        _ => sync_dir(Path::new(".")),
+    }
+}
+
+/// The journal of the task file at `path` is kept in `<file>.journal`
+fn journal_path(path: &Path) -> PathBuf {
+    with_suffix(path, ".journal")
+}
+
+/// Store the operation journal of the task file at `path`
+pub fn store_journal(path: &Path, journal: &Journal) -> Result<(), RustaskError> {
+    store_atomic(&journal_path(path), &serde_json::to_vec(journal)?, false)
+}
+
+/// Load the operation journal of the task file at `path` (empty if there is none yet)
+///
+/// Journals from earlier versions, which kept whole copies of the task file, cannot be
+/// replayed: they are dropped, and the next operation starts a new journal.
+pub fn load_journal(path: &Path) -> Result<Journal, RustaskError> {
+    let path = journal_path(path);
+    match fs::read(&path) {
+        Ok(contents) => match serde_json::from_slice(&contents) {
+            Err(e) if e.is_data() && journal::is_legacy(&contents) => Ok(Journal::default()),
+            Err(e) => Err(RustaskError::FileError(FileError {
+                path: path.display().to_string(),
+                operation: "read the journal".to_string(),
+                position: Some((e.line(), e.column())),
+                message: e.to_string(),
+                suggestion: Some(format!(
+                    "remove `{}` to start a new journal, losing the undo history",
+                    path.display()
+                )),
+            })),
+            Ok(journal) => Ok(journal),
+        },
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(Journal::default()),
+        Err(e) => Err(io_error(&path, "read the journal", e)),
+    }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
-    use crate::commands::project;
+    use crate::commands::backend::Backend;
+    use crate::commands::project::{self, Project};
     use crate::commands::task;
     use std::path::Path;
+
+    fn store_tasks(path: &Path, tasks: &[Project]) -> Result<(), RustaskError> {
+        Backend::Json.open(path).store(tasks)
+    }
+
+    fn load_tasks(path: &Path) -> Result<Vec<Project>, RustaskError> {
+        Backend::Json.open(path).load()
+    }
     #[test]
     fn store_load() {
         let path = Path::new("test_file");
@@ -71,10 +280,105 @@ mod tests {
         );
         p[1].push(task::TaskBuilder::new("default".to_string()).build());
 
-        store_tasks(&path, &p).unwrap();
-        let new_p = load_tasks(&path).unwrap();
+        store_tasks(path, &p).unwrap();
+        let new_p = load_tasks(path).unwrap();
 
         assert_eq!(p, new_p);
         fs::remove_file(path).unwrap();
     }
+
+    #[test]
+    fn store_keeps_backups() {
+        let path = Path::new("test_file_backups");
+        let snapshots: Vec<Vec<project::Project>> = (0..=BACKUP_COUNT + 1)
+            .map(|n| vec![project::Project::new(format!("proj{}", n))])
+            .collect();
+
+        for snapshot in &snapshots {
+            store_tasks(path, snapshot).unwrap();
+        }
+
+        assert_eq!(load_tasks(path).unwrap(), snapshots[BACKUP_COUNT + 1]);
+        for n in 1..=BACKUP_COUNT {
+            let backup = load_tasks(&backup_path(path, n)).unwrap();
+            assert_eq!(backup, snapshots[BACKUP_COUNT + 1 - n]);
+        }
+        assert!(!backup_path(path, BACKUP_COUNT + 1).exists());
+        assert!(!with_suffix(path, &format!(".tmp.{}", std::process::id())).exists());
+
+        fs::remove_file(path).unwrap();
+        for n in 1..=BACKUP_COUNT {
+            fs::remove_file(backup_path(path, n)).unwrap();
+        }
+    }
+
+    #[test]
+    fn lock_modes() {
+        let path = Path::new("test_file_lock");
+        let timeout = Duration::from_millis(20);
+
+        let shared = lock(path, LockMode::Shared, timeout).unwrap();
+        let other_shared = lock(path, LockMode::Shared, timeout).unwrap();
+        assert_eq!(
+            lock(path, LockMode::Exclusive, timeout).unwrap_err(),
+            RustaskError::LockTimeout(path.display().to_string(), timeout)
+        );
+        drop(shared);
+        drop(other_shared);
+
+        let exclusive = lock(path, LockMode::Exclusive, timeout).unwrap();
+        assert_eq!(
+            lock(path, LockMode::Shared, timeout).unwrap_err(),
+            RustaskError::LockTimeout(path.display().to_string(), timeout)
+        );
+        drop(exclusive);
+
+        assert!(lock(path, LockMode::Exclusive, timeout).is_ok());
+        fs::remove_file(with_suffix(path, ".lock")).unwrap();
+    }
+
+    #[test]
+    fn old_journal_dropped() {
+        let path = Path::new("test_file_old_journal");
+        fs::write(
+            journal_path(path),
+            r#"{"done":[{"time":"2022-05-06T17:00:00+00:00","operation":"add","before":[],"after":[]}],"undone":[]}"#,
+        )
+        .unwrap();
+
+        assert_eq!(load_journal(path).unwrap(), Journal::default());
+        fs::write(journal_path(path), "{").unwrap();
+        assert!(load_journal(path).is_err());
+        fs::remove_file(journal_path(path)).unwrap();
+    }
+
+    #[test]
+    fn bad_journal_kept() {
+        let path = Path::new("test_file_bad_journal");
+        let contents = r#"{"done":[{"time":"2022-05-06T17:00:00+00:00","operation":"add","changes":{}}],"undone":[]}"#;
+        fs::write(journal_path(path), contents).unwrap();
+
+        match load_journal(path) {
+            Err(RustaskError::FileError(e)) => assert!(e.suggestion.unwrap().starts_with("remove")),
+            other => panic!("expected a file error, got {:?}", other),
+        }
+        assert_eq!(fs::read_to_string(journal_path(path)).unwrap(), contents);
+        fs::remove_file(journal_path(path)).unwrap();
+    }
+
+    #[test]
+    fn load_without_ids() {
+        let path = Path::new("test_file_without_ids");
+        fs::write(
+            path,
+            r#"[{"name":"proj","tasks":[{"description":"task","priority":null,"deadline":null}]}]"#,
+        )
+        .unwrap();
+
+        let first = load_tasks(path).unwrap();
+        let second = load_tasks(path).unwrap();
+        assert_eq!(first[0].tasks()[0].id, task::legacy_id("proj", 0, "task"));
+        assert_eq!(first, second);
+        fs::remove_file(path).unwrap();
+    }
 }
@@ -0,0 +1,904 @@
+// store.rs
+//
+// library access to a task file
+
+use std::collections::{BTreeMap, BTreeSet};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::commands::backend::{Backend, Storage};
+use crate::commands::change::{Change, Projects};
+use crate::commands::error::RustaskError;
+use crate::commands::filter::{Filter, Scope};
+use crate::commands::output::Listing;
+use crate::commands::project::{Project, Summary};
+use crate::commands::repair::Salvage;
+use crate::commands::schema;
+use crate::commands::selection::Selection;
+use crate::commands::storage::{self, LockMode};
+use crate::commands::task::{now_deadline, Deadline, Task, TaskEdit};
+use crate::commands::timesheet::{Grouping, Period, Report};
+use crate::commands::transaction::{Completion, Transaction};
+
+/// What [`TaskStore::doctor`] found out about a task file
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Diagnosis {
+    /// The version of the format the file is in, if it could be read
+    pub version: Option<u32>,
+    /// The version of the format written by this rustask
+    pub current_version: u32,
+    pub projects: usize,
+    pub tasks: usize,
+    /// One line per problem found
+    pub problems: Vec<String>,
+}
+
+/// A task file, in one of the storage [`Backend`]s
+///
+/// Every query reads the file under a shared lock and returns owned values; every
+/// mutation runs in a [`Transaction`] of its own. Use [`TaskStore::begin`] to batch
+/// several mutations.
+///
+/// # Examples
+///
+/// ```
+/// use rustask::commands::filter::Filter;
+/// use rustask::commands::task::TaskBuilder;
+/// use rustask::commands::TaskStore;
+///
+/// let path = std::env::temp_dir().join(format!("rustask-doctest-{}", std::process::id()));
+/// std::fs::write(&path, "[]").unwrap();
+///
+/// let store = TaskStore::open(&path).unwrap();
+/// store
+///     .add_task(TaskBuilder::new("write docs".to_string()).build(), "work".to_string())
+///     .unwrap();
+/// let listings = store.list(Some("work"), &Filter::default()).unwrap();
+/// assert_eq!(listings[0].tasks[0].0.description, "write docs");
+/// # for entry in std::fs::read_dir(std::env::temp_dir()).unwrap() {
+/// #     let entry = entry.unwrap().path();
+/// #     if entry.to_str().unwrap().starts_with(path.to_str().unwrap()) {
+/// #         std::fs::remove_file(entry).unwrap();
+/// #     }
+/// # }
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TaskStore {
+    path: PathBuf,
+    backend: Backend,
+}
+
+impl TaskStore {
+    /// Open an existing task file, in the backend its extension implies
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<TaskStore, RustaskError> {
+        let backend = Backend::from_path(path.as_ref());
+        TaskStore::open_with(path, backend)
+    }
+
+    /// Open an existing task file, in the given backend
+    pub fn open_with<P: AsRef<Path>>(path: P, backend: Backend) -> Result<TaskStore, RustaskError> {
+        let path = path.as_ref();
+        fs::metadata(path).map_err(|e| {
+            let missing = e.kind() == std::io::ErrorKind::NotFound;
+            match storage::io_error(path, "open the task file", e) {
+                RustaskError::FileError(mut error) if missing => {
+                    error.suggestion = Some(format!(
+                        "create it with `rustask -f {} init`",
+                        path.display()
+                    ));
+                    RustaskError::FileError(error)
+                }
+                e => e,
+            }
+        })?;
+        Ok(TaskStore {
+            path: path.to_path_buf(),
+            backend,
+        })
+    }
+
+    /// Create an empty task file, in the given backend, and the directories it is in
+    pub fn init<P: AsRef<Path>>(path: P, backend: Backend) -> Result<TaskStore, RustaskError> {
+        let path = path.as_ref();
+        if path.exists() {
+            return Err(RustaskError::TaskFileExists(path.display().to_string()));
+        }
+        if let Some(dir) = path.parent().filter(|d| !d.as_os_str().is_empty()) {
+            fs::create_dir_all(dir)
+                .map_err(|e| storage::io_error(dir, "create the directory", e))?;
+        }
+
+        let _lock = super::lock(path, LockMode::Exclusive)?;
+        backend.open(path).store(&[])?;
+        TaskStore::open_with(path, backend)
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    pub fn backend(&self) -> Backend {
+        self.backend
+    }
+
+    fn storage(&self) -> Box<dyn Storage> {
+        self.backend.open(&self.path)
+    }
+
+    fn load(&self) -> Result<Vec<Project>, RustaskError> {
+        let _lock = super::lock(&self.path, LockMode::Shared)?;
+        self.storage().load()
+    }
+
+    /// Every project, with all of its tasks
+    pub fn projects(&self) -> Result<Vec<Project>, RustaskError> {
+        self.load()
+    }
+
+    /// The project with the given name
+    pub fn project(&self, name: &str) -> Result<Project, RustaskError> {
+        let _lock = super::lock(&self.path, LockMode::Shared)?;
+        self.storage()
+            .load_project(name)?
+            .ok_or_else(|| RustaskError::ProjectNotFound(name.to_string()))
+    }
+
+    /// The tasks within `scope`, by project, failing if `project` is given but not found
+    ///
+    /// The caller holds the lock on the task file.
+    fn query(
+        &self,
+        project: Option<&str>,
+        scope: &Scope,
+    ) -> Result<Vec<(Summary, Vec<Task>)>, RustaskError> {
+        let projects = self.storage().query(scope)?;
+        match project {
+            Some(name) if !projects.iter().any(|(p, _)| p.name == name) => {
+                Err(RustaskError::ProjectNotFound(name.to_string()))
+            }
+            _ => Ok(projects),
+        }
+    }
+
+    fn list_filter<F>(
+        &self,
+        project: Option<&str>,
+        filter: &Filter,
+        op: F,
+    ) -> Result<Vec<Listing>, RustaskError>
+    where
+        F: Fn(&usize, &Task) -> bool,
+    {
+        self.list_annotated(project, filter, |i, t| op(i, t).then(String::new))
+    }
+
+    /// The tasks matching `filter` for which `op` yields an annotation, shown after the task
+    fn list_annotated<F>(
+        &self,
+        project: Option<&str>,
+        filter: &Filter,
+        op: F,
+    ) -> Result<Vec<Listing>, RustaskError>
+    where
+        F: Fn(&usize, &Task) -> Option<String>,
+    {
+        let _lock = super::lock(&self.path, LockMode::Shared)?;
+        let projects = self.query(project, &filter.scope(project))?;
+        let storage = self.storage();
+        let mut progress = storage.progress(project)?;
+        let dependencies: BTreeSet<String> = projects
+            .iter()
+            .flat_map(|(_, tasks)| tasks.iter().flat_map(|t| t.depends_on.iter().cloned()))
+            .collect();
+        let pending = if dependencies.is_empty() {
+            BTreeSet::new()
+        } else {
+            storage.pending(&dependencies)?
+        };
+        let is_blocked = |t: &Task| t.depends_on.iter().any(|id| pending.contains(id));
+
+        Ok(projects
+            .into_iter()
+            .filter_map(|(summary, tasks)| {
+                let matching: Vec<(usize, &Task)> = tasks
+                    .iter()
+                    .enumerate()
+                    .filter(|(_, t)| filter.matches(&summary.name, t))
+                    .filter(|(_, t)| !(filter.hide_blocked && is_blocked(t)))
+                    .collect();
+                // projects holding no matching task (e.g. only completed ones) are left
+                // out, unless asked for
+                if project.is_none() && matching.is_empty() {
+                    return None;
+                }
+                let tasks: Vec<(Task, String)> = matching
+                    .into_iter()
+                    .filter_map(|(i, t)| op(&i, t).map(|note| (t.clone(), note)))
+                    .collect();
+                let blocked = tasks
+                    .iter()
+                    .filter(|(t, _)| is_blocked(t))
+                    .map(|(t, _)| t.id.clone())
+                    .collect();
+                let progress = progress.remove(&summary.name).unwrap_or_default();
+                let listing = Listing {
+                    context: None,
+                    project: summary,
+                    tasks,
+                    progress,
+                    blocked,
+                };
+                Some(listing.outline())
+            })
+            .collect())
+    }
+
+    /// The tasks matching `filter`, in one project or in all of them
+    pub fn list(
+        &self,
+        project: Option<&str>,
+        filter: &Filter,
+    ) -> Result<Vec<Listing>, RustaskError> {
+        self.list_filter(project, filter, |_a, _b| true)
+    }
+
+    /// The tasks matching `filter`, picked according to their priority and deadline
+    ///
+    /// With `explain`, each task is annotated with the odds it had of being picked
+    pub fn pick(
+        &self,
+        project: Option<&str>,
+        filter: &Filter,
+        selection: &Selection,
+        explain: bool,
+    ) -> Result<Vec<Listing>, RustaskError> {
+        self.list_annotated(project, filter, |_, t| {
+            let choice = t.choose_with(selection);
+            match (choice.chosen, explain) {
+                (false, _) => None,
+                (true, false) => Some(String::new()),
+                (true, true) => Some(format!(" ({})", choice)),
+            }
+        })
+    }
+
+    /// The tasks completed in the range `[since, until)`, oldest first
+    ///
+    /// Projects without such tasks are left out
+    pub fn completed(
+        &self,
+        project: Option<&str>,
+        since: Option<Deadline>,
+        until: Option<Deadline>,
+    ) -> Result<Vec<Listing>, RustaskError> {
+        let scope = Scope {
+            project: project.map(str::to_string),
+            include_completed: true,
+            ..Scope::default()
+        };
+        let in_range = |t: &Task| match t.completed {
+            Some(c) => since.is_none_or(|s| c >= s) && until.is_none_or(|u| c < u),
+            None => false,
+        };
+
+        let _lock = super::lock(&self.path, LockMode::Shared)?;
+        Ok(self
+            .query(project, &scope)?
+            .into_iter()
+            .filter_map(|(summary, tasks)| {
+                let mut tasks: Vec<(Task, String)> = tasks
+                    .into_iter()
+                    .filter(in_range)
+                    .map(|t| (t, String::new()))
+                    .collect();
+                tasks.sort_by_key(|(t, _)| t.completed);
+                (!tasks.is_empty()).then_some(Listing {
+                    context: None,
+                    project: summary,
+                    tasks,
+                    progress: BTreeMap::new(),
+                    blocked: BTreeSet::new(),
+                })
+            })
+            .collect())
+    }
+
+    /// Check that the task file can be read and that its contents are valid
+    ///
+    /// Problems are reported in the diagnosis rather than as errors; a file in an
+    /// older version of the format is not a problem, as it is upgraded when written.
+    pub fn doctor(&self) -> Result<Diagnosis, RustaskError> {
+        let _lock = super::lock(&self.path, LockMode::Shared)?;
+        let storage = self.storage();
+        let mut diagnosis = Diagnosis {
+            version: None,
+            current_version: storage.current_version(),
+            projects: 0,
+            tasks: 0,
+            problems: vec![],
+        };
+
+        let loaded = storage.version().and_then(|version| {
+            diagnosis.version = Some(version);
+            storage.load()
+        });
+        match loaded {
+            Ok(projects) => {
+                diagnosis.projects = projects.len();
+                diagnosis.tasks = projects.iter().map(Project::len).sum();
+                diagnosis.problems = schema::validate(&projects);
+            }
+            Err(e) => diagnosis.problems.push(e.to_string()),
+        }
+        Ok(diagnosis)
+    }
+
+    /// Rewrite the task file with whatever projects and tasks can be read from it
+    ///
+    /// The file is left as it is if `dry_run` is set; otherwise, the previous version
+    /// is kept as a backup, as on every write.
+    pub fn repair(&self, dry_run: bool) -> Result<Salvage, RustaskError> {
+        let _lock = super::lock(&self.path, LockMode::Exclusive)?;
+        let storage = self.storage();
+        let mut salvage = storage.salvage()?;
+        salvage.replace_invalid_ids();
+        if !dry_run {
+            storage.store(&salvage.projects)?;
+        }
+        Ok(salvage)
+    }
+
+    /// Copy every project to a new task file at `path`, in `backend`
+    ///
+    /// The copy is read back and compared with the original, so that no data is lost
+    /// silently. The journal is not copied: the new file starts without undo history.
+    pub fn migrate<P: AsRef<Path>>(
+        &self,
+        path: P,
+        backend: Backend,
+    ) -> Result<TaskStore, RustaskError> {
+        let path = path.as_ref();
+        if path.exists() {
+            return Err(RustaskError::TaskFileExists(path.display().to_string()));
+        }
+
+        let _lock = super::lock(&self.path, LockMode::Shared)?;
+        let projects = self.storage().load()?;
+        let _new_lock = super::lock(path, LockMode::Exclusive)?;
+        let storage = backend.open(path);
+        storage.store(&projects)?;
+        if storage.load()? != projects {
+            return Err(RustaskError::LossyMigration(path.display().to_string()));
+        }
+        TaskStore::open_with(path, backend)
+    }
+
+    /// Start a batch of mutations
+    pub fn begin(&self) -> Result<Transaction, RustaskError> {
+        Transaction::begin(&self.path, self.backend)
+    }
+
+    /// Renames a project if it exists and if the other name is not taken
+    pub fn rename(&self, project: String, name: String) -> Result<(), RustaskError> {
+        let mut tx = self.begin()?;
+        tx.rename(project, name)?;
+        tx.commit()
+    }
+
+    /// Add a new task
+    pub fn add_task(&self, task: Task, name: String) -> Result<(), RustaskError> {
+        let mut tx = self.begin()?;
+        tx.add_task(task, name)?;
+        tx.commit()
+    }
+
+    /// Remove a task, given (a unique prefix of) its id
+    pub fn remove_task(&self, id: &str, name: String) -> Result<Task, RustaskError> {
+        let mut tx = self.begin()?;
+        let task = tx.remove_task(id, name)?;
+        tx.commit()?;
+        Ok(task)
+    }
+
+    /// Complete several tasks of a project: either all of them are completed or none is
+    pub fn complete_tasks(&self, ids: &[&str], name: String) -> Result<Vec<Task>, RustaskError> {
+        self.complete_tasks_with(ids, name, Completion::default())
+    }
+
+    /// Complete several tasks of a project as set in `completion`, as a whole
+    ///
+    /// Returns every task completed, subtasks included.
+    pub fn complete_tasks_with(
+        &self,
+        ids: &[&str],
+        name: String,
+        completion: Completion,
+    ) -> Result<Vec<Task>, RustaskError> {
+        let mut tx = self.begin()?;
+        let tasks = tx.complete_tasks(ids, name, completion)?;
+        tx.commit()?;
+        Ok(tasks)
+    }
+
+    /// Add a note, written now, to a task given (a unique prefix of) its id
+    pub fn annotate_task(
+        &self,
+        id: &str,
+        name: String,
+        text: String,
+    ) -> Result<Task, RustaskError> {
+        let mut tx = self.begin()?;
+        let task = tx.annotate_task(id, name, text)?;
+        tx.commit()?;
+        Ok(task)
+    }
+
+    /// Start a timer on a task, given (a unique prefix of) its id
+    ///
+    /// Returns the task whose timer was stopped to start this one, with its project.
+    pub fn start_timer(
+        &self,
+        id: &str,
+        name: String,
+    ) -> Result<Option<(String, Task)>, RustaskError> {
+        let mut tx = self.begin()?;
+        let stopped = tx.start_timer(id, name)?;
+        tx.commit()?;
+        Ok(stopped)
+    }
+
+    /// Stop the running timer, returning the task it ran on with its project
+    pub fn stop_timer(&self) -> Result<(String, Task), RustaskError> {
+        let mut tx = self.begin()?;
+        let stopped = tx.stop_timer()?;
+        tx.commit()?;
+        Ok(stopped)
+    }
+
+    /// The time spent in `period` on the tasks of `project` (or of every project), summed
+    /// up by `grouping`
+    pub fn report(
+        &self,
+        project: Option<&str>,
+        grouping: Grouping,
+        period: &Period,
+    ) -> Result<Report, RustaskError> {
+        let projects = match project {
+            Some(name) => vec![self.project(name)?],
+            None => self.load()?,
+        };
+        Ok(Report::new(&projects, grouping, period, now_deadline()))
+    }
+
+    /// Move a task, given (a unique prefix of) its id, to another project
+    pub fn move_task(
+        &self,
+        old_project: String,
+        id: &str,
+        new_project: String,
+    ) -> Result<(), RustaskError> {
+        let mut tx = self.begin()?;
+        tx.move_task(old_project, id, new_project)?;
+        tx.commit()
+    }
+
+    /// Edit a task, given (a unique prefix of) its id
+    pub fn edit_task(&self, id: &str, name: String, edit: TaskEdit) -> Result<(), RustaskError> {
+        let mut tx = self.begin()?;
+        tx.edit_task(id, name, edit)?;
+        tx.commit()
+    }
+
+    /// Undo the last operation recorded in the journal
+    ///
+    /// Returns the description of the undone operation, if there was one.
+    /// Fails if the task file changed since the operation in a way which conflicts with it.
+    pub fn undo(&self) -> Result<Option<String>, RustaskError> {
+        let _lock = super::lock(&self.path, LockMode::Exclusive)?;
+        let mut journal = storage::load_journal(&self.path)?;
+        let entry = match journal.last_done() {
+            Some(entry) => entry,
+            None => return Ok(None),
+        };
+        let operation = entry.operation.clone();
+        self.replay(&entry.inverse(), &operation)?;
+        journal.undo();
+        storage::store_journal(&self.path, &journal)?;
+        Ok(Some(operation))
+    }
+
+    /// Redo the last undone operation
+    ///
+    /// Returns the description of the redone operation, if there was one.
+    /// Fails if the task file changed since the undo in a way which conflicts with it.
+    pub fn redo(&self) -> Result<Option<String>, RustaskError> {
+        let _lock = super::lock(&self.path, LockMode::Exclusive)?;
+        let mut journal = storage::load_journal(&self.path)?;
+        let entry = match journal.last_undone() {
+            Some(entry) => entry,
+            None => return Ok(None),
+        };
+        let operation = entry.operation.clone();
+        self.replay(entry.changes(), &operation)?;
+        journal.redo();
+        storage::store_journal(&self.path, &journal)?;
+        Ok(Some(operation))
+    }
+
+    /// Apply changes from the journal, checking first that the projects they touch are as
+    /// the changes expect
+    fn replay(&self, changes: &[Change], operation: &str) -> Result<(), RustaskError> {
+        let storage = self.storage();
+        let mut projects = Projects::new();
+        for name in changes.iter().flat_map(Change::projects) {
+            if !projects.contains_key(name) {
+                projects.insert(name.to_string(), storage.load_project(name)?);
+            }
+        }
+        if !changes.iter().all(|c| c.apply(&mut projects)) {
+            return Err(RustaskError::JournalConflict(operation.to_string()));
+        }
+        storage.apply(changes)
+    }
+
+    /// The operations which can be undone (time and description), most recent first
+    pub fn operations(&self) -> Result<Vec<(Deadline, String)>, RustaskError> {
+        let _lock = super::lock(&self.path, LockMode::Shared)?;
+        let journal = storage::load_journal(&self.path)?;
+        Ok(journal
+            .entries()
+            .map(|e| (e.time, e.operation.clone()))
+            .collect())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::commands::task::{now_deadline, Priority, TaskBuilder};
+
+    fn setup(path: &Path) -> TaskStore {
+        fs::write(path, "[]").unwrap();
+        TaskStore::open(path).unwrap()
+    }
+
+    /// Remove the task file at `path` and the files kept next to it
+    fn cleanup(path: &Path) {
+        let mut files = vec![
+            path.to_path_buf(),
+            PathBuf::from(format!("{}.lock", path.display())),
+            PathBuf::from(format!("{}.journal", path.display())),
+        ];
+        files.extend(
+            (1..)
+                .map(|n| storage::backup_path(path, n))
+                .take_while(|backup| backup.exists()),
+        );
+        for file in files {
+            match fs::remove_file(&file) {
+                Err(e) if e.kind() != std::io::ErrorKind::NotFound => {
+                    panic!("failed to remove {}: {}", file.display(), e)
+                }
+                _ => {}
+            }
+        }
+    }
+
+    #[test]
+    fn open_missing() {
+        assert!(matches!(
+            TaskStore::open("test_file_store_missing"),
+            Err(RustaskError::FileError(_))
+        ));
+    }
+
+    #[test]
+    fn init() {
+        let path = Path::new("test_file_store_init.d/tasks.yaml");
+        let store = TaskStore::init(path, Backend::Yaml).unwrap();
+        assert_eq!(store.projects().unwrap(), vec![]);
+        assert_eq!(
+            store.doctor().unwrap().version,
+            Some(schema::CURRENT_VERSION)
+        );
+        assert_eq!(
+            TaskStore::init(path, Backend::Yaml),
+            Err(RustaskError::TaskFileExists(path.display().to_string()))
+        );
+        fs::remove_dir_all("test_file_store_init.d").unwrap();
+    }
+
+    #[test]
+    fn list() {
+        let path = Path::new("test_file_store_list");
+        let store = setup(path);
+        let work = TaskBuilder::new("work".to_string())
+            .tag("office".to_string())
+            .build();
+        let done = TaskBuilder::new("done".to_string()).build();
+        store.add_task(work.clone(), "a".to_string()).unwrap();
+        store.add_task(done.clone(), "b".to_string()).unwrap();
+        store.complete_tasks(&[&done.id], "b".to_string()).unwrap();
+
+        let listings = store.list(None, &Filter::default()).unwrap();
+        assert_eq!(listings.len(), 1);
+        assert_eq!(listings[0].project.name, "a");
+        assert_eq!(listings[0].tasks, vec![(work, String::new())]);
+
+        let filter = Filter {
+            without_tags: vec!["office".to_string()],
+            ..Filter::default()
+        };
+        assert_eq!(store.list(Some("a"), &filter).unwrap()[0].tasks, vec![]);
+        assert_eq!(
+            store.list(Some("c"), &filter).unwrap_err(),
+            RustaskError::ProjectNotFound("c".to_string())
+        );
+        assert_eq!(store.project("b").unwrap().pending_len(), 0);
+        cleanup(path);
+    }
+
+    #[test]
+    fn subtasks() {
+        let path = Path::new("test_file_store_subtasks");
+        let store = setup(path);
+        let parent = TaskBuilder::new("parent".to_string())
+            .priority(Priority::High)
+            .build();
+        let first = TaskBuilder::new("first".to_string())
+            .parent(parent.id.clone())
+            .build();
+        let second = TaskBuilder::new("second".to_string())
+            .priority(Priority::Urgent)
+            .parent(parent.id.clone())
+            .build();
+        let mut tx = store.begin().unwrap();
+        for t in [&parent, &first, &second] {
+            tx.add_task(t.clone(), "proj".to_string()).unwrap();
+        }
+        tx.commit().unwrap();
+        store
+            .complete_tasks(&[&first.id], "proj".to_string())
+            .unwrap();
+
+        let listings = store.list(None, &Filter::default()).unwrap();
+        let listed: Vec<&str> = listings[0]
+            .tasks
+            .iter()
+            .map(|(t, _)| t.description.as_str())
+            .collect();
+        // the urgent subtask is listed under its parent, not before it
+        assert_eq!(listed, vec!["parent", "second"]);
+        assert_eq!(listings[0].progress[&parent.id].to_string(), "1/2");
+
+        let cascade = Completion {
+            cascade: true,
+            ..Completion::default()
+        };
+        let done = store
+            .complete_tasks_with(&[&parent.id[..4]], "proj".to_string(), cascade)
+            .unwrap();
+        assert_eq!(done.len(), 2);
+        assert_eq!(store.project("proj").unwrap().pending_len(), 0);
+        cleanup(path);
+    }
+
+    #[test]
+    fn blocked() {
+        let path = Path::new("test_file_store_blocked");
+        let store = setup(path);
+        let first = TaskBuilder::new("first".to_string()).build();
+        let second = TaskBuilder::new("second".to_string())
+            .depends_on(first.id.clone())
+            .build();
+        store.add_task(first.clone(), "a".to_string()).unwrap();
+        store.add_task(second.clone(), "b".to_string()).unwrap();
+
+        let listings = store.list(None, &Filter::default()).unwrap();
+        assert_eq!(listings[1].blocked, [second.id.clone()].into());
+        let hide = Filter {
+            hide_blocked: true,
+            ..Filter::default()
+        };
+        let listings = store.list(None, &hide).unwrap();
+        assert_eq!(listings.len(), 1);
+        assert_eq!(listings[0].project.name, "a");
+
+        store.complete_tasks(&[&first.id], "a".to_string()).unwrap();
+        let listings = store.list(Some("b"), &hide).unwrap();
+        assert_eq!(listings[0].tasks, vec![(second, String::new())]);
+        assert!(listings[0].blocked.is_empty());
+        cleanup(path);
+    }
+
+    #[test]
+    fn timers() {
+        use crate::commands::timesheet::Effort;
+
+        let path = Path::new("test_file_store_timers.db");
+        let store = TaskStore::init(path, Backend::Sqlite).unwrap();
+        let task = TaskBuilder::new("task".to_string())
+            .estimate(Effort::minutes(30))
+            .build();
+        store.add_task(task.clone(), "proj".to_string()).unwrap();
+        assert_eq!(
+            store.start_timer(&task.id[..4], "proj".to_string()),
+            Ok(None)
+        );
+        let (project, stopped) = store.stop_timer().unwrap();
+        assert_eq!(project, "proj");
+        assert_eq!(store.project("proj").unwrap().tasks(), &vec![stopped]);
+
+        let report = store
+            .report(None, Grouping::Project, &Period::default())
+            .unwrap();
+        assert_eq!(report.lines.len(), 1);
+        assert_eq!(report.lines[0].key, "proj");
+        assert_eq!(report.lines[0].estimate, Some(Effort::minutes(30)));
+        assert_eq!(
+            store
+                .report(Some("other"), Grouping::Project, &Period::default())
+                .unwrap_err(),
+            RustaskError::ProjectNotFound("other".to_string())
+        );
+        cleanup(path);
+    }
+
+    #[test]
+    fn completed() {
+        let path = Path::new("test_file_store_completed");
+        let store = setup(path);
+        let first = TaskBuilder::new("first".to_string()).build();
+        let second = TaskBuilder::new("second".to_string()).build();
+        let mut tx = store.begin().unwrap();
+        tx.add_task(first.clone(), "proj".to_string()).unwrap();
+        tx.add_task(second.clone(), "proj".to_string()).unwrap();
+        tx.commit().unwrap();
+        store
+            .complete_tasks(&[&second.id], "proj".to_string())
+            .unwrap();
+        store
+            .complete_tasks(&[&first.id], "proj".to_string())
+            .unwrap();
+
+        let listings = store.completed(None, None, None).unwrap();
+        let descriptions: Vec<&str> = listings[0]
+            .tasks
+            .iter()
+            .map(|(t, _)| t.description.as_str())
+            .collect();
+        assert_eq!(descriptions, vec!["second", "first"]);
+        assert_eq!(
+            store
+                .completed(
+                    Some("proj"),
+                    None,
+                    Some(now_deadline() - chrono::Duration::hours(1))
+                )
+                .unwrap(),
+            vec![]
+        );
+        cleanup(path);
+    }
+
+    #[test]
+    fn undo_redo() {
+        let path = Path::new("test_file_store_undo_redo");
+        let store = setup(path);
+        let task = TaskBuilder::new("task".to_string()).build();
+        store.add_task(task.clone(), "proj".to_string()).unwrap();
+
+        assert_eq!(store.operations().unwrap().len(), 1);
+        assert_eq!(
+            store.undo().unwrap(),
+            Some("add \"task\" to proj".to_string())
+        );
+        assert_eq!(store.projects().unwrap(), vec![]);
+        assert_eq!(store.undo().unwrap(), None);
+        assert!(store.redo().unwrap().is_some());
+        assert_eq!(store.project("proj").unwrap().tasks(), &vec![task]);
+        cleanup(path);
+    }
+
+    #[test]
+    fn undo_conflict() {
+        let path = Path::new("test_file_store_undo_conflict.db");
+        fs::write(path, "").unwrap();
+        let store = TaskStore::open(path).unwrap();
+        let task = TaskBuilder::new("task".to_string()).build();
+        let other = TaskBuilder::new("other".to_string()).build();
+        store.add_task(task.clone(), "proj".to_string()).unwrap();
+        store.add_task(other.clone(), "proj".to_string()).unwrap();
+
+        // another program edits the task added last
+        let mut edited = store.project("proj").unwrap();
+        edited
+            .edit(&other.id, |t| t.description = "edited".to_string())
+            .unwrap();
+        Backend::Sqlite.open(path).store(&[edited.clone()]).unwrap();
+
+        assert_eq!(
+            store.undo().unwrap_err(),
+            RustaskError::JournalConflict("add \"other\" to proj".to_string())
+        );
+        assert_eq!(store.projects().unwrap(), vec![edited]);
+        assert_eq!(store.operations().unwrap().len(), 2);
+        cleanup(path);
+    }
+
+    #[test]
+    fn doctor() {
+        let path = Path::new("test_file_store_doctor");
+        fs::write(
+            path,
+            r#"[{"name":"proj","tasks":[{"id":"0123","description":"task","priority":null,"deadline":null}]}]"#,
+        )
+        .unwrap();
+        let store = TaskStore::open(path).unwrap();
+        let diagnosis = store.doctor().unwrap();
+        assert_eq!(diagnosis.version, Some(1));
+        assert_eq!(diagnosis.current_version, schema::CURRENT_VERSION);
+        assert_eq!((diagnosis.projects, diagnosis.tasks), (1, 1));
+        assert_eq!(diagnosis.problems.len(), 1);
+
+        // the next write upgrades the file
+        store
+            .add_task(
+                TaskBuilder::new("new".to_string()).build(),
+                "proj".to_string(),
+            )
+            .unwrap();
+        assert_eq!(
+            store.doctor().unwrap().version,
+            Some(schema::CURRENT_VERSION)
+        );
+
+        fs::write(path, r#"{"version":100,"projects":[]}"#).unwrap();
+        let diagnosis = store.doctor().unwrap();
+        assert_eq!(diagnosis.version, Some(100));
+        assert_eq!(
+            diagnosis.problems,
+            vec![RustaskError::UnsupportedVersion(100, schema::CURRENT_VERSION).to_string()]
+        );
+        cleanup(path);
+    }
+
+    #[test]
+    fn repair() {
+        let path = Path::new("test_file_store_repair");
+        let store = setup(path);
+        for description in ["first", "second"] {
+            store
+                .add_task(
+                    TaskBuilder::new(description.to_string()).build(),
+                    "proj".to_string(),
+                )
+                .unwrap();
+        }
+        let contents = fs::read_to_string(path).unwrap();
+        let cut = contents.find("second").unwrap();
+        fs::write(path, &contents[..cut]).unwrap();
+        assert!(matches!(store.projects(), Err(RustaskError::FileError(_))));
+
+        let salvage = store.repair(true).unwrap();
+        assert_eq!(salvage.tasks(), 1);
+        assert_eq!(salvage.dropped.len(), 1);
+        assert!(store.projects().is_err());
+
+        store.repair(false).unwrap();
+        let projects = store.projects().unwrap();
+        assert_eq!(projects[0].tasks()[0].description, "first");
+        assert_eq!(store.doctor().unwrap().problems, Vec::<String>::new());
+
+        fs::write(
+            path,
+            r#"[{"name":"proj","tasks":[{"id":"ab","description":"task","priority":null,"deadline":null}]}]"#,
+        )
+        .unwrap();
+        let salvage = store.repair(false).unwrap();
+        assert_eq!(salvage.renamed.len(), 1);
+        assert!(salvage.renamed[0].starts_with("task ab in proj to "));
+        assert_eq!(store.doctor().unwrap().problems, Vec::<String>::new());
+        cleanup(path);
+    }
+}
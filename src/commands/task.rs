@@ -3,45 +3,24 @@
 // define task type
 
 //use chrono::prelude::*;
-use chrono::naive::{NaiveDate, NaiveDateTime};
-use chrono::{DateTime, Local, TimeZone};
+use chrono::{DateTime, Local};
 use colored::*;
 use serde::{Deserialize, Serialize};
 use std::cmp::Ordering;
+use std::collections::BTreeSet;
 use std::fmt;
 use std::str::FromStr;
 
-pub type Deadline = DateTime<Local>;
-
-#[derive(thiserror::Error, Debug)]
-pub enum DeadlineParseError {
-    #[error("failed to parse the string as a datetime: {}", .string)]
-    ParseError {
-        string: String,
-        #[source]
-        source: chrono::format::ParseError,
-    },
-    #[error("failed to assign a timezone")]
-    TimezoneError,
-}
+use crate::commands::annotation::Annotation;
+use crate::commands::config;
+pub use crate::commands::deadline::{
+    parse_deadline, parse_deadline_at, parse_deadline_with, DeadlineParseError,
+};
+use crate::commands::recurrence::Recurrence;
+use crate::commands::selection::Selection;
+use crate::commands::timesheet::{Effort, Interval};
 
-pub fn parse_deadline(s: &str) -> Result<Deadline, DeadlineParseError> {
-    const DATE_FMT: &str = "%F";
-    const DATETIME_FMT: &str = "%F %H:%M";
-
-    let naive: NaiveDateTime = NaiveDate::parse_from_str(s, DATE_FMT)
-        .map(|date| date.and_hms(0, 0, 0))
-        .or(NaiveDateTime::parse_from_str(s, DATETIME_FMT))
-        .map_err(|e| DeadlineParseError::ParseError {
-            string: s.to_string(),
-            source: e,
-        })?;
-
-    Local
-        .from_local_datetime(&naive)
-        .earliest()
-        .ok_or_else(|| DeadlineParseError::TimezoneError)
-}
+pub type Deadline = DateTime<Local>;
 
 #[allow(unused)]
 pub fn now_deadline() -> Deadline {
@@ -57,17 +36,129 @@ pub enum Priority {
     Note,
 }
 
+/// Length of the hexadecimal task identifiers
+pub const ID_LEN: usize = 16;
+
+/// Whether `id` is [`ID_LEN`] lowercase hexadecimal digits, as generated
+pub fn is_valid_id(id: &str) -> bool {
+    id.len() == ID_LEN && id.bytes().all(|b| matches!(b, b'0'..=b'9' | b'a'..=b'f'))
+}
+
+/// The priority of tasks given none: `default_priority` in the configuration, or normal
+pub fn default_priority() -> &'static Priority {
+    config::get()
+        .default_priority
+        .as_ref()
+        .unwrap_or(&Priority::Normal)
+}
+
+/// Generate a fresh random task identifier
+pub fn new_id() -> String {
+    format!("{:016x}", rand::random::<u64>())
+}
+
+/// Derive an identifier for a task stored before identifiers existed
+///
+/// The id is a hash of the project name, the position of the task and its description,
+/// so the same file yields the same ids until it is written back with them.
+pub fn legacy_id(project: &str, position: usize, description: &str) -> String {
+    let position = position.to_le_bytes();
+    let hash = fnv1a(
+        project
+            .bytes()
+            .chain([0u8])
+            .chain(position.iter().copied())
+            .chain(description.bytes()),
+    );
+    format!("{:016x}", hash)
+}
+
+/// FNV-1a hash: unlike `std`'s hashers, stable across platforms and compiler versions
+pub(crate) fn fnv1a<I: IntoIterator<Item = u8>>(bytes: I) -> u64 {
+    let mut hash: u64 = 0xcbf29ce484222325;
+    for byte in bytes {
+        hash ^= byte as u64;
+        hash = hash.wrapping_mul(0x100000001b3);
+    }
+    hash
+}
+
 #[derive(Debug, Serialize, Deserialize, PartialEq, Eq, Clone)]
 pub struct Task {
+    #[serde(default)]
+    pub id: String,
     pub description: String,
     pub priority: Option<Priority>,
     pub deadline: Option<Deadline>,
+    #[serde(default)]
+    pub completed: Option<Deadline>,
+    #[serde(default)]
+    pub recurrence: Option<Recurrence>,
+    #[serde(default)]
+    pub tags: BTreeSet<String>,
+    /// The id of the task this one is a subtask of, in the same project
+    #[serde(default)]
+    pub parent: Option<String>,
+    /// The ids of the tasks, in any project, to be done before this one
+    #[serde(default)]
+    pub depends_on: BTreeSet<String>,
+    /// How long the task is expected to take
+    #[serde(default)]
+    pub estimate: Option<Effort>,
+    /// The time spent on the task, the last interval still open while its timer runs
+    #[serde(default)]
+    pub time: Vec<Interval>,
+    /// Notes on the task, oldest first
+    #[serde(default)]
+    pub annotations: Vec<Annotation>,
+}
+
+/// The name of a `+tag` token, if the word is one
+///
+/// Tag names are made of letters, digits, `-`, `_` and `@`, and do not start with a digit
+/// (so `+3d` is left alone).
+fn tag_name(word: &str) -> Option<&str> {
+    let name = word.strip_prefix('+')?;
+    let valid = name.chars().next().is_some_and(|c| !c.is_ascii_digit())
+        && name
+            .chars()
+            .all(|c| c.is_alphanumeric() || matches!(c, '-' | '_' | '@'));
+    valid.then_some(name)
+}
+
+/// Split the `+tag` tokens out of a description
+///
+/// # Examples
+///
+/// ```
+/// use rustask::commands::task::parse_tags;
+/// let (description, tags) = parse_tags("review the +@review patch +work");
+/// assert_eq!(description, "review the patch");
+/// assert_eq!(tags.into_iter().collect::<Vec<_>>(), vec!["@review", "work"]);
+/// ```
+pub fn parse_tags(description: &str) -> (String, BTreeSet<String>) {
+    let mut tags = BTreeSet::new();
+    let mut words = vec![];
+    for word in description.split_whitespace() {
+        match tag_name(word) {
+            Some(tag) => {
+                tags.insert(tag.to_string());
+            }
+            None => words.push(word),
+        }
+    }
+    (words.join(" "), tags)
 }
 
 pub struct TaskBuilder {
     description: String,
     priority: Option<Priority>,
     deadline: Option<Deadline>,
+    recurrence: Option<Recurrence>,
+    tags: BTreeSet<String>,
+    parent: Option<String>,
+    depends_on: BTreeSet<String>,
+    estimate: Option<Effort>,
 }
 
 impl TaskBuilder {
@@ -76,6 +167,11 @@ impl TaskBuilder {
             description,
             priority: None,
             deadline: None,
+            recurrence: None,
+            tags: BTreeSet::new(),
+            parent: None,
+            depends_on: BTreeSet::new(),
+            estimate: None,
         }
     }
 
@@ -89,17 +185,190 @@ impl TaskBuilder {
         self
     }
 
+    pub fn recurrence(mut self, recurrence: Recurrence) -> TaskBuilder {
+        self.recurrence = Some(recurrence);
+        self
+    }
+
+    pub fn tag(mut self, tag: String) -> TaskBuilder {
+        self.tags.insert(tag);
+        self
+    }
+
+    pub fn tags<I: IntoIterator<Item = String>>(mut self, tags: I) -> TaskBuilder {
+        self.tags.extend(tags);
+        self
+    }
+
+    /// Make the task a subtask of the task with (a unique prefix of) the id `parent`
+    pub fn parent(mut self, parent: String) -> TaskBuilder {
+        self.parent = Some(parent);
+        self
+    }
+
+    /// Make the task wait on the task with (a unique prefix of) the id `id`
+    pub fn depends_on(mut self, id: String) -> TaskBuilder {
+        self.depends_on.insert(id);
+        self
+    }
+
+    pub fn estimate(mut self, estimate: Effort) -> TaskBuilder {
+        self.estimate = Some(estimate);
+        self
+    }
+
     pub fn build(self) -> Task {
         Task {
+            id: new_id(),
             description: self.description,
             priority: self.priority,
             deadline: self.deadline,
+            completed: None,
+            recurrence: self.recurrence,
+            tags: self.tags,
+            parent: self.parent,
+            depends_on: self.depends_on,
+            estimate: self.estimate,
+            time: vec![],
+            annotations: vec![],
+        }
+    }
+}
+
+/// Changes to the fields of a task; fields left as `None` are kept
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct TaskEdit {
+    pub description: Option<String>,
+    pub priority: Option<Priority>,
+    pub deadline: Option<Deadline>,
+    /// `Some(None)` stops a recurring task from repeating
+    pub recurrence: Option<Option<Recurrence>>,
+    pub add_tags: BTreeSet<String>,
+    /// Removed after `add_tags` are added
+    pub remove_tags: BTreeSet<String>,
+    /// Ids of tasks to wait on
+    pub add_dependencies: BTreeSet<String>,
+    /// Ids of tasks to stop waiting on, removed after `add_dependencies` are added
+    pub remove_dependencies: BTreeSet<String>,
+    /// `Some(None)` drops the estimate
+    pub estimate: Option<Option<Effort>>,
+    /// Added after the annotations the task has
+    pub annotations: Vec<Annotation>,
+}
+
+impl TaskEdit {
+    pub fn apply(self, task: &mut Task) {
+        if let Some(d) = self.description {
+            task.description = d;
+        }
+        if let Some(p) = self.priority {
+            task.priority = Some(p);
         }
+        if let Some(d) = self.deadline {
+            task.deadline = Some(d);
+        }
+        if let Some(r) = self.recurrence {
+            task.recurrence = r;
+        }
+        if let Some(e) = self.estimate {
+            task.estimate = e;
+        }
+        task.annotations.extend(self.annotations);
+        task.tags.extend(self.add_tags);
+        task.tags.retain(|t| !self.remove_tags.contains(t));
+        task.depends_on.extend(self.add_dependencies);
+        task.depends_on
+            .retain(|id| !self.remove_dependencies.contains(id));
     }
 }
 
 impl Task {
-    /// Whether to choose this task or not
+    /// Whether the id of this task starts with `prefix`
+    pub fn matches_id(&self, prefix: &str) -> bool {
+        !prefix.is_empty() && self.id.starts_with(&prefix.to_lowercase())
+    }
+
+    /// The priority of the task, or the configured default if it has none
+    pub fn effective_priority(&self) -> &Priority {
+        self.priority_or(default_priority())
+    }
+
+    /// The priority of the task, or `default` if it has none
+    pub fn priority_or<'a>(&'a self, default: &'a Priority) -> &'a Priority {
+        self.priority.as_ref().unwrap_or(default)
+    }
+
+    /// Like [`Ord::cmp`], with tasks without a priority having `default`
+    pub fn cmp_with_default(&self, other: &Task, default: &Priority) -> Ordering {
+        self.priority_or(default)
+            .cmp(other.priority_or(default))
+            .then_with(|| self.description.cmp(&other.description))
+            .then_with(|| self.id.cmp(&other.id))
+    }
+
+    /// The first `len` bytes of the id, or all of it if it is shorter or not ASCII there
+    pub fn id_prefix(&self, len: usize) -> &str {
+        self.id.get(..len).unwrap_or(&self.id)
+    }
+
+    /// Whether the task is tagged with `tag`
+    pub fn has_tag(&self, tag: &str) -> bool {
+        self.tags.contains(tag)
+    }
+
+    /// Whether the task is a subtask of another one
+    pub fn is_subtask(&self) -> bool {
+        self.parent.is_some()
+    }
+
+    /// Whether the task was completed
+    pub fn is_done(&self) -> bool {
+        self.completed.is_some()
+    }
+
+    /// Whether a timer is running on the task
+    pub fn is_running(&self) -> bool {
+        self.time.last().is_some_and(Interval::is_running)
+    }
+
+    /// Stop the timer running on the task at `now`, if any
+    ///
+    /// Returns whether one was running.
+    pub fn stop_timer(&mut self, now: Deadline) -> bool {
+        match self.time.last_mut().filter(|i| i.is_running()) {
+            Some(interval) => {
+                interval.end = Some(now.max(interval.start));
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// The time spent on the task, a running timer counting up to `now`
+    pub fn spent(&self, now: Deadline) -> Effort {
+        Effort::of(self.time.iter().map(|i| i.duration(now)).sum())
+    }
+
+    /// The next occurrence of a recurring task completed at `now`, if any
+    ///
+    /// The occurrence is a new pending task, with the deadline advanced, no time spent
+    /// on it and no annotations
+    pub fn next_occurrence(&self, now: Deadline) -> Option<Task> {
+        let recurrence = self.recurrence.as_ref()?;
+        let deadline = recurrence.next(self.deadline.unwrap_or(now), now)?;
+        Some(Task {
+            id: new_id(),
+            deadline: Some(deadline),
+            completed: None,
+            time: vec![],
+            annotations: vec![],
+            ..self.clone()
+        })
+    }
+
+    /// Whether to choose this task or not, at random with the default probabilities
+    ///
+    /// See [`Task::choose_with`] for a reproducible choice
     ///
     /// # Examples
     ///
@@ -109,54 +378,30 @@ impl Task {
     /// assert_eq!(task.choose(), true);
     /// ```
     pub fn choose(&self) -> bool {
-        use rand::{thread_rng, Rng};
-        let mut rng = thread_rng();
-
-        match self.priority.as_ref().unwrap_or(&Priority::Normal) {
-            Priority::Urgent => true,
-            Priority::High => true,
-            Priority::Normal => {
-                let prob = 1. / 3. + self.deadline_near();
-                if prob < 1.0 {
-                    rng.gen_bool(prob)
-                } else {
-                    true
-                }
-            }
-            Priority::Low => {
-                let prob = 1. / 5. + self.deadline_near();
-                if prob < 1.0 {
-                    rng.gen_bool(prob)
-                } else {
-                    true
-                }
-            }
-            Priority::Note => {
-                let prob = 1. / 8. + self.deadline_near();
-                if prob < 1.0 {
-                    rng.gen_bool(prob)
-                } else {
-                    true
-                }
-            }
-        }
+        self.choose_with(&Selection::random()).chosen
     }
 
     /// Whether there is a deadline near
     /// Yields a percentage, which can be read as an auxiliar priority level
     ///
     /// If a task is overdue or happening now, the percentage is 1.0
-    /// If a task is more than a week in the future, the percentage is 0.0
-    fn deadline_near(&self) -> f64 {
+    /// If a task is further in the future than the configured horizon (a week by
+    /// default), the percentage is 0.0
+    pub fn deadline_near(&self) -> f64 {
+        let days = config::get().deadlines.horizon_days;
+        self.deadline_within(chrono::Duration::days(days as i64))
+    }
+
+    /// Like [`Task::deadline_near`], with the given horizon
+    pub fn deadline_within(&self, horizon: chrono::Duration) -> f64 {
         if let Some(d) = self.deadline {
             let diff = d - now_deadline();
             if diff <= chrono::Duration::zero() {
                 1.0
-            } else if diff.num_weeks() > 0 {
+            } else if diff >= horizon {
                 0.0
             } else {
-                const MINUTES_IN_WEEK: f64 = (7 * 24 * 60) as f64;
-                diff.num_minutes() as f64 / MINUTES_IN_WEEK
+                1.0 - diff.num_minutes() as f64 / horizon.num_minutes() as f64
             }
         } else {
             0.0
@@ -209,33 +454,59 @@ impl fmt::Display for Task {
             )
         }
 
-        if let Some(deadline) = self.deadline {
+        let config = config::get();
+        let description = config
+            .colors
+            .priority(self.priority.as_ref())
+            .paint(&self.description);
+        if let Some(completed) = self.completed {
+            write!(
+                f,
+                "{} [done {}]",
+                self.description.dimmed().strikethrough(),
+                completed.format(&config.deadlines.output_format)
+            )?;
+        } else if let Some(deadline) = self.deadline {
             write!(
                 f,
                 "{} [{}]",
-                match &self.priority {
-                    Some(Priority::Urgent) => self.description.on_red().bold().bright_white(),
-                    Some(Priority::High) => self.description.red(),
-                    Some(Priority::Normal) => self.description.yellow(),
-                    Some(Priority::Low) => self.description.green(),
-                    Some(Priority::Note) => self.description.cyan(),
-                    None => self.description.bold(),
-                },
+                description,
                 display_diff(deadline - now_deadline())
-            )
+            )?;
         } else {
-            write!(
-                f,
-                "{}",
-                match &self.priority {
-                    Some(Priority::Urgent) => self.description.on_red().bold().bright_white(),
-                    Some(Priority::High) => self.description.red(),
-                    Some(Priority::Normal) => self.description.yellow(),
-                    Some(Priority::Low) => self.description.green(),
-                    Some(Priority::Note) => self.description.cyan(),
-                    None => self.description.bold(),
-                }
-            )
+            write!(f, "{}", description)?;
+        }
+
+        if self.estimate.is_some() || !self.time.is_empty() {
+            write!(f, " [{}", self.spent(now_deadline()))?;
+            if let Some(estimate) = self.estimate {
+                write!(f, "/{}", estimate)?;
+            }
+            write!(f, "{}]", if self.is_running() { ", running" } else { "" })?;
+        }
+        if let Some(recurrence) = self.recurrence.as_ref().filter(|_| !self.is_done()) {
+            write!(f, " [repeats {}]", recurrence)?;
+        }
+        for tag in &self.tags {
+            write!(f, " {}", config.colors.tag.paint(&format!("+{}", tag)))?;
+        }
+        match self.annotations.len() {
+            0 => {}
+            1 => write!(f, " [1 note]")?,
+            n => write!(f, " [{} notes]", n)?,
+        }
+        Ok(())
+    }
+}
+
+impl fmt::Display for Priority {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Priority::Urgent => write!(f, "urgent"),
+            Priority::High => write!(f, "high"),
+            Priority::Normal => write!(f, "normal"),
+            Priority::Low => write!(f, "low"),
+            Priority::Note => write!(f, "note"),
         }
     }
 }
@@ -258,13 +529,7 @@ impl FromStr for Priority {
 
 impl Ord for Task {
     fn cmp(&self, other: &Self) -> Ordering {
-        let self_pri = self.priority.as_ref().unwrap_or(&Priority::Normal);
-        let other_pri = other.priority.as_ref().unwrap_or(&Priority::Normal);
-        if self_pri == other_pri {
-            self.description.cmp(&other.description)
-        } else {
-            self_pri.cmp(&other_pri)
-        }
+        self.cmp_with_default(other, default_priority())
     }
 }
 
@@ -275,8 +540,10 @@ impl PartialOrd for Task {
 }
 
 #[cfg(test)]
+#[allow(clippy::bool_assert_comparison)]
 mod tests {
     use super::*;
+    use crate::commands::selection::Probabilities;
     #[test]
     fn urgent_task_display() {
         let task = TaskBuilder::new("urgent task".to_string())
@@ -344,6 +611,239 @@ mod tests {
         );
     }
 
+    #[test]
+    fn fresh_ids() {
+        let a = TaskBuilder::new("task".to_string()).build();
+        let b = TaskBuilder::new("task".to_string()).build();
+        assert_eq!(a.id.len(), ID_LEN);
+        assert_ne!(a.id, b.id);
+    }
+
+    #[test]
+    fn legacy_ids_are_stable() {
+        assert_eq!(legacy_id("proj", 0, "task"), legacy_id("proj", 0, "task"));
+        assert_ne!(legacy_id("proj", 0, "task"), legacy_id("proj", 1, "task"));
+        assert_ne!(legacy_id("proj", 0, "task"), legacy_id("other", 0, "task"));
+        assert_eq!(legacy_id("proj", 0, "task").len(), ID_LEN);
+    }
+
+    #[test]
+    fn match_id_prefix() {
+        let mut task = TaskBuilder::new("task".to_string()).build();
+        task.id = "0123456789abcdef".to_string();
+        assert!(task.matches_id("0123"));
+        assert!(task.matches_id("0123456789ABCDEF"));
+        assert!(!task.matches_id("123"));
+        assert!(!task.matches_id(""));
+        assert_eq!(task.id_prefix(4), "0123");
+        assert!(is_valid_id(&task.id));
+
+        task.id = "ab".to_string();
+        assert_eq!(task.id_prefix(4), "ab");
+        task.id = "aéb".to_string();
+        assert_eq!(task.id_prefix(2), "aéb");
+        assert!(!is_valid_id(&task.id));
+        assert!(!is_valid_id("0123456789ABCDEF"));
+    }
+
+    #[test]
+    fn done_display() {
+        let mut task = TaskBuilder::new("done".to_string())
+            .priority(Priority::High)
+            .build();
+        let completed = parse_deadline("2022-05-01 10:30").unwrap();
+        task.completed = Some(completed);
+        assert!(task.is_done());
+        assert_eq!(
+            format!("Done: {}", task),
+            format!(
+                "Done: {} [done 2022-05-01 10:30]",
+                "done".dimmed().strikethrough()
+            ),
+        );
+    }
+
+    #[test]
+    fn deadline_near() {
+        let far = TaskBuilder::new("far".to_string())
+            .deadline(now_deadline() + chrono::Duration::weeks(2))
+            .build();
+        let soon = TaskBuilder::new("soon".to_string())
+            .deadline(now_deadline() + chrono::Duration::days(1))
+            .build();
+        let overdue = TaskBuilder::new("overdue".to_string())
+            .deadline(now_deadline() - chrono::Duration::days(1))
+            .build();
+        let none = TaskBuilder::new("none".to_string()).build();
+
+        assert_eq!(far.deadline_near(), 0.0);
+        assert!(soon.deadline_near() > 0.8 && soon.deadline_near() < 1.0);
+        assert_eq!(overdue.deadline_near(), 1.0);
+        assert_eq!(none.deadline_near(), 0.0);
+
+        let horizon = chrono::Duration::days(2);
+        assert!((soon.deadline_within(horizon) - 0.5).abs() < 0.01);
+        assert!((far.deadline_within(chrono::Duration::weeks(4)) - 0.5).abs() < 0.01);
+    }
+
+    #[test]
+    fn recurring_display() {
+        let task = TaskBuilder::new("weekly".to_string())
+            .recurrence(Recurrence::new(crate::commands::recurrence::Rule::Weekly))
+            .build();
+        assert_eq!(
+            format!("Recurring: {}", task),
+            format!("Recurring: {} [repeats weekly]", "weekly".bold()),
+        );
+    }
+
+    #[test]
+    fn next_occurrence() {
+        use crate::commands::recurrence::Rule;
+
+        let deadline = parse_deadline("2022-05-02 10:00").unwrap();
+        let task = TaskBuilder::new("report".to_string())
+            .priority(Priority::High)
+            .deadline(deadline)
+            .recurrence(Recurrence::new(Rule::Weekly))
+            .build();
+
+        let next = task.next_occurrence(deadline).unwrap();
+        assert_ne!(next.id, task.id);
+        assert_eq!(next.description, task.description);
+        assert_eq!(next.priority, task.priority);
+        assert_eq!(next.recurrence, task.recurrence);
+        assert_eq!(
+            next.deadline,
+            Some(parse_deadline("2022-05-09 10:00").unwrap())
+        );
+
+        let once = TaskBuilder::new("once".to_string())
+            .deadline(deadline)
+            .build();
+        assert_eq!(once.next_occurrence(deadline), None);
+    }
+
+    #[test]
+    fn edit() {
+        let mut task = TaskBuilder::new("task".to_string())
+            .priority(Priority::Low)
+            .recurrence(Recurrence::new(crate::commands::recurrence::Rule::Daily))
+            .build();
+        TaskEdit {
+            description: Some("edited".to_string()),
+            recurrence: Some(None),
+            ..TaskEdit::default()
+        }
+        .apply(&mut task);
+
+        assert_eq!(task.description, "edited");
+        assert_eq!(task.priority, Some(Priority::Low));
+        assert_eq!(task.recurrence, None);
+    }
+
+    #[test]
+    fn tags() {
+        let (description, tags) = parse_tags("call +home  mom +@home in +3d +");
+        assert_eq!(description, "call mom in +3d +");
+        assert_eq!(
+            tags,
+            BTreeSet::from(["home".to_string(), "@home".to_string()])
+        );
+
+        let mut task = TaskBuilder::new(description).tags(tags).build();
+        assert!(task.has_tag("home"));
+        TaskEdit {
+            add_tags: BTreeSet::from(["phone".to_string()]),
+            remove_tags: BTreeSet::from(["home".to_string(), "missing".to_string()]),
+            ..TaskEdit::default()
+        }
+        .apply(&mut task);
+        assert_eq!(
+            task.tags,
+            BTreeSet::from(["@home".to_string(), "phone".to_string()])
+        );
+    }
+
+    #[test]
+    fn tagged_display() {
+        let task = TaskBuilder::new("tagged".to_string())
+            .tag("work".to_string())
+            .build();
+        assert_eq!(
+            format!("Tagged: {}", task),
+            format!("Tagged: {} {}", "tagged".bold(), "+work".blue()),
+        );
+    }
+
+    #[test]
+    fn timed_display() {
+        let mut task = TaskBuilder::new("timed".to_string())
+            .estimate(Effort::minutes(120))
+            .build();
+        assert_eq!(format!("{}", task), format!("{} [0m/2h]", "timed".bold()));
+
+        let now = now_deadline();
+        task.time = vec![
+            Interval {
+                start: now - chrono::Duration::minutes(50),
+                end: Some(now - chrono::Duration::minutes(40)),
+            },
+            Interval::starting(now - chrono::Duration::minutes(5)),
+        ];
+        assert!(task.is_running());
+        assert_eq!(task.spent(now), Effort::minutes(15));
+        assert!(format!("{}", task).ends_with(" [15m/2h, running]"));
+
+        assert!(task.stop_timer(now));
+        assert!(!task.stop_timer(now));
+        assert_eq!(task.spent(now_deadline()), Effort::minutes(15));
+        task.estimate = None;
+        assert_eq!(format!("{}", task), format!("{} [15m]", "timed".bold()));
+    }
+
+    #[test]
+    fn annotated_display() {
+        let mut task = TaskBuilder::new("annotated".to_string()).build();
+        let note = Annotation::new("called them".to_string(), now_deadline());
+        TaskEdit {
+            annotations: vec![note.clone()],
+            ..TaskEdit::default()
+        }
+        .apply(&mut task);
+        assert_eq!(task.annotations, vec![note.clone()]);
+        assert_eq!(
+            format!("{}", task),
+            format!("{} [1 note]", "annotated".bold())
+        );
+
+        task.annotations.push(note);
+        assert!(format!("{}", task).ends_with(" [2 notes]"));
+    }
+
+    #[test]
+    fn default_priority_applies() {
+        let unset = TaskBuilder::new("a".to_string()).build();
+        let normal = TaskBuilder::new("b".to_string())
+            .priority(Priority::Normal)
+            .build();
+        assert_eq!(unset.effective_priority(), &Priority::Normal);
+        assert_eq!(unset.cmp(&normal), Ordering::Less);
+        assert_eq!(
+            unset.cmp_with_default(&normal, &Priority::Low),
+            Ordering::Greater
+        );
+
+        let mut probabilities = Probabilities::default();
+        probabilities.set(&Priority::Normal, 0.0);
+        probabilities.set(&Priority::Urgent, 1.0);
+        let selection = Selection::seeded(7).probabilities(probabilities);
+        assert!(!unset.choose_with(&selection).chosen);
+        let selection = selection.default_priority(Priority::Urgent);
+        assert!(unset.choose_with(&selection).chosen);
+        assert!(!normal.choose_with(&selection).chosen);
+    }
+
     #[test]
     fn urgent_filter() {
         let task = TaskBuilder::new("task".to_string())
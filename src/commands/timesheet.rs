@@ -0,0 +1,513 @@
+// timesheet.rs
+//
+// track the time spent on tasks and sum it up
+
+use chrono::{Days, NaiveDate};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fmt;
+use std::io::{self, Write};
+use std::iter::Sum;
+use std::ops::Add;
+use std::str::FromStr;
+
+use crate::commands::deadline::localize;
+use crate::commands::project::Project;
+use crate::commands::task::{Deadline, Task};
+
+/// An amount of work, to the minute
+#[derive(Debug, Default, Clone, Copy, Serialize, Deserialize, PartialEq, Eq, PartialOrd, Ord)]
+#[serde(transparent)]
+pub struct Effort {
+    minutes: u32,
+}
+
+impl Effort {
+    pub fn minutes(minutes: u32) -> Effort {
+        Effort { minutes }
+    }
+
+    /// The whole minutes in `duration`, none if it is negative
+    pub fn of(duration: chrono::Duration) -> Effort {
+        Effort::minutes(duration.num_minutes().clamp(0, u32::MAX as i64) as u32)
+    }
+
+    pub fn as_minutes(&self) -> u32 {
+        self.minutes
+    }
+}
+
+impl Add for Effort {
+    type Output = Effort;
+    fn add(self, other: Effort) -> Effort {
+        Effort::minutes(self.minutes.saturating_add(other.minutes))
+    }
+}
+
+impl Sum for Effort {
+    fn sum<I: Iterator<Item = Effort>>(iter: I) -> Effort {
+        iter.fold(Effort::default(), Add::add)
+    }
+}
+
+impl fmt::Display for Effort {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match (self.minutes / 60, self.minutes % 60) {
+            (0, minutes) => write!(f, "{}m", minutes),
+            (hours, 0) => write!(f, "{}h", hours),
+            (hours, minutes) => write!(f, "{}h{:02}m", hours, minutes),
+        }
+    }
+}
+
+#[derive(thiserror::Error, Debug, PartialEq, Eq)]
+#[error(
+    "failed to parse `{}` as an effort: expected hours and minutes, e.g. 1h30m, 1.5h or 45m",
+    .0
+)]
+pub struct EffortParseError(String);
+
+impl FromStr for Effort {
+    type Err = EffortParseError;
+
+    /// Hours and minutes, each a number followed by `h` or `m`, in any order and
+    /// possibly spaced apart (hours may have a fraction)
+    fn from_str(s: &str) -> Result<Self, EffortParseError> {
+        let err = || EffortParseError(s.to_string());
+        let lower = s.to_lowercase().replace(char::is_whitespace, "");
+        if lower.is_empty() {
+            return Err(err());
+        }
+
+        let mut minutes = 0.0;
+        let mut rest = lower.as_str();
+        while !rest.is_empty() {
+            let unit = rest.find(['h', 'm']).ok_or_else(err)?;
+            let amount = rest[..unit].parse::<f64>().map_err(|_| err())?;
+            if !amount.is_finite() || amount < 0.0 {
+                return Err(err());
+            }
+            minutes += match &rest[unit..unit + 1] {
+                "h" => amount * 60.0,
+                _ => amount,
+            };
+            rest = &rest[unit + 1..];
+        }
+        if minutes > u32::MAX as f64 {
+            return Err(err());
+        }
+        Ok(Effort::minutes(minutes.round() as u32))
+    }
+}
+
+/// A stretch of time spent on a task
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+pub struct Interval {
+    pub start: Deadline,
+    /// Unset while the timer runs
+    #[serde(default)]
+    pub end: Option<Deadline>,
+}
+
+impl Interval {
+    /// An interval whose timer starts at `start`
+    pub fn starting(start: Deadline) -> Interval {
+        Interval { start, end: None }
+    }
+
+    pub fn is_running(&self) -> bool {
+        self.end.is_none()
+    }
+
+    /// The time within the interval, a running one counting up to `now`
+    pub fn duration(&self, now: Deadline) -> chrono::Duration {
+        self.end.unwrap_or(now) - self.start
+    }
+
+    /// The time within the interval and `period`, by local day
+    pub fn days(&self, period: &Period, now: Deadline) -> Vec<(NaiveDate, chrono::Duration)> {
+        let start = period
+            .since
+            .map_or(self.start, |since| self.start.max(since));
+        let end = self.end.unwrap_or(now);
+        let end = period.until.map_or(end, |until| end.min(until));
+
+        let mut days = vec![];
+        let mut from = start;
+        while from < end {
+            let day = from.date_naive();
+            let midnight = day
+                .checked_add_days(Days::new(1))
+                .and_then(|d| localize(d.and_hms_opt(0, 0, 0)?))
+                .map_or(end, |m| m.min(end));
+            days.push((day, midnight - from));
+            from = midnight;
+        }
+        days
+    }
+}
+
+/// A span of time to report on, either end open; intervals are cut at its ends
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct Period {
+    pub since: Option<Deadline>,
+    pub until: Option<Deadline>,
+}
+
+/// What the time in a report is summed up by
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Grouping {
+    Project,
+    /// A task with several tags counts towards each of them
+    Tag,
+    /// The local day the time was spent on
+    Day,
+    Task,
+}
+
+#[derive(thiserror::Error, Debug, PartialEq, Eq)]
+#[error("unknown grouping `{}`: expected project, tag, day or task", .0)]
+pub struct GroupingParseError(String);
+
+impl FromStr for Grouping {
+    type Err = GroupingParseError;
+    fn from_str(s: &str) -> Result<Self, GroupingParseError> {
+        match s.to_lowercase().as_str() {
+            "project" => Ok(Grouping::Project),
+            "tag" => Ok(Grouping::Tag),
+            "day" => Ok(Grouping::Day),
+            "task" => Ok(Grouping::Task),
+            _ => Err(GroupingParseError(s.to_string())),
+        }
+    }
+}
+
+impl fmt::Display for Grouping {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Grouping::Project => write!(f, "project"),
+            Grouping::Tag => write!(f, "tag"),
+            Grouping::Day => write!(f, "day"),
+            Grouping::Task => write!(f, "task"),
+        }
+    }
+}
+
+/// The time spent on some tasks, and what it was estimated to take
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct Line {
+    pub key: String,
+    pub spent: Effort,
+    /// The estimates of the tasks which have one, if any does
+    pub estimate: Option<Effort>,
+    pub tasks: usize,
+}
+
+impl Line {
+    fn count(&mut self, spent: chrono::Duration, estimate: Option<Effort>) {
+        self.spent = self.spent + Effort::of(spent);
+        if let Some(estimate) = estimate {
+            self.estimate = Some(self.estimate.unwrap_or_default() + estimate);
+        }
+        self.tasks += 1;
+    }
+}
+
+/// The time spent on tasks in a period, summed up
+///
+/// Only tasks with time spent in the period are counted. Estimates are of whole tasks,
+/// so they are left out of reports by day.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Report {
+    pub grouping: Grouping,
+    /// In the order of their keys, which for days is the order of the days
+    pub lines: Vec<Line>,
+    /// Every task counted, once
+    pub total: Line,
+}
+
+/// The key of the lines of untagged tasks, when grouping by tag
+pub const UNTAGGED: &str = "(untagged)";
+
+impl Report {
+    pub fn new<'a, I>(projects: I, grouping: Grouping, period: &Period, now: Deadline) -> Report
+    where
+        I: IntoIterator<Item = &'a Project>,
+    {
+        let mut lines: BTreeMap<String, Line> = BTreeMap::new();
+        let mut total = Line {
+            key: "total".to_string(),
+            ..Line::default()
+        };
+        let mut count = |key: &str, spent, estimate| {
+            lines
+                .entry(key.to_string())
+                .or_insert_with(|| Line {
+                    key: key.to_string(),
+                    ..Line::default()
+                })
+                .count(spent, estimate)
+        };
+
+        for project in projects {
+            let id_prefix_len = project.id_prefix_len();
+            for task in project.tasks() {
+                let days = spent_by_day(task, period, now);
+                if days.is_empty() {
+                    continue;
+                }
+                let spent = days.values().copied().sum();
+                match grouping {
+                    Grouping::Project => count(&project.name, spent, task.estimate),
+                    Grouping::Tag if task.tags.is_empty() => count(UNTAGGED, spent, task.estimate),
+                    Grouping::Tag => {
+                        for tag in &task.tags {
+                            count(tag, spent, task.estimate)
+                        }
+                    }
+                    Grouping::Day => {
+                        for (day, spent) in &days {
+                            count(&day.format("%F %a").to_string(), *spent, None)
+                        }
+                    }
+                    Grouping::Task => {
+                        let key = format!(
+                            "[{}] {}: {}",
+                            task.id_prefix(id_prefix_len),
+                            project.name,
+                            task.description
+                        );
+                        count(&key, spent, task.estimate)
+                    }
+                }
+                let estimate = task.estimate.filter(|_| grouping != Grouping::Day);
+                total.count(spent, estimate);
+            }
+        }
+
+        Report {
+            grouping,
+            lines: lines.into_values().collect(),
+            total,
+        }
+    }
+
+    /// Write the report as a table, with how much of the estimates was spent
+    pub fn write<W: Write>(&self, out: &mut W) -> io::Result<()> {
+        let width = self
+            .lines
+            .iter()
+            .chain([&self.total])
+            .map(|l| l.key.chars().count())
+            .max()
+            .unwrap_or(0)
+            .max(self.grouping.to_string().len());
+        writeln!(
+            out,
+            "{:<width$}  {:>8}  {:>8}  {:>5}  {:>5}",
+            self.grouping.to_string(),
+            "spent",
+            "estimate",
+            "used",
+            "tasks"
+        )?;
+        for line in self.lines.iter().chain([&self.total]) {
+            let (estimate, used) = match line.estimate {
+                Some(e) if e.as_minutes() > 0 => (
+                    e.to_string(),
+                    format!(
+                        "{}%",
+                        (line.spent.as_minutes() as u64 * 100 / e.as_minutes() as u64)
+                    ),
+                ),
+                Some(e) => (e.to_string(), "-".to_string()),
+                None => ("-".to_string(), "-".to_string()),
+            };
+            writeln!(
+                out,
+                "{:<width$}  {:>8}  {:>8}  {:>5}  {:>5}",
+                line.key,
+                line.spent.to_string(),
+                estimate,
+                used,
+                line.tasks
+            )?;
+        }
+        Ok(())
+    }
+}
+
+/// The time spent on `task` in `period`, by local day
+fn spent_by_day(
+    task: &Task,
+    period: &Period,
+    now: Deadline,
+) -> BTreeMap<NaiveDate, chrono::Duration> {
+    let mut days: BTreeMap<NaiveDate, chrono::Duration> = BTreeMap::new();
+    for (day, spent) in task.time.iter().flat_map(|i| i.days(period, now)) {
+        *days.entry(day).or_insert_with(chrono::Duration::zero) += spent;
+    }
+    days
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::commands::task::{parse_deadline, TaskBuilder};
+
+    fn at(s: &str) -> Deadline {
+        parse_deadline(s).unwrap()
+    }
+
+    fn interval(start: &str, end: &str) -> Interval {
+        Interval {
+            start: at(start),
+            end: Some(at(end)),
+        }
+    }
+
+    #[test]
+    fn effort() {
+        assert_eq!("45m".parse::<Effort>(), Ok(Effort::minutes(45)));
+        assert_eq!("1h30m".parse::<Effort>(), Ok(Effort::minutes(90)));
+        assert_eq!("2H 5m".parse::<Effort>(), Ok(Effort::minutes(125)));
+        assert_eq!("1.5h".parse::<Effort>(), Ok(Effort::minutes(90)));
+        assert!("".parse::<Effort>().is_err());
+        assert!("90".parse::<Effort>().is_err());
+        assert!("-1h".parse::<Effort>().is_err());
+        assert!("1d".parse::<Effort>().is_err());
+
+        assert_eq!(Effort::minutes(45).to_string(), "45m");
+        assert_eq!(Effort::minutes(120).to_string(), "2h");
+        assert_eq!(Effort::minutes(125).to_string(), "2h05m");
+        assert_eq!(Effort::of(chrono::Duration::seconds(-5)), Effort::default());
+    }
+
+    #[test]
+    fn days() {
+        let now = at("2022-05-03 12:00");
+        let day = |s: &str| NaiveDate::parse_from_str(s, "%F").unwrap();
+        let late = interval("2022-05-01 23:00", "2022-05-02 01:30");
+        assert_eq!(late.duration(now), chrono::Duration::minutes(150));
+        assert_eq!(
+            late.days(&Period::default(), now),
+            vec![
+                (day("2022-05-01"), chrono::Duration::hours(1)),
+                (day("2022-05-02"), chrono::Duration::minutes(90)),
+            ]
+        );
+
+        let period = Period {
+            since: Some(at("2022-05-02 01:00")),
+            until: None,
+        };
+        assert_eq!(
+            late.days(&period, now),
+            vec![(day("2022-05-02"), chrono::Duration::minutes(30))]
+        );
+
+        let running = Interval::starting(at("2022-05-03 11:00"));
+        assert!(running.is_running());
+        assert_eq!(
+            running.days(&Period::default(), now),
+            vec![(day("2022-05-03"), chrono::Duration::hours(1))]
+        );
+    }
+
+    #[test]
+    fn report() {
+        let now = at("2022-05-03 12:00");
+        let mut a = TaskBuilder::new("a".to_string())
+            .tag("work".to_string())
+            .tag("review".to_string())
+            .estimate(Effort::minutes(60))
+            .build();
+        a.time = vec![
+            interval("2022-05-01 10:00", "2022-05-01 11:00"),
+            interval("2022-05-02 10:00", "2022-05-02 10:30"),
+        ];
+        let mut b = TaskBuilder::new("b".to_string()).build();
+        b.time = vec![Interval::starting(at("2022-05-03 11:45"))];
+        let idle = TaskBuilder::new("idle".to_string())
+            .estimate(Effort::minutes(10))
+            .build();
+
+        let mut first = Project::new("first".to_string());
+        first.push(a);
+        first.push(idle);
+        let mut second = Project::new("second".to_string());
+        second.push(b);
+        let projects = [first, second];
+        let report = |grouping| Report::new(&projects, grouping, &Period::default(), now);
+
+        let by_project = report(Grouping::Project);
+        assert_eq!(
+            by_project.lines,
+            vec![
+                Line {
+                    key: "first".to_string(),
+                    spent: Effort::minutes(90),
+                    estimate: Some(Effort::minutes(60)),
+                    tasks: 1,
+                },
+                Line {
+                    key: "second".to_string(),
+                    spent: Effort::minutes(15),
+                    estimate: None,
+                    tasks: 1,
+                },
+            ]
+        );
+        assert_eq!(by_project.total.spent, Effort::minutes(105));
+        assert_eq!(by_project.total.estimate, Some(Effort::minutes(60)));
+        assert_eq!(by_project.total.tasks, 2);
+
+        let keys =
+            |report: Report| -> Vec<String> { report.lines.into_iter().map(|l| l.key).collect() };
+        assert_eq!(
+            keys(report(Grouping::Tag)),
+            vec![UNTAGGED, "review", "work"]
+        );
+        assert_eq!(
+            keys(report(Grouping::Day)),
+            vec!["2022-05-01 Sun", "2022-05-02 Mon", "2022-05-03 Tue"]
+        );
+        assert_eq!(report(Grouping::Day).total.estimate, None);
+
+        let period = Period {
+            since: Some(at("2022-05-02")),
+            until: Some(at("2022-05-03")),
+        };
+        let recent = Report::new(&projects, Grouping::Task, &period, now);
+        assert_eq!(recent.lines.len(), 1);
+        assert!(recent.lines[0].key.ends_with("] first: a"));
+        assert_eq!(recent.total.spent, Effort::minutes(30));
+    }
+
+    #[test]
+    fn write() {
+        let report = Report {
+            grouping: Grouping::Tag,
+            lines: vec![Line {
+                key: "work".to_string(),
+                spent: Effort::minutes(90),
+                estimate: Some(Effort::minutes(60)),
+                tasks: 2,
+            }],
+            total: Line {
+                key: "total".to_string(),
+                spent: Effort::minutes(90),
+                estimate: None,
+                tasks: 2,
+            },
+        };
+        let mut out = vec![];
+        report.write(&mut out).unwrap();
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            "tag       spent  estimate   used  tasks\n\
+             work      1h30m        1h   150%      2\n\
+             total     1h30m         -      -      2\n"
+        );
+    }
+}
@@ -0,0 +1,817 @@
+// transaction.rs
+//
+// batch mutations over a task file
+
+use std::collections::BTreeSet;
+use std::path::{Path, PathBuf};
+
+use crate::commands::annotation::Annotation;
+use crate::commands::backend::{Backend, Storage};
+use crate::commands::change::{Change, Projects};
+use crate::commands::dependency::Graph;
+use crate::commands::error::RustaskError;
+use crate::commands::project::Project;
+use crate::commands::storage::{self, FileLock, LockMode};
+use crate::commands::task::{self, Task, TaskEdit};
+use crate::commands::timesheet::Interval;
+
+/// How [`Transaction::complete_tasks`] completes tasks
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct Completion {
+    /// Also complete the pending subtasks of the tasks, at any depth
+    pub cascade: bool,
+    /// Complete tasks even if they wait on unfinished ones
+    pub force: bool,
+}
+
+/// A batch of mutations over a task file
+///
+/// The task file is locked exclusively until the transaction ends, and the projects
+/// are loaded as the mutations need them.
+/// Nothing is written until [`Transaction::commit`]: if any step fails, dropping the
+/// transaction leaves the file untouched.
+/// A committed transaction is recorded in the journal as a single operation.
+pub struct Transaction {
+    path: PathBuf,
+    storage: Box<dyn Storage>,
+    /// the projects loaded so far, with every mutation made so far applied
+    projects: Projects,
+    /// whether every project was loaded, to follow dependencies
+    all_loaded: bool,
+    changes: Vec<Change>,
+    operations: Vec<String>,
+    _lock: FileLock,
+}
+
+impl Transaction {
+    /// Lock the task file, stored in `backend`
+    pub fn begin(path: &Path, backend: Backend) -> Result<Transaction, RustaskError> {
+        let lock = super::lock(path, LockMode::Exclusive)?;
+        Ok(Transaction {
+            path: path.to_path_buf(),
+            storage: backend.open(path),
+            projects: Projects::new(),
+            all_loaded: false,
+            changes: vec![],
+            operations: vec![],
+            _lock: lock,
+        })
+    }
+
+    /// The project named `name`, if it exists, with every mutation made so far applied
+    pub fn project(&mut self, name: &str) -> Result<Option<&Project>, RustaskError> {
+        if !self.projects.contains_key(name) {
+            let project = self.storage.load_project(name)?;
+            self.projects.insert(name.to_string(), project);
+        }
+        Ok(self.projects[name].as_ref())
+    }
+
+    /// Load every project not loaded yet
+    fn load_all(&mut self) -> Result<(), RustaskError> {
+        if !self.all_loaded {
+            for project in self.storage.load()? {
+                self.projects
+                    .entry(project.name.clone())
+                    .or_insert(Some(project));
+            }
+            self.all_loaded = true;
+        }
+        Ok(())
+    }
+
+    /// Every project, with every mutation made so far applied, by their tasks
+    fn graph(&mut self) -> Result<Graph<'_>, RustaskError> {
+        self.load_all()?;
+        Ok(Graph::new(self.projects.values().flatten()))
+    }
+
+    /// The task a timer is running on, in any project, with the name of its project
+    fn running(&mut self) -> Result<Option<(String, Task)>, RustaskError> {
+        self.load_all()?;
+        Ok(self.projects.values().flatten().find_map(|p| {
+            p.tasks()
+                .iter()
+                .find(|t| t.is_running())
+                .map(|t| (p.name.clone(), t.clone()))
+        }))
+    }
+
+    /// The full ids of the tasks, in any project, whose ids start with `prefixes`
+    fn resolve(&mut self, prefixes: &BTreeSet<String>) -> Result<BTreeSet<String>, RustaskError> {
+        if prefixes.is_empty() {
+            return Ok(BTreeSet::new());
+        }
+        let graph = self.graph()?;
+        prefixes.iter().map(|p| graph.resolve(p)).collect()
+    }
+
+    /// Fail if `task`, as it would be, depends on itself
+    fn check_cycle(&mut self, task: &Task) -> Result<(), RustaskError> {
+        if task.depends_on.is_empty() {
+            return Ok(());
+        }
+        match self.graph()?.cycle(task) {
+            Some(cycle) => Err(RustaskError::DependencyCycle(cycle.join(" -> "))),
+            None => Ok(()),
+        }
+    }
+
+    fn existing(&mut self, name: &str) -> Result<&Project, RustaskError> {
+        self.project(name)?
+            .ok_or_else(|| RustaskError::ProjectNotFound(name.to_string()))
+    }
+
+    /// Apply a change to the loaded projects, which must hold every project it names
+    fn record(&mut self, change: Change) {
+        let applied = change.apply(&mut self.projects);
+        debug_assert!(applied, "{:?} does not apply", change);
+        self.changes.push(change);
+    }
+
+    /// Renames a project if it exists and if the other name is not taken
+    pub fn rename(&mut self, project: String, name: String) -> Result<(), RustaskError> {
+        self.existing(&project)?;
+        if self.project(&name)?.is_some() {
+            return Err(RustaskError::ProjectNameTaken(name));
+        }
+        self.operations
+            .push(format!("rename project {} to {}", project, name));
+        self.record(Change::Project {
+            before: Some(project),
+            after: Some(name),
+        });
+        Ok(())
+    }
+
+    /// Add a new task, creating the project if needed
+    ///
+    /// A subtask is added next to its parent, whose id may be given as a unique prefix:
+    /// the project must then exist. So may the ids of the tasks it depends on.
+    pub fn add_task(&mut self, mut task: Task, name: String) -> Result<(), RustaskError> {
+        if let Some(parent) = &task.parent {
+            task.parent = Some(self.existing(&name)?.task(parent)?.id.clone());
+        }
+        task.depends_on = self.resolve(&task.depends_on)?;
+        self.check_cycle(&task)?;
+        self.operations
+            .push(format!("add \"{}\" to {}", task.description, name));
+        self.put_task(task, name)
+    }
+
+    fn put_task(&mut self, task: Task, name: String) -> Result<(), RustaskError> {
+        if self.project(&name)?.is_none() {
+            self.record(Change::Project {
+                before: None,
+                after: Some(name.clone()),
+            });
+        }
+        self.record(Change::Task {
+            project: name,
+            before: None,
+            after: Some(task),
+        });
+        Ok(())
+    }
+
+    /// Remove a task, given (a unique prefix of) its id
+    ///
+    /// Projects left without tasks are removed. Tasks with subtasks are not removed;
+    /// tasks waiting on the removed one stop waiting on it.
+    pub fn remove_task(&mut self, id: &str, name: String) -> Result<Task, RustaskError> {
+        let project = self.existing(&name)?;
+        let task = project.task(id)?;
+        if project.subtasks(&task.id).next().is_some() {
+            return Err(RustaskError::TaskHasSubtasks(id.to_string()));
+        }
+        let task = self.take_task(id, &name)?;
+
+        let waiting: Vec<(String, Task)> = self
+            .graph()?
+            .waiting_on(&task.id)
+            .into_iter()
+            .map(|(project, t)| (project.to_string(), t.clone()))
+            .collect();
+        for (project, before) in waiting {
+            let mut after = before.clone();
+            after.depends_on.remove(&task.id);
+            self.record(Change::Task {
+                project,
+                before: Some(before),
+                after: Some(after),
+            });
+        }
+        self.operations
+            .push(format!("remove \"{}\" from {}", task.description, name));
+        Ok(task)
+    }
+
+    fn take_task(&mut self, id: &str, name: &str) -> Result<Task, RustaskError> {
+        let task = self.existing(name)?.task(id)?.clone();
+        self.record(Change::Task {
+            project: name.to_string(),
+            before: Some(task.clone()),
+            after: None,
+        });
+        if self.existing(name)?.is_empty() {
+            self.record(Change::Project {
+                before: Some(name.to_string()),
+                after: None,
+            });
+        }
+        Ok(task)
+    }
+
+    /// Mark a task, given (a unique prefix of) its id, as completed now
+    ///
+    /// The task is kept in the project, to be shown in the history.
+    /// If the task is recurring, its next occurrence is added to the project.
+    /// Tasks waiting on unfinished ones are not completed.
+    pub fn complete_task(&mut self, id: &str, name: String) -> Result<Task, RustaskError> {
+        self.complete(id, name, false)
+    }
+
+    /// Complete several tasks of a project, as set in `completion`
+    ///
+    /// Returns every task completed, subtasks included.
+    pub fn complete_tasks(
+        &mut self,
+        ids: &[&str],
+        name: String,
+        completion: Completion,
+    ) -> Result<Vec<Task>, RustaskError> {
+        let mut tasks = ids
+            .iter()
+            .map(|id| self.complete(id, name.clone(), completion.force))
+            .collect::<Result<Vec<_>, _>>()?;
+        if completion.cascade {
+            for id in ids {
+                tasks.extend(self.complete_subtasks(id, name.clone(), completion.force)?);
+            }
+        }
+        Ok(tasks)
+    }
+
+    fn complete(&mut self, id: &str, name: String, force: bool) -> Result<Task, RustaskError> {
+        let before = self.existing(&name)?.task(id)?.clone();
+        if before.is_done() {
+            return Err(RustaskError::TaskAlreadyDone(id.to_string()));
+        }
+        if !force && !before.depends_on.is_empty() {
+            let blockers: Vec<String> = self
+                .graph()?
+                .blockers(&before)
+                .iter()
+                .map(|t| format!("{} ({})", t.id, t.description))
+                .collect();
+            if !blockers.is_empty() {
+                return Err(RustaskError::TaskBlocked(
+                    id.to_string(),
+                    blockers.join(", "),
+                ));
+            }
+        }
+
+        let now = task::now_deadline();
+        let mut task = before.clone();
+        task.completed = Some(now);
+        task.stop_timer(now);
+        self.operations
+            .push(format!("done \"{}\" in {}", task.description, name));
+        self.record(Change::Task {
+            project: name.clone(),
+            before: Some(before),
+            after: Some(task.clone()),
+        });
+        if let Some(next) = task.next_occurrence(now) {
+            self.record(Change::Task {
+                project: name,
+                before: None,
+                after: Some(next),
+            });
+        }
+        Ok(task)
+    }
+
+    /// Complete the pending subtasks of a task, given (a unique prefix of) its id, at
+    /// any depth
+    fn complete_subtasks(
+        &mut self,
+        id: &str,
+        name: String,
+        force: bool,
+    ) -> Result<Vec<Task>, RustaskError> {
+        let project = self.existing(&name)?;
+        let pending: Vec<String> = project
+            .descendants(&project.task(id)?.id)
+            .into_iter()
+            .filter(|t| !t.is_done())
+            .map(|t| t.id.clone())
+            .collect();
+        pending
+            .iter()
+            .map(|id| self.complete(id, name.clone(), force))
+            .collect()
+    }
+
+    /// Add a note, written now, to a task given (a unique prefix of) its id
+    pub fn annotate_task(
+        &mut self,
+        id: &str,
+        name: String,
+        text: String,
+    ) -> Result<Task, RustaskError> {
+        let before = self.existing(&name)?.task(id)?.clone();
+        let mut task = before.clone();
+        task.annotations
+            .push(Annotation::new(text, task::now_deadline()));
+        self.operations
+            .push(format!("annotate \"{}\" in {}", task.description, name));
+        self.record(Change::Task {
+            project: name,
+            before: Some(before),
+            after: Some(task.clone()),
+        });
+        Ok(task)
+    }
+
+    /// Start a timer on a task, given (a unique prefix of) its id
+    ///
+    /// Only one timer runs at a time: the running one, on any task, is stopped, and that
+    /// task returned with the name of its project.
+    pub fn start_timer(
+        &mut self,
+        id: &str,
+        name: String,
+    ) -> Result<Option<(String, Task)>, RustaskError> {
+        let before = self.existing(&name)?.task(id)?.clone();
+        if before.is_done() {
+            return Err(RustaskError::TaskAlreadyDone(id.to_string()));
+        }
+        if before.is_running() {
+            return Err(RustaskError::TimerRunning(id.to_string()));
+        }
+
+        let now = task::now_deadline();
+        let stopped = self
+            .running()?
+            .map(|(project, task)| self.stop(project, task, now));
+        let mut task = before.clone();
+        task.time.push(Interval::starting(now));
+        self.operations
+            .push(format!("start \"{}\" in {}", task.description, name));
+        self.record(Change::Task {
+            project: name,
+            before: Some(before),
+            after: Some(task),
+        });
+        Ok(stopped)
+    }
+
+    /// Stop the running timer, returning the task it ran on with the name of its project
+    pub fn stop_timer(&mut self) -> Result<(String, Task), RustaskError> {
+        let (project, task) = self.running()?.ok_or(RustaskError::NoTimerRunning)?;
+        Ok(self.stop(project, task, task::now_deadline()))
+    }
+
+    fn stop(&mut self, project: String, before: Task, now: task::Deadline) -> (String, Task) {
+        let mut task = before.clone();
+        task.stop_timer(now);
+        self.operations
+            .push(format!("stop \"{}\" in {}", task.description, project));
+        self.record(Change::Task {
+            project: project.clone(),
+            before: Some(before),
+            after: Some(task.clone()),
+        });
+        (project, task)
+    }
+
+    /// Move a task, given (a unique prefix of) its id, to another project
+    ///
+    /// Its subtasks are moved along; a subtask moved on its own is no longer one.
+    pub fn move_task(
+        &mut self,
+        old_project: String,
+        id: &str,
+        new_project: String,
+    ) -> Result<(), RustaskError> {
+        let project = self.existing(&old_project)?;
+        let subtasks: Vec<String> = project
+            .descendants(&project.task(id)?.id)
+            .into_iter()
+            .map(|t| t.id.clone())
+            .collect();
+        let mut task = self.take_task(id, &old_project)?;
+        self.operations.push(format!(
+            "move \"{}\" from {} to {}",
+            task.description, old_project, new_project
+        ));
+        let subtasks = subtasks
+            .iter()
+            .map(|id| self.take_task(id, &old_project))
+            .collect::<Result<Vec<_>, _>>()?;
+        task.parent = None;
+        self.put_task(task, new_project.clone())?;
+        for subtask in subtasks {
+            self.put_task(subtask, new_project.clone())?;
+        }
+        Ok(())
+    }
+
+    /// Edit a task, given (a unique prefix of) its id
+    pub fn edit_task(
+        &mut self,
+        id: &str,
+        name: String,
+        mut edit: TaskEdit,
+    ) -> Result<(), RustaskError> {
+        let before = self.existing(&name)?.task(id)?.clone();
+        edit.add_dependencies = self.resolve(&edit.add_dependencies)?;
+        edit.remove_dependencies = edit
+            .remove_dependencies
+            .iter()
+            .map(|prefix| {
+                let mut matches = before.depends_on.iter().filter(|d| d.starts_with(prefix));
+                match (matches.next(), matches.next()) {
+                    (Some(id), None) => Ok(id.clone()),
+                    (Some(_), Some(_)) => Err(RustaskError::AmbiguousTaskId(prefix.clone())),
+                    (None, _) => Err(RustaskError::TaskNotFound(prefix.clone())),
+                }
+            })
+            .collect::<Result<_, _>>()?;
+        let mut task = before.clone();
+        edit.apply(&mut task);
+        self.check_cycle(&task)?;
+        self.operations
+            .push(format!("edit \"{}\" in {}", before.description, name));
+        self.record(Change::Task {
+            project: name,
+            before: Some(before),
+            after: Some(task),
+        });
+        Ok(())
+    }
+
+    /// Write every mutation to the task file, record them in the journal and release the lock
+    pub fn commit(self) -> Result<(), RustaskError> {
+        if self.operations.is_empty() {
+            return Ok(());
+        }
+
+        let mut journal = storage::load_journal(&self.path)?;
+        self.storage.apply(&self.changes)?;
+        journal.record(
+            task::now_deadline(),
+            self.operations.join("; "),
+            self.changes,
+        );
+        storage::store_journal(&self.path, &journal)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::commands::task::TaskBuilder;
+    use std::fs;
+
+    fn setup(path: &Path) -> Task {
+        let mut p = Project::new("proj".to_string());
+        let task = TaskBuilder::new("task".to_string()).build();
+        p.push(task.clone());
+        Backend::Json.open(path).store(&[p]).unwrap();
+        task
+    }
+
+    fn cleanup(path: &Path) {
+        for entry in fs::read_dir(".").unwrap() {
+            let entry = entry.unwrap().path();
+            if entry
+                .to_str()
+                .unwrap()
+                .starts_with(&format!("./{}", path.display()))
+            {
+                fs::remove_file(entry).unwrap();
+            }
+        }
+    }
+
+    #[test]
+    fn commit_batch() {
+        let path = Path::new("test_file_tx_commit");
+        let task = setup(path);
+
+        let mut tx = Transaction::begin(path, Backend::Json).unwrap();
+        tx.move_task("proj".to_string(), &task.id, "other".to_string())
+            .unwrap();
+        tx.add_task(
+            TaskBuilder::new("new".to_string()).build(),
+            "proj".to_string(),
+        )
+        .unwrap();
+        tx.rename("other".to_string(), "renamed".to_string())
+            .unwrap();
+        tx.commit().unwrap();
+
+        let projects = Backend::Json.open(path).load().unwrap();
+        assert_eq!(projects.len(), 2);
+        assert_eq!(projects[0].name, "proj");
+        assert_eq!(projects[0].tasks()[0].description, "new");
+        assert_eq!(projects[1].name, "renamed");
+        assert_eq!(projects[1].tasks()[0], task);
+        cleanup(path);
+    }
+
+    #[test]
+    fn failed_step_leaves_file_untouched() {
+        let path = Path::new("test_file_tx_rollback");
+        setup(path);
+        let before = Backend::Json.open(path).load().unwrap();
+
+        let mut tx = Transaction::begin(path, Backend::Json).unwrap();
+        tx.add_task(
+            TaskBuilder::new("new".to_string()).build(),
+            "proj".to_string(),
+        )
+        .unwrap();
+        assert_eq!(
+            tx.move_task("proj".to_string(), "nope", "other".to_string())
+                .unwrap_err(),
+            RustaskError::TaskNotFound("nope".to_string())
+        );
+        drop(tx);
+
+        assert_eq!(Backend::Json.open(path).load().unwrap(), before);
+        cleanup(path);
+    }
+
+    #[test]
+    fn complete_keeps_task() {
+        let path = Path::new("test_file_tx_complete");
+        let task = setup(path);
+
+        let mut tx = Transaction::begin(path, Backend::Json).unwrap();
+        let done = tx.complete_task(&task.id, "proj".to_string()).unwrap();
+        assert!(done.is_done());
+        assert_eq!(
+            tx.complete_task(&task.id, "proj".to_string()).unwrap_err(),
+            RustaskError::TaskAlreadyDone(task.id.clone())
+        );
+        tx.commit().unwrap();
+
+        let projects = Backend::Json.open(path).load().unwrap();
+        assert_eq!(projects[0].tasks(), &vec![done]);
+        cleanup(path);
+    }
+
+    #[test]
+    fn complete_recurring() {
+        use crate::commands::recurrence::{Recurrence, Rule};
+
+        let path = Path::new("test_file_tx_recurring");
+        setup(path);
+        let deadline = task::now_deadline() + chrono::Duration::hours(1);
+        let task = TaskBuilder::new("daily".to_string())
+            .deadline(deadline)
+            .recurrence(Recurrence::new(Rule::Daily))
+            .build();
+
+        let mut tx = Transaction::begin(path, Backend::Json).unwrap();
+        tx.add_task(task.clone(), "proj".to_string()).unwrap();
+        tx.complete_task(&task.id, "proj".to_string()).unwrap();
+        tx.commit().unwrap();
+
+        let projects = Backend::Json.open(path).load().unwrap();
+        let dailies: Vec<&Task> = projects[0]
+            .tasks()
+            .iter()
+            .filter(|t| t.description == "daily")
+            .collect();
+        assert_eq!(dailies.len(), 2);
+        let next = dailies.iter().find(|t| !t.is_done()).unwrap();
+        assert_eq!(next.deadline, task.recurrence.unwrap().rule.after(deadline));
+        cleanup(path);
+    }
+
+    #[test]
+    fn subtasks() {
+        let path = Path::new("test_file_tx_subtasks");
+        let task = setup(path);
+        let child = TaskBuilder::new("child".to_string())
+            .parent(task.id[..4].to_string())
+            .build();
+        let grandchild = TaskBuilder::new("grandchild".to_string())
+            .parent(child.id.clone())
+            .build();
+
+        let mut tx = Transaction::begin(path, Backend::Json).unwrap();
+        tx.add_task(child.clone(), "proj".to_string()).unwrap();
+        tx.add_task(grandchild.clone(), "proj".to_string()).unwrap();
+        assert_eq!(
+            tx.add_task(child.clone(), "other".to_string()).unwrap_err(),
+            RustaskError::ProjectNotFound("other".to_string())
+        );
+        assert_eq!(
+            tx.remove_task(&child.id, "proj".to_string()).unwrap_err(),
+            RustaskError::TaskHasSubtasks(child.id.clone())
+        );
+        let done = tx
+            .complete_subtasks(&task.id, "proj".to_string(), false)
+            .unwrap();
+        assert_eq!(done.len(), 2);
+        tx.move_task("proj".to_string(), &child.id, "other".to_string())
+            .unwrap();
+        tx.commit().unwrap();
+
+        let projects = Backend::Json.open(path).load().unwrap();
+        assert_eq!(projects[0].name, "other");
+        assert_eq!(projects[0].len(), 2);
+        assert!(projects[0].tasks().iter().all(Task::is_done));
+        assert_eq!(projects[0].get(&child.id).unwrap().parent, None);
+        assert_eq!(
+            projects[0].get(&grandchild.id).unwrap().parent,
+            Some(child.id.clone())
+        );
+        assert_eq!(projects[1].tasks(), &vec![task]);
+        cleanup(path);
+    }
+
+    #[test]
+    fn dependencies() {
+        let path = Path::new("test_file_tx_dependencies");
+        let task = setup(path);
+        let after = TaskBuilder::new("after".to_string())
+            .depends_on(task.id[..4].to_string())
+            .build();
+
+        let mut tx = Transaction::begin(path, Backend::Json).unwrap();
+        tx.add_task(after.clone(), "other".to_string()).unwrap();
+        assert_eq!(
+            tx.complete_task(&after.id, "other".to_string())
+                .unwrap_err(),
+            RustaskError::TaskBlocked(after.id.clone(), format!("{} (task)", task.id))
+        );
+        let edit = TaskEdit {
+            add_dependencies: [after.id[..6].to_string()].into(),
+            ..TaskEdit::default()
+        };
+        assert_eq!(
+            tx.edit_task(&task.id, "proj".to_string(), edit)
+                .unwrap_err(),
+            RustaskError::DependencyCycle(format!("{0} -> {1} -> {0}", task.id, after.id))
+        );
+        let force = Completion {
+            force: true,
+            ..Completion::default()
+        };
+        tx.complete_tasks(&[&after.id], "other".to_string(), force)
+            .unwrap();
+        tx.remove_task(&task.id, "proj".to_string()).unwrap();
+        tx.commit().unwrap();
+
+        let projects = Backend::Json.open(path).load().unwrap();
+        assert_eq!(projects.len(), 1);
+        assert!(projects[0].tasks()[0].is_done());
+        assert!(projects[0].tasks()[0].depends_on.is_empty());
+        cleanup(path);
+    }
+
+    #[test]
+    fn timers() {
+        let path = Path::new("test_file_tx_timers");
+        let task = setup(path);
+        let other = TaskBuilder::new("other".to_string()).build();
+
+        let mut tx = Transaction::begin(path, Backend::Json).unwrap();
+        assert_eq!(tx.stop_timer().unwrap_err(), RustaskError::NoTimerRunning);
+        tx.add_task(other.clone(), "other".to_string()).unwrap();
+        assert_eq!(tx.start_timer(&task.id, "proj".to_string()), Ok(None));
+        assert_eq!(
+            tx.start_timer(&task.id[..4], "proj".to_string())
+                .unwrap_err(),
+            RustaskError::TimerRunning(task.id[..4].to_string())
+        );
+        let (project, stopped) = tx
+            .start_timer(&other.id, "other".to_string())
+            .unwrap()
+            .unwrap();
+        assert_eq!(project, "proj");
+        assert!(!stopped.is_running());
+        assert_eq!(stopped.time.len(), 1);
+        tx.commit().unwrap();
+
+        let mut tx = Transaction::begin(path, Backend::Json).unwrap();
+        let done = tx.complete_task(&other.id, "other".to_string()).unwrap();
+        assert!(!done.is_running());
+        assert_eq!(tx.stop_timer().unwrap_err(), RustaskError::NoTimerRunning);
+        assert_eq!(
+            tx.start_timer(&other.id, "other".to_string()).unwrap_err(),
+            RustaskError::TaskAlreadyDone(other.id.clone())
+        );
+        tx.start_timer(&task.id, "proj".to_string()).unwrap();
+        let (_, stopped) = tx.stop_timer().unwrap();
+        assert_eq!(stopped.time.len(), 2);
+        tx.commit().unwrap();
+
+        let journal = storage::load_journal(path).unwrap();
+        assert_eq!(
+            journal.entries().next().unwrap().operation,
+            "done \"other\" in other; start \"task\" in proj; stop \"task\" in proj"
+        );
+        let projects = Backend::Json.open(path).load().unwrap();
+        assert!(projects
+            .iter()
+            .flat_map(|p| p.tasks())
+            .all(|t| !t.is_running()));
+        cleanup(path);
+    }
+
+    #[test]
+    fn annotate() {
+        let path = Path::new("test_file_tx_annotate");
+        let task = setup(path);
+
+        let mut tx = Transaction::begin(path, Backend::Json).unwrap();
+        tx.annotate_task(&task.id[..4], "proj".to_string(), "first".to_string())
+            .unwrap();
+        let annotated = tx
+            .annotate_task(&task.id, "proj".to_string(), "second".to_string())
+            .unwrap();
+        assert_eq!(
+            tx.annotate_task("nope", "proj".to_string(), "third".to_string())
+                .unwrap_err(),
+            RustaskError::TaskNotFound("nope".to_string())
+        );
+        tx.commit().unwrap();
+
+        let notes: Vec<&str> = annotated
+            .annotations
+            .iter()
+            .map(|a| a.text.as_str())
+            .collect();
+        assert_eq!(notes, vec!["first", "second"]);
+        let projects = Backend::Json.open(path).load().unwrap();
+        assert_eq!(projects[0].tasks(), &vec![annotated]);
+        cleanup(path);
+    }
+
+    #[test]
+    fn commit_records_operation() {
+        let path = Path::new("test_file_tx_journal");
+        let task = setup(path);
+
+        let mut tx = Transaction::begin(path, Backend::Json).unwrap();
+        let done = tx.complete_task(&task.id, "proj".to_string()).unwrap();
+        tx.rename("proj".to_string(), "renamed".to_string())
+            .unwrap();
+        tx.commit().unwrap();
+
+        let journal = storage::load_journal(path).unwrap();
+        let entry = journal.entries().next().unwrap();
+        assert_eq!(
+            entry.operation,
+            "done \"task\" in proj; rename project proj to renamed"
+        );
+        assert_eq!(
+            entry.changes(),
+            &[
+                Change::Task {
+                    project: "proj".to_string(),
+                    before: Some(task),
+                    after: Some(done),
+                },
+                Change::Project {
+                    before: Some("proj".to_string()),
+                    after: Some("renamed".to_string()),
+                },
+            ]
+        );
+        cleanup(path);
+    }
+
+    #[test]
+    fn rename_taken() {
+        let path = Path::new("test_file_tx_rename");
+        setup(path);
+
+        let mut tx = Transaction::begin(path, Backend::Json).unwrap();
+        tx.add_task(
+            TaskBuilder::new("new".to_string()).build(),
+            "other".to_string(),
+        )
+        .unwrap();
+        assert_eq!(
+            tx.rename("proj".to_string(), "other".to_string())
+                .unwrap_err(),
+            RustaskError::ProjectNameTaken("other".to_string())
+        );
+        assert_eq!(
+            tx.rename("nope".to_string(), "another".to_string())
+                .unwrap_err(),
+            RustaskError::ProjectNotFound("nope".to_string())
+        );
+        drop(tx);
+        cleanup(path);
+    }
+}
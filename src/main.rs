@@ -1,13 +1,156 @@
-mod commands;
-use clap::{App, Arg, SubCommand};
-use commands::task;
-use std::{env, path::Path};
+use clap::{App, Arg, ArgMatches, SubCommand};
+use rustask::commands::annotation::{self, Annotation};
+use rustask::commands::backend::Backend;
+use rustask::commands::config::{self, Config};
+use rustask::commands::dependency::Graph;
+use rustask::commands::error::RustaskError;
+use rustask::commands::filter::Filter;
+use rustask::commands::location;
+use rustask::commands::output::{self, Format, Listing};
+use rustask::commands::query;
+use rustask::commands::recurrence::{self, Recurrence};
+use rustask::commands::selection::{self, Selection};
+use rustask::commands::timesheet::{Effort, Grouping, Period};
+use rustask::commands::transaction::Completion;
+use rustask::commands::{self, task, TaskStore};
+use std::{
+    collections::BTreeSet,
+    env,
+    ffi::OsString,
+    io,
+    path::{Path, PathBuf},
+    time::Duration,
+};
 
-use color_eyre::eyre::Result;
+use color_eyre::eyre::{eyre, Result};
 
-fn main() -> Result<()> {
-    color_eyre::install()?;
-    let matches = App::new("rustask")
+/// Parse the `repeat` and `until` arguments
+fn parse_recurrence(matches: &ArgMatches) -> Result<Option<Recurrence>> {
+    let rule = match matches.value_of("repeat") {
+        Some(r) => r.parse::<recurrence::Rule>()?,
+        None => return Ok(None),
+    };
+    let recurrence = Recurrence::new(rule);
+    Ok(Some(match matches.value_of("until") {
+        Some(until) => recurrence.until(task::parse_deadline(until)?),
+        None => recurrence,
+    }))
+}
+
+/// Parse the `tag`, `no-tag` and `where` arguments
+fn parse_filter(matches: &ArgMatches) -> Result<Filter> {
+    let tags = |name| {
+        matches
+            .values_of(name)
+            .into_iter()
+            .flatten()
+            .map(|t| t.trim_start_matches('+').to_string())
+            .collect()
+    };
+    let query = match matches.value_of("where") {
+        Some(q) => Some(query::parse_query(q)?),
+        None => None,
+    };
+    Ok(Filter {
+        tags: tags("tag"),
+        without_tags: tags("no-tag"),
+        query,
+        ..Filter::default()
+    })
+}
+
+/// Parse the `format` argument
+fn parse_format(matches: &ArgMatches) -> Result<Format> {
+    Ok(match matches.value_of("format") {
+        Some(f) => f.parse::<Format>()?,
+        None => Format::Text,
+    })
+}
+
+fn print(listings: &[Listing], format: Format) -> Result<()> {
+    output::write(&mut io::stdout().lock(), listings, format)?;
+    Ok(())
+}
+
+/// Pick tasks to work on, for `list`
+fn pick(matches: &ArgMatches, store: &TaskStore) -> Result<Vec<Listing>> {
+    let project = matches.value_of("project");
+    let selection = if matches.is_present("random") {
+        Selection::random()
+    } else if let Some(seed) = matches.value_of("seed") {
+        Selection::seeded(seed.parse::<u64>()?)
+    } else {
+        Selection::daily()
+    };
+
+    let mut probabilities = selection.probabilities.clone();
+    for p in matches.values_of("probability").into_iter().flatten() {
+        let (priority, probability) = selection::parse_probability(p)?;
+        probabilities.set(&priority, probability);
+    }
+
+    let filter = Filter {
+        hide_blocked: !matches.is_present("blocked"),
+        ..parse_filter(matches)?
+    };
+    Ok(store.pick(
+        project,
+        &filter,
+        &selection.probabilities(probabilities),
+        matches.is_present("explain"),
+    )?)
+}
+
+/// List every task matching the filter, for `listall`
+fn list_all(matches: &ArgMatches, store: &TaskStore) -> Result<Vec<Listing>> {
+    let filter = Filter {
+        include_completed: matches.is_present("completed"),
+        ..parse_filter(matches)?
+    };
+    Ok(store.list(matches.value_of("project"), &filter)?)
+}
+
+/// List the task file of every context, marking the listings with its name
+///
+/// Contexts without the project listed are skipped, as are, with a warning, those
+/// whose task file cannot be read.
+fn across_contexts<F>(config: &Config, list: F) -> Result<Vec<Listing>>
+where
+    F: Fn(&TaskStore) -> Result<Vec<Listing>>,
+{
+    let contexts = config.contexts()?;
+    if contexts.is_empty() {
+        return Err(eyre!(
+            "no contexts are configured: add them under [contexts]"
+        ));
+    }
+
+    let mut listings = vec![];
+    for (name, path) in contexts {
+        let listed = match TaskStore::open(&path)
+            .map_err(|e| e.into())
+            .and_then(|store| list(&store))
+        {
+            Err(e) => match e.downcast_ref() {
+                Some(RustaskError::ProjectNotFound(_)) => continue,
+                Some(RustaskError::FileError(error)) => {
+                    eprintln!("skipping context {}: {}", name, error);
+                    continue;
+                }
+                _ => return Err(e),
+            },
+            Ok(listed) => listed,
+        };
+        listings.extend(listed.into_iter().map(|listing| Listing {
+            context: Some(name.clone()),
+            ..listing
+        }));
+    }
+    Ok(listings)
+}
+
+fn app() -> App<'static> {
+    App::new("rustask")
         .version("0.9.2")
         .author("bsdinis <baltasar.dinis@tecnico.ulisboa.pt>")
         .about("Task Manager")
@@ -17,24 +160,242 @@ fn main() -> Result<()> {
                 .help("task file")
                 .takes_value(true),
         )
+        .arg(
+            Arg::with_name("context")
+                .long("context")
+                .short('c')
+                .help("use the task file of a context configured under [contexts]")
+                .takes_value(true)
+                .conflicts_with("file"),
+        )
+        .arg(
+            Arg::with_name("lock timeout")
+                .long("lock-timeout")
+                .help("seconds to wait for other rustask processes to release the task file")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("backend")
+                .long("backend")
+                .help("storage backend of the task file: json, toml, yaml or sqlite (defaults to its extension)")
+                .takes_value(true),
+        )
         .subcommand(
             SubCommand::with_name("list")
                 .aliases(&["l"])
                 .help("List tasks")
+                .arg(
+                    Arg::with_name("all contexts")
+                        .help("list the tasks of every context")
+                        .long("all-contexts")
+                        .short('A'),
+                )
                 .arg(
                     Arg::with_name("project")
                         .help("project to be listed")
                         .index(1),
+                )
+                .arg(
+                    Arg::with_name("format")
+                        .help("output format: text, json, ndjson, csv or tsv")
+                        .takes_value(true)
+                        .long("format")
+                        .short('F'),
+                )
+                .arg(
+                    Arg::with_name("where")
+                        .help("only tasks matching an expression, e.g. 'priority>=high and due<7d'")
+                        .takes_value(true)
+                        .long("where")
+                        .short('w'),
+                )
+                .arg(
+                    Arg::with_name("tag")
+                        .help("only tasks with this tag")
+                        .takes_value(true)
+                        .multiple_occurrences(true)
+                        .long("tag")
+                        .short('t'),
+                )
+                .arg(
+                    Arg::with_name("no-tag")
+                        .help("only tasks without this tag")
+                        .takes_value(true)
+                        .multiple_occurrences(true)
+                        .long("no-tag")
+                        .short('T'),
+                )
+                .arg(
+                    Arg::with_name("explain")
+                        .help("show why each task was picked")
+                        .long("explain")
+                        .short('x'),
+                )
+                .arg(
+                    Arg::with_name("seed")
+                        .help("seed the pick (defaults to today's date)")
+                        .takes_value(true)
+                        .long("seed")
+                        .conflicts_with("random"),
+                )
+                .arg(
+                    Arg::with_name("random")
+                        .help("pick differently on every run")
+                        .long("random"),
+                )
+                .arg(
+                    Arg::with_name("probability")
+                        .help("probability of picking a priority, as <priority>=<probability>")
+                        .takes_value(true)
+                        .multiple_occurrences(true)
+                        .long("probability")
+                        .short('P'),
+                )
+                .arg(
+                    Arg::with_name("blocked")
+                        .help("also pick tasks waiting on unfinished ones")
+                        .long("blocked")
+                        .short('b'),
                 ),
         )
         .subcommand(
             SubCommand::with_name("listall")
                 .aliases(&["la"])
                 .help("List all tasks")
+                .arg(
+                    Arg::with_name("all contexts")
+                        .help("list the tasks of every context")
+                        .long("all-contexts")
+                        .short('A'),
+                )
                 .arg(
                     Arg::with_name("project")
                         .help("project to be listed")
                         .index(1),
+                )
+                .arg(
+                    Arg::with_name("format")
+                        .help("output format: text, json, ndjson, csv or tsv")
+                        .takes_value(true)
+                        .long("format")
+                        .short('F'),
+                )
+                .arg(
+                    Arg::with_name("where")
+                        .help("only tasks matching an expression, e.g. 'priority>=high and due<7d'")
+                        .takes_value(true)
+                        .long("where")
+                        .short('w'),
+                )
+                .arg(
+                    Arg::with_name("tag")
+                        .help("only tasks with this tag")
+                        .takes_value(true)
+                        .multiple_occurrences(true)
+                        .long("tag")
+                        .short('t'),
+                )
+                .arg(
+                    Arg::with_name("no-tag")
+                        .help("only tasks without this tag")
+                        .takes_value(true)
+                        .multiple_occurrences(true)
+                        .long("no-tag")
+                        .short('T'),
+                )
+                .arg(
+                    Arg::with_name("completed")
+                        .help("also list completed tasks")
+                        .long("completed")
+                        .short('c'),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("undo")
+                .aliases(&["u"])
+                .help("Undo the last operation")
+                .arg(
+                    Arg::with_name("list")
+                        .help("list the operations which can be undone")
+                        .long("list")
+                        .short('l'),
+                ),
+        )
+        .subcommand(SubCommand::with_name("redo").help("Redo the last undone operation"))
+        .subcommand(
+            SubCommand::with_name("migrate")
+                .help("Copy the task file to a new file, in another backend")
+                .arg(
+                    Arg::with_name("destination")
+                        .help("the new task file")
+                        .index(1)
+                        .required(true),
+                )
+                .arg(
+                    Arg::with_name("to")
+                        .help("backend of the new task file: json, toml, yaml or sqlite (defaults to its extension)")
+                        .takes_value(true)
+                        .long("to"),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("init")
+                .help("Create an empty task file")
+                .arg(
+                    Arg::with_name("local")
+                        .help("create a .rustask.json in the current directory, used from anywhere below it")
+                        .long("local")
+                        .short('l'),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("tui")
+                .help("Browse and edit the tasks in a full-screen interface"),
+        )
+        .subcommand(
+            SubCommand::with_name("contexts")
+                .help("List the configured contexts and their task files"),
+        )
+        .subcommand(
+            SubCommand::with_name("where")
+                .help("Show which task file is in use, and why"),
+        )
+        .subcommand(
+            SubCommand::with_name("doctor")
+                .help("Check the task file and report the version of its format"),
+        )
+        .subcommand(
+            SubCommand::with_name("repair")
+                .help("Rewrite a corrupt task file with whatever can be read from it")
+                .arg(
+                    Arg::with_name("dry-run")
+                        .help("only report what would be kept and dropped")
+                        .long("dry-run")
+                        .short('n'),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("log")
+                .aliases(&["history"])
+                .help("List completed tasks")
+                .arg(
+                    Arg::with_name("project")
+                        .help("project to be listed")
+                        .index(1),
+                )
+                .arg(
+                    Arg::with_name("since")
+                        .help("only tasks completed at or after this date")
+                        .takes_value(true)
+                        .long("since")
+                        .short('s'),
+                )
+                .arg(
+                    Arg::with_name("until")
+                        .help("only tasks completed before this date")
+                        .takes_value(true)
+                        .long("until")
+                        .short('u'),
                 ),
         )
         .subcommand(
@@ -60,15 +421,14 @@ fn main() -> Result<()> {
                 .help("Add a task")
                 .arg(
                     Arg::with_name("project")
-                        .help("project to assign the task to")
+                        .help("project to assign the task to (the task, if default_project is configured)")
                         .index(1)
                         .required(true),
                 )
                 .arg(
                     Arg::with_name("task")
                         .help("the task to be added")
-                        .index(2)
-                        .required(true),
+                        .index(2),
                 )
                 .arg(
                     Arg::with_name("priority")
@@ -78,9 +438,45 @@ fn main() -> Result<()> {
                 )
                 .arg(
                     Arg::with_name("deadline")
-                        .help("deadline of the task")
+                        .help("deadline of the task (e.g. 2022-05-01 17:00, tomorrow 5pm, friday, +3d, in 2 weeks)")
                         .takes_value(true)
                         .short('d'),
+                )
+                .arg(
+                    Arg::with_name("repeat")
+                        .help(
+                            "repeat the task once done: daily, weekly, monthly, \
+                             every <n> days, <n>d or weekdays (e.g. mon,thu)",
+                        )
+                        .takes_value(true)
+                        .long("repeat")
+                        .short('r'),
+                )
+                .arg(
+                    Arg::with_name("until")
+                        .help("last date on which the task repeats")
+                        .takes_value(true)
+                        .long("until")
+                        .requires("repeat"),
+                )
+                .arg(
+                    Arg::with_name("parent")
+                        .help("id (or unique id prefix) of the task to add a subtask to")
+                        .takes_value(true)
+                        .long("parent"),
+                )
+                .arg(
+                    Arg::with_name("after")
+                        .help("id (or unique id prefix) of a task, in any project, to be done first")
+                        .takes_value(true)
+                        .multiple_occurrences(true)
+                        .long("after"),
+                )
+                .arg(
+                    Arg::with_name("estimate")
+                        .help("how long the task is expected to take (e.g. 1h30m, 1.5h, 45m)")
+                        .takes_value(true)
+                        .long("estimate"),
                 ),
         )
         .subcommand(
@@ -93,7 +489,24 @@ fn main() -> Result<()> {
                         .index(1)
                         .required(true),
                 )
-                .arg(Arg::with_name("task index").index(2).required(true)),
+                .arg(
+                    Arg::with_name("task id")
+                        .help("ids (or unique id prefixes) of the finished tasks")
+                        .index(2)
+                        .multiple_values(true)
+                        .required(true),
+                )
+                .arg(
+                    Arg::with_name("cascade")
+                        .help("also conclude the pending subtasks of the tasks")
+                        .long("cascade")
+                        .short('c'),
+                )
+                .arg(
+                    Arg::with_name("force")
+                        .help("conclude the tasks even if they wait on unfinished ones")
+                        .long("force"),
+                ),
         )
         .subcommand(
             SubCommand::with_name("move")
@@ -107,7 +520,7 @@ fn main() -> Result<()> {
                 )
                 .arg(
                     Arg::with_name("id")
-                        .help("id of the task being moved")
+                        .help("id (or unique id prefix) of the task being moved")
                         .index(2)
                         .required(true),
                 )
@@ -129,8 +542,8 @@ fn main() -> Result<()> {
                         .required(true),
                 )
                 .arg(
-                    Arg::with_name("task index")
-                        .help("the index of the task to be changed")
+                    Arg::with_name("task id")
+                        .help("the id (or unique id prefix) of the task to be changed")
                         .index(2)
                         .required(true),
                 )
@@ -147,34 +560,283 @@ fn main() -> Result<()> {
                 )
                 .arg(
                     Arg::with_name("deadline")
-                        .help("deadline of the task")
+                        .help("deadline of the task (e.g. 2022-05-01 17:00, tomorrow 5pm, friday, +3d, in 2 weeks)")
                         .takes_value(true)
                         .short('d'),
+                )
+                .arg(
+                    Arg::with_name("repeat")
+                        .help(
+                            "repeat the task once done: daily, weekly, monthly, \
+                             every <n> days, <n>d or weekdays (e.g. mon,thu)",
+                        )
+                        .takes_value(true)
+                        .long("repeat")
+                        .short('r'),
+                )
+                .arg(
+                    Arg::with_name("until")
+                        .help("last date on which the task repeats")
+                        .takes_value(true)
+                        .long("until")
+                        .requires("repeat"),
+                )
+                .arg(
+                    Arg::with_name("tag")
+                        .help("add a tag to the task")
+                        .takes_value(true)
+                        .multiple_occurrences(true)
+                        .long("tag")
+                        .short('t'),
+                )
+                .arg(
+                    Arg::with_name("untag")
+                        .help("remove a tag from the task")
+                        .takes_value(true)
+                        .multiple_occurrences(true)
+                        .long("untag")
+                        .short('T'),
+                )
+                .arg(
+                    Arg::with_name("after")
+                        .help("id (or unique id prefix) of a task, in any project, to be done first")
+                        .takes_value(true)
+                        .multiple_occurrences(true)
+                        .long("after"),
+                )
+                .arg(
+                    Arg::with_name("not after")
+                        .help("id (or unique id prefix) of a task to stop waiting on")
+                        .takes_value(true)
+                        .multiple_occurrences(true)
+                        .long("not-after"),
+                )
+                .arg(
+                    Arg::with_name("estimate")
+                        .help("how long the task is expected to take (e.g. 1h30m, 1.5h, 45m), or none")
+                        .takes_value(true)
+                        .long("estimate"),
+                )
+                .arg(
+                    Arg::with_name("notes")
+                        .help("write a note on the task in $VISUAL or $EDITOR")
+                        .long("notes"),
                 ),
         )
-        .get_matches();
+        .subcommand(
+            SubCommand::with_name("annotate")
+                .help("Add a note to a task")
+                .arg(
+                    Arg::with_name("project")
+                        .help("project where the task is assigned to")
+                        .index(1)
+                        .required(true),
+                )
+                .arg(
+                    Arg::with_name("task id")
+                        .help("the id (or unique id prefix) of the task to annotate")
+                        .index(2)
+                        .required(true),
+                )
+                .arg(
+                    Arg::with_name("note")
+                        .help("the note (written in $VISUAL or $EDITOR if not given)")
+                        .index(3)
+                        .multiple_values(true),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("show")
+                .help("Show a task with all of its fields and notes")
+                .arg(
+                    Arg::with_name("project")
+                        .help("project where the task is assigned to")
+                        .index(1)
+                        .required(true),
+                )
+                .arg(
+                    Arg::with_name("task id")
+                        .help("the id (or unique id prefix) of the task to show")
+                        .index(2)
+                        .required(true),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("start")
+                .help("Start a timer on a task, stopping the running one")
+                .arg(
+                    Arg::with_name("project")
+                        .help("project where the task is assigned to")
+                        .index(1)
+                        .required(true),
+                )
+                .arg(
+                    Arg::with_name("task id")
+                        .help("the id (or unique id prefix) of the task to work on")
+                        .index(2)
+                        .required(true),
+                ),
+        )
+        .subcommand(SubCommand::with_name("stop").help("Stop the running timer"))
+        .subcommand(
+            SubCommand::with_name("report")
+                .help("Sum up the time spent on tasks, against their estimates")
+                .arg(
+                    Arg::with_name("project")
+                        .help("project whose tasks are reported on")
+                        .index(1),
+                )
+                .arg(
+                    Arg::with_name("by")
+                        .help("what to sum the time up by: project, tag, day or task")
+                        .takes_value(true)
+                        .long("by")
+                        .short('b'),
+                )
+                .arg(
+                    Arg::with_name("since")
+                        .help("only count the time spent since")
+                        .takes_value(true)
+                        .long("since")
+                        .short('s'),
+                )
+                .arg(
+                    Arg::with_name("until")
+                        .help("only count the time spent until")
+                        .takes_value(true)
+                        .long("until")
+                        .short('u'),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("graph")
+                .help("Show which tasks wait on which")
+                .arg(
+                    Arg::with_name("project")
+                        .help("project whose tasks are shown with what they wait on")
+                        .index(1),
+                )
+                .arg(
+                    Arg::with_name("dot")
+                        .help("write a Graphviz digraph, e.g. for `dot -Tsvg`")
+                        .long("dot"),
+                )
+                .arg(
+                    Arg::with_name("completed")
+                        .help("also show completed tasks")
+                        .long("completed")
+                        .short('c'),
+                ),
+        )
+}
 
-    if !matches.is_present("file") && env::var("RUSTASK_TASKFILE").is_err() {
-        eprintln!("Could not find rustask file");
-        eprintln!("Maybe set RUSTASK_TASKFILE env var or pass in -f flag");
-        std::process::exit(1);
+fn main() -> Result<()> {
+    color_eyre::install()?;
+    let mut matches = app().get_matches();
+
+    let global_config = Config::load(&config::global_path().into_iter().collect::<Vec<_>>())?;
+    let context = match matches.value_of("context") {
+        Some(name) => Some((name.to_string(), global_config.context(name)?)),
+        None => None,
+    };
+    let location = location::locate(
+        matches.value_of("file"),
+        context,
+        env::var("RUSTASK_TASKFILE").ok(),
+        &env::current_dir()?,
+        location::data_dir(),
+    )?;
+
+    let config_paths: Vec<PathBuf> = config::global_path()
+        .into_iter()
+        .chain([config::task_file_path(&location.path)])
+        .collect();
+    config::set(Config::load(&config_paths)?);
+    if matches.subcommand_name().is_none() {
+        if let Some(command) = &config::get().default_command {
+            let words = command.split_whitespace().map(OsString::from);
+            matches = app().get_matches_from(env::args_os().chain(words));
+        }
     }
 
-    let task_location = if matches.is_present("file") {
-        matches.value_of("file").unwrap().to_string()
-    } else {
-        env::var("RUSTASK_TASKFILE")?
+    let lock_timeout = match matches.value_of("lock timeout") {
+        Some(secs) => Some(("--lock-timeout", secs.to_string())),
+        None => env::var("RUSTASK_LOCK_TIMEOUT")
+            .ok()
+            .map(|secs| ("RUSTASK_LOCK_TIMEOUT", secs)),
     };
-    let path = Path::new(&task_location);
+    if let Some((source, secs)) = lock_timeout {
+        let timeout = secs
+            .trim()
+            .parse::<f64>()
+            .ok()
+            .and_then(|secs| Duration::try_from_secs_f64(secs).ok())
+            .ok_or_else(|| {
+                eyre!(
+                    "invalid {} `{}`: expected a non-negative number of seconds",
+                    source,
+                    secs
+                )
+            })?;
+        commands::set_lock_timeout(timeout);
+    }
+    let backend = |path: &Path| match matches.value_of("backend") {
+        Some(backend) => backend.parse::<Backend>(),
+        None => Ok(Backend::from_path(path)),
+    };
+    // listing across contexts does not need a task file of its own
+    match matches.subcommand() {
+        Some(("list", sub_matches)) if sub_matches.is_present("all contexts") => {
+            let listings = across_contexts(&global_config, |store| pick(sub_matches, store))?;
+            return print(&listings, parse_format(sub_matches)?);
+        }
+        Some(("listall", sub_matches)) if sub_matches.is_present("all contexts") => {
+            let listings = across_contexts(&global_config, |store| list_all(sub_matches, store))?;
+            return print(&listings, parse_format(sub_matches)?);
+        }
+        _ => {}
+    }
+    match matches.subcommand_name() {
+        Some("init") => {
+            let path = if matches
+                .subcommand_matches("init")
+                .unwrap()
+                .is_present("local")
+            {
+                PathBuf::from(location::LOCAL_FILE)
+            } else {
+                location.path
+            };
+            let store = TaskStore::init(&path, backend(&path)?)?;
+            println!("created {} ({})", store.path().display(), store.backend());
+            return Ok(());
+        }
+        Some("contexts") => {
+            for (name, path) in global_config.contexts()? {
+                let current = if path == location.path { "*" } else { " " };
+                println!("{} {}: {}", current, name, path.display());
+            }
+            return Ok(());
+        }
+        Some("where") => {
+            println!("{}", location.path.display());
+            println!("{}", location.source);
+            if !location.path.exists() {
+                println!("it does not exist yet, create it with `rustask init`");
+            }
+            for path in config_paths.iter().filter(|path| path.exists()) {
+                println!("configured by {}", path.display());
+            }
+            return Ok(());
+        }
+        _ => {}
+    }
+    let store = TaskStore::open_with(&location.path, backend(&location.path)?)?;
 
     match matches.subcommand_name() {
         Some("list") => {
             let sub_matches = matches.subcommand_matches("list").unwrap();
-            let project = sub_matches
-                .value_of("project")
-                .and_then(|s| s.to_string().parse::<String>().ok());
-
-            commands::list(path, project)?
+            print(&pick(sub_matches, &store)?, parse_format(sub_matches)?)?
         }
         Some("rename") => {
             let sub_matches = matches.subcommand_matches("rename").unwrap();
@@ -182,7 +844,7 @@ fn main() -> Result<()> {
 
             let name = sub_matches.value_of("name").unwrap().parse::<String>()?;
 
-            commands::rename(path, project, name)?
+            store.rename(project, name)?
         }
         Some("move") => {
             let sub_matches = matches.subcommand_matches("move").unwrap();
@@ -191,34 +853,44 @@ fn main() -> Result<()> {
                 .unwrap()
                 .parse::<String>()?;
 
-            let id = sub_matches.value_of("id").unwrap().parse::<usize>()?;
+            let id = sub_matches.value_of("id").unwrap();
 
             let new_project = sub_matches
                 .value_of("new project")
                 .unwrap()
                 .parse::<String>()?;
 
-            commands::move_task(path, old_project, id, new_project)?
+            store.move_task(old_project, id, new_project)?
         }
         Some("add") => {
             let sub_matches = matches.subcommand_matches("add").unwrap();
-            let task_descript = sub_matches.value_of("task").unwrap().parse::<String>()?;
-
-            let project = sub_matches
-                .value_of("project")
-                .unwrap_or("")
-                .parse::<String>()?;
+            let (project, task_descript) =
+                match (sub_matches.value_of("task"), &config::get().default_project) {
+                    (Some(task), _) => (sub_matches.value_of("project").unwrap(), task),
+                    (None, Some(project)) => {
+                        (project.as_str(), sub_matches.value_of("project").unwrap())
+                    }
+                    (None, None) => {
+                        return Err(eyre!(
+                        "no task given: pass a project and a task, or configure default_project"
+                    ))
+                    }
+                };
+            let project = project.to_string();
+            let task_descript = task_descript.to_string();
 
             let priority = sub_matches
                 .value_of("priority")
-                .and_then(|s| s.parse::<task::Priority>().ok());
+                .and_then(|s| s.parse::<task::Priority>().ok())
+                .or_else(|| config::get().default_priority.clone());
             let deadline = if let Some(p_str) = sub_matches.value_of("deadline") {
                 Some(task::parse_deadline(p_str)?)
             } else {
                 None
             };
 
-            let task_b = task::TaskBuilder::new(task_descript);
+            let (task_descript, tags) = task::parse_tags(&task_descript);
+            let task_b = task::TaskBuilder::new(task_descript).tags(tags);
             let task_b = if let Some(p) = priority {
                 task_b.priority(p)
             } else {
@@ -229,8 +901,31 @@ fn main() -> Result<()> {
             } else {
                 task_b
             };
-            commands::add_task(path, task_b.build(), project.clone())?;
-            commands::list_all(path, Some(project))?;
+            let task_b = if let Some(r) = parse_recurrence(sub_matches)? {
+                task_b.recurrence(r)
+            } else {
+                task_b
+            };
+            let task_b = if let Some(parent) = sub_matches.value_of("parent") {
+                task_b.parent(parent.to_string())
+            } else {
+                task_b
+            };
+            let task_b = sub_matches
+                .values_of("after")
+                .into_iter()
+                .flatten()
+                .fold(task_b, |t, id| t.depends_on(id.to_string()));
+            let task_b = if let Some(e) = sub_matches.value_of("estimate") {
+                task_b.estimate(e.parse::<Effort>()?)
+            } else {
+                task_b
+            };
+            store.add_task(task_b.build(), project.clone())?;
+            print(
+                &store.list(Some(&project), &Filter::default())?,
+                Format::Text,
+            )?;
         }
         Some("done") => {
             let sub_matches = matches.subcommand_matches("done").unwrap();
@@ -239,17 +934,20 @@ fn main() -> Result<()> {
                 .unwrap_or("")
                 .parse::<String>()?;
 
-            if let Ok(idx) = sub_matches.value_of("task index").unwrap().parse::<usize>() {
-                let task = commands::remove_task(path, idx, project.clone())?;
-                println!("finished task {}: {}", idx, task);
-                match commands::list_all(path, Some(project)) {
-                    Err(commands::error::RustaskError::ProjectNotFound(_)) | Ok(_) => {}
-                    Err(e) => {
-                        return Err(e.into());
-                    }
+            let ids: Vec<&str> = sub_matches.values_of("task id").unwrap().collect();
+            let completion = Completion {
+                cascade: sub_matches.is_present("cascade"),
+                force: sub_matches.is_present("force"),
+            };
+            for task in store.complete_tasks_with(&ids, project.clone(), completion)? {
+                println!("finished task {}: {}", task.id, task);
+            }
+            match store.list(Some(&project), &Filter::default()) {
+                Ok(listings) => print(&listings, Format::Text)?,
+                Err(commands::error::RustaskError::ProjectNotFound(_)) => {}
+                Err(e) => {
+                    return Err(e.into());
                 }
-            } else {
-                eprintln!("error: Refer to the task done by its id");
             }
         }
         Some("edit") => {
@@ -259,43 +957,288 @@ fn main() -> Result<()> {
                 .unwrap_or("")
                 .parse::<String>()?;
 
-            if let Ok(idx) = sub_matches.value_of("task index").unwrap().parse::<usize>() {
-                let task_descript = sub_matches
-                    .value_of("descript")
-                    .and_then(|d_str| d_str.parse::<String>().ok());
+            let id = sub_matches.value_of("task id").unwrap();
+            let (task_descript, mut add_tags) = match sub_matches.value_of("descript") {
+                Some(d_str) => {
+                    let (descript, tags) = task::parse_tags(d_str);
+                    (Some(descript), tags)
+                }
+                None => (None, BTreeSet::new()),
+            };
+            add_tags.extend(
+                sub_matches
+                    .values_of("tag")
+                    .into_iter()
+                    .flatten()
+                    .map(|t| t.trim_start_matches('+').to_string()),
+            );
+            let remove_tags = sub_matches
+                .values_of("untag")
+                .into_iter()
+                .flatten()
+                .map(|t| t.trim_start_matches('+').to_string())
+                .collect();
+
+            let priority = sub_matches
+                .value_of("priority")
+                .and_then(|p_str| p_str.parse::<task::Priority>().ok());
 
-                let priority = sub_matches
-                    .value_of("priority")
-                    .and_then(|p_str| p_str.parse::<task::Priority>().ok());
+            let deadline = if let Some(p_str) = sub_matches.value_of("deadline") {
+                Some(task::parse_deadline(p_str)?)
+            } else {
+                None
+            };
 
-                let deadline = if let Some(p_str) = sub_matches.value_of("deadline") {
-                    Some(task::parse_deadline(p_str)?)
-                } else {
-                    None
-                };
+            let recurrence = match sub_matches.value_of("repeat") {
+                Some("never") => Some(None),
+                Some(_) => Some(parse_recurrence(sub_matches)?),
+                None => None,
+            };
+
+            let estimate = match sub_matches.value_of("estimate") {
+                Some("none") => Some(None),
+                Some(e) => Some(Some(e.parse::<Effort>()?)),
+                None => None,
+            };
 
-                commands::edit_task(
-                    path,
-                    idx,
-                    project.clone(),
-                    task_descript,
-                    priority,
-                    deadline,
-                )?;
-                commands::list_all(path, Some(project))?
+            let annotations = if sub_matches.is_present("notes") {
+                let task = store.project(&project)?.task(id)?.clone();
+                match annotation::compose(&task)? {
+                    Some(note) => vec![Annotation::new(note, task::now_deadline())],
+                    None => {
+                        eprintln!("no note written");
+                        vec![]
+                    }
+                }
             } else {
-                eprintln!("error: Refer to the task done by its id");
+                vec![]
+            };
+
+            let ids = |name| {
+                sub_matches
+                    .values_of(name)
+                    .into_iter()
+                    .flatten()
+                    .map(str::to_string)
+                    .collect()
+            };
+            let edit = task::TaskEdit {
+                description: task_descript,
+                priority,
+                deadline,
+                recurrence,
+                add_tags,
+                remove_tags,
+                add_dependencies: ids("after"),
+                remove_dependencies: ids("not after"),
+                estimate,
+                annotations,
+            };
+            store.edit_task(id, project.clone(), edit)?;
+            print(
+                &store.list(Some(&project), &Filter::default())?,
+                Format::Text,
+            )?
+        }
+        Some("annotate") => {
+            let sub_matches = matches.subcommand_matches("annotate").unwrap();
+            let project = sub_matches.value_of("project").unwrap().to_string();
+            let id = sub_matches.value_of("task id").unwrap();
+
+            let note = match sub_matches.values_of("note") {
+                Some(words) => Some(words.collect::<Vec<_>>().join(" ")),
+                None => annotation::compose(store.project(&project)?.task(id)?)?,
+            };
+            match note {
+                Some(note) => {
+                    let task = store.annotate_task(id, project.clone(), note)?;
+                    println!("annotated {}: {}", project, task);
+                }
+                None => eprintln!("no note written"),
             }
         }
+        Some("show") => {
+            let sub_matches = matches.subcommand_matches("show").unwrap();
+            let project = sub_matches.value_of("project").unwrap();
+            let id = sub_matches.value_of("task id").unwrap();
+
+            let projects = store.projects()?;
+            let task = projects
+                .iter()
+                .find(|p| p.name == project)
+                .ok_or_else(|| RustaskError::ProjectNotFound(project.to_string()))?
+                .task(id)?;
+            let graph = Graph::new(&projects);
+            output::write_task(&mut io::stdout().lock(), project, task, &graph)?
+        }
+        Some("start") => {
+            let sub_matches = matches.subcommand_matches("start").unwrap();
+            let project = sub_matches.value_of("project").unwrap().to_string();
+            let id = sub_matches.value_of("task id").unwrap();
+
+            if let Some((project, task)) = store.start_timer(id, project.clone())? {
+                println!("stopped {}: {}", project, task);
+            }
+            let task = store.project(&project)?.task(id)?.clone();
+            println!("started {}: {}", project, task);
+        }
+        Some("stop") => {
+            let (project, task) = store.stop_timer()?;
+            println!("stopped {}: {}", project, task);
+        }
+        Some("report") => {
+            let sub_matches = matches.subcommand_matches("report").unwrap();
+            let grouping = match sub_matches.value_of("by") {
+                Some(g) => g.parse::<Grouping>()?,
+                None => Grouping::Project,
+            };
+            let period = Period {
+                since: match sub_matches.value_of("since") {
+                    Some(s) => Some(task::parse_deadline(s)?),
+                    None => None,
+                },
+                until: match sub_matches.value_of("until") {
+                    Some(s) => Some(task::parse_deadline(s)?),
+                    None => None,
+                },
+            };
+
+            let report = store.report(sub_matches.value_of("project"), grouping, &period)?;
+            report.write(&mut io::stdout().lock())?
+        }
         Some("listall") => {
             let sub_matches = matches.subcommand_matches("listall").unwrap();
+            print(&list_all(sub_matches, &store)?, parse_format(sub_matches)?)?
+        }
+        Some("log") => {
+            let sub_matches = matches.subcommand_matches("log").unwrap();
             let project = sub_matches
                 .value_of("project")
                 .and_then(|s| s.to_string().parse::<String>().ok());
 
-            commands::list_all(path, project)?
+            let since = if let Some(s) = sub_matches.value_of("since") {
+                Some(task::parse_deadline(s)?)
+            } else {
+                None
+            };
+            let until = if let Some(s) = sub_matches.value_of("until") {
+                Some(task::parse_deadline(s)?)
+            } else {
+                None
+            };
+
+            let listings = store.completed(project.as_deref(), since, until)?;
+            output::write_completed(&mut io::stdout().lock(), &listings)?
+        }
+        Some("undo") => {
+            let sub_matches = matches.subcommand_matches("undo").unwrap();
+            if sub_matches.is_present("list") {
+                for (i, (time, operation)) in store.operations()?.iter().enumerate() {
+                    println!(
+                        "[{}] {}: {}",
+                        i,
+                        time.format(&config::get().deadlines.output_format),
+                        operation
+                    );
+                }
+            } else if let Some(operation) = store.undo()? {
+                println!("undid: {}", operation);
+            } else {
+                eprintln!("nothing to undo");
+            }
+        }
+        Some("redo") => {
+            if let Some(operation) = store.redo()? {
+                println!("redid: {}", operation);
+            } else {
+                eprintln!("nothing to redo");
+            }
+        }
+        Some("migrate") => {
+            let sub_matches = matches.subcommand_matches("migrate").unwrap();
+            let destination = Path::new(sub_matches.value_of("destination").unwrap());
+            let backend = match sub_matches.value_of("to") {
+                Some(b) => b.parse::<Backend>()?,
+                None => Backend::from_path(destination),
+            };
+
+            let migrated = store.migrate(destination, backend)?;
+            println!(
+                "migrated {} ({}) to {} ({})",
+                store.path().display(),
+                store.backend(),
+                migrated.path().display(),
+                migrated.backend()
+            );
+        }
+        Some("doctor") => {
+            let diagnosis = store.doctor()?;
+            println!("{} ({})", store.path().display(), store.backend());
+            match diagnosis.version {
+                Some(v) if v < diagnosis.current_version => println!(
+                    "format version {}, upgraded to {} on the next write",
+                    v, diagnosis.current_version
+                ),
+                Some(v) => println!("format version {}", v),
+                None => println!("format version unknown"),
+            }
+            println!(
+                "projects: {}, tasks: {}",
+                diagnosis.projects, diagnosis.tasks
+            );
+            if diagnosis.problems.is_empty() {
+                println!("no problems found");
+            } else {
+                for problem in &diagnosis.problems {
+                    println!("problem: {}", problem);
+                }
+                std::process::exit(1);
+            }
+        }
+        Some("tui") => rustask::tui::run(&store)?,
+        Some("graph") => {
+            let sub_matches = matches.subcommand_matches("graph").unwrap();
+            let project = sub_matches.value_of("project");
+            let projects = store.projects()?;
+            if let Some(name) = project.filter(|name| !projects.iter().any(|p| p.name == *name)) {
+                return Err(RustaskError::ProjectNotFound(name.to_string()).into());
+            }
+
+            let graph = Graph::new(&projects);
+            let completed = sub_matches.is_present("completed");
+            let mut out = io::stdout().lock();
+            if sub_matches.is_present("dot") {
+                graph.write_dot(&mut out, project, completed)?
+            } else {
+                graph.write_tree(&mut out, project, completed)?
+            }
+        }
+        Some("repair") => {
+            let dry_run = matches
+                .subcommand_matches("repair")
+                .unwrap()
+                .is_present("dry-run");
+            let salvage = store.repair(dry_run)?;
+            for dropped in &salvage.dropped {
+                println!("dropped {}", dropped);
+            }
+            for renamed in &salvage.renamed {
+                println!("renamed {}", renamed);
+            }
+            println!(
+                "{} {} projects and {} tasks",
+                if dry_run { "would keep" } else { "kept" },
+                salvage.projects.len(),
+                salvage.tasks()
+            );
+            if !dry_run && store.backend() != Backend::Sqlite {
+                println!(
+                    "the previous file was kept as {}.bak.1",
+                    store.path().display()
+                );
+            }
         }
-        _ => commands::list_all(path, None)?,
+        _ => print(&store.list(None, &Filter::default())?, Format::Text)?,
     };
 
     Ok(())
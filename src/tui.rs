@@ -0,0 +1,572 @@
+// tui.rs
+//
+// browse and edit a task file in a full-screen terminal interface
+
+use ratatui::crossterm::event::{self, Event, KeyCode, KeyEvent, KeyEventKind, KeyModifiers};
+use ratatui::layout::{Constraint, Layout};
+use ratatui::style::{Color, Modifier, Style};
+use ratatui::text::{Line, Span};
+use ratatui::widgets::{Block, Borders, List, ListItem, ListState, Paragraph};
+use ratatui::{DefaultTerminal, Frame};
+
+use crate::commands::config;
+use crate::commands::error::RustaskError;
+use crate::commands::filter::Filter;
+use crate::commands::project::{Progress, Summary};
+use crate::commands::query;
+use crate::commands::task::{self, Priority, Task, TaskBuilder, TaskEdit};
+use crate::commands::TaskStore;
+
+const HELP: &str = "j/k move  a add  A add to new project  e edit  p priority  d deadline  \
+                    m move  x done  X delete  / filter  c completed  u undo  ^r redo  q quit";
+
+/// A line of the listing
+// most rows are tasks, not worth boxing them for
+#[allow(clippy::large_enum_variant)]
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Row {
+    Project(Summary),
+    Task {
+        project: String,
+        task: Task,
+        id_prefix_len: usize,
+        /// How deep the task is nested under the listed tasks
+        depth: usize,
+        progress: Option<Progress>,
+        /// Whether the task waits on unfinished ones
+        blocked: bool,
+    },
+}
+
+/// What the text being typed is for
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Prompt {
+    /// A task for the given project
+    Add(String),
+    /// The project of a new task
+    AddProject,
+    Description,
+    Priority,
+    Deadline,
+    /// The project to move the selected task to
+    Move,
+    Filter,
+}
+
+impl Prompt {
+    fn label(&self) -> String {
+        match self {
+            Prompt::Add(project) => format!("add to {}: ", project),
+            Prompt::AddProject => "project: ".to_string(),
+            Prompt::Description => "description: ".to_string(),
+            Prompt::Priority => "priority (urgent, high, normal, low, note): ".to_string(),
+            Prompt::Deadline => "deadline: ".to_string(),
+            Prompt::Move => "move to project: ".to_string(),
+            Prompt::Filter => "filter (e.g. priority>=high and tag:work): ".to_string(),
+        }
+    }
+}
+
+/// The state of the interface, apart from the terminal
+struct App<'a> {
+    store: &'a TaskStore,
+    rows: Vec<Row>,
+    selected: usize,
+    filter: Filter,
+    /// The filter as typed
+    query: String,
+    input: Option<(Prompt, String)>,
+    /// The outcome of the last action
+    message: String,
+    quit: bool,
+}
+
+impl<'a> App<'a> {
+    fn new(store: &'a TaskStore) -> Result<App<'a>, RustaskError> {
+        let mut app = App {
+            store,
+            rows: vec![],
+            selected: 0,
+            filter: Filter::default(),
+            query: String::new(),
+            input: None,
+            message: String::new(),
+            quit: false,
+        };
+        app.reload()?;
+        Ok(app)
+    }
+
+    /// Read the task file again, keeping the same task or project selected if it is
+    /// still listed
+    fn reload(&mut self) -> Result<(), RustaskError> {
+        let selected = self.rows.get(self.selected).cloned();
+        self.rows = self
+            .store
+            .list(None, &self.filter)?
+            .into_iter()
+            .flat_map(|listing| {
+                let project = listing.project.name.clone();
+                let id_prefix_len = listing.project.id_prefix_len;
+                let depths = listing.depths();
+                let progress = listing.progress;
+                let blocked = listing.blocked;
+                std::iter::once(Row::Project(listing.project)).chain(
+                    listing
+                        .tasks
+                        .into_iter()
+                        .zip(depths)
+                        .map(move |((task, _), depth)| Row::Task {
+                            project: project.clone(),
+                            progress: progress.get(&task.id).copied(),
+                            blocked: blocked.contains(&task.id),
+                            task,
+                            id_prefix_len,
+                            depth,
+                        }),
+                )
+            })
+            .collect();
+
+        let same = |row: &Row| match (row, &selected) {
+            (Row::Project(a), Some(Row::Project(b))) => a.name == b.name,
+            (Row::Task { task: a, .. }, Some(Row::Task { task: b, .. })) => a.id == b.id,
+            _ => false,
+        };
+        if let Some(i) = self.rows.iter().position(same) {
+            self.selected = i;
+        }
+        self.selected = self.selected.min(self.rows.len().saturating_sub(1));
+        Ok(())
+    }
+
+    /// The project of the selected row
+    fn project(&self) -> Option<&str> {
+        match self.rows.get(self.selected)? {
+            Row::Project(summary) => Some(&summary.name),
+            Row::Task { project, .. } => Some(project),
+        }
+    }
+
+    /// The selected task, and its project
+    fn task(&self) -> Option<(&str, &Task)> {
+        match self.rows.get(self.selected)? {
+            Row::Task { project, task, .. } => Some((project, task)),
+            Row::Project(_) => None,
+        }
+    }
+
+    fn handle(&mut self, key: KeyEvent) {
+        let result = match self.input.take() {
+            Some((prompt, text)) => self.type_key(prompt, text, key),
+            None => self.command_key(key),
+        };
+        if let Err(e) = result {
+            self.message = e;
+        }
+    }
+
+    /// Show the task file as it is after a change
+    fn refresh(&mut self) -> Result<(), String> {
+        self.reload().map_err(|e| e.to_string())
+    }
+
+    fn prompt(&mut self, prompt: Prompt, text: String) -> Result<(), String> {
+        self.input = Some((prompt, text));
+        Ok(())
+    }
+
+    fn command_key(&mut self, key: KeyEvent) -> Result<(), String> {
+        self.message.clear();
+        let task = self.task().map(|(_, t)| t.clone());
+        let need_task = || "select a task first".to_string();
+        match key.code {
+            KeyCode::Char('c') if key.modifiers.contains(KeyModifiers::CONTROL) => self.quit = true,
+            KeyCode::Char('r') if key.modifiers.contains(KeyModifiers::CONTROL) => {
+                self.message = match self.store.redo().map_err(|e| e.to_string())? {
+                    Some(operation) => format!("redid: {}", operation),
+                    None => "nothing to redo".to_string(),
+                };
+                return self.refresh();
+            }
+            KeyCode::Char('q') | KeyCode::Esc => self.quit = true,
+            KeyCode::Char('j') | KeyCode::Down => {
+                self.selected = (self.selected + 1).min(self.rows.len().saturating_sub(1))
+            }
+            KeyCode::Char('k') | KeyCode::Up => self.selected = self.selected.saturating_sub(1),
+            KeyCode::Char('g') | KeyCode::Home => self.selected = 0,
+            KeyCode::Char('G') | KeyCode::End => self.selected = self.rows.len().saturating_sub(1),
+            KeyCode::Char('a') => match self.project() {
+                Some(project) => {
+                    return self.prompt(Prompt::Add(project.to_string()), String::new())
+                }
+                None => return self.prompt(Prompt::AddProject, String::new()),
+            },
+            KeyCode::Char('A') => return self.prompt(Prompt::AddProject, String::new()),
+            KeyCode::Char('e') => {
+                let task = task.ok_or_else(need_task)?;
+                return self.prompt(Prompt::Description, task.description);
+            }
+            KeyCode::Char('p') => {
+                let task = task.ok_or_else(need_task)?;
+                let priority = task.priority.map(|p| p.to_string()).unwrap_or_default();
+                return self.prompt(Prompt::Priority, priority);
+            }
+            KeyCode::Char('d') => {
+                task.ok_or_else(need_task)?;
+                return self.prompt(Prompt::Deadline, String::new());
+            }
+            KeyCode::Char('m') => {
+                task.ok_or_else(need_task)?;
+                return self.prompt(Prompt::Move, String::new());
+            }
+            KeyCode::Char('/') => return self.prompt(Prompt::Filter, self.query.clone()),
+            KeyCode::Char('x') | KeyCode::Char(' ') => {
+                let (project, task) = self.task().ok_or_else(need_task)?;
+                let project = project.to_string();
+                let id = task.id.clone();
+                let done = self
+                    .store
+                    .complete_tasks(&[&id], project)
+                    .map_err(|e| e.to_string())?;
+                self.message = format!("done: {}", done[0].description);
+                return self.refresh();
+            }
+            KeyCode::Char('X') => {
+                let (project, task) = self.task().ok_or_else(need_task)?;
+                let project = project.to_string();
+                let id = task.id.clone();
+                let removed = self
+                    .store
+                    .remove_task(&id, project)
+                    .map_err(|e| e.to_string())?;
+                self.message = format!("deleted: {}", removed.description);
+                return self.refresh();
+            }
+            KeyCode::Char('c') => {
+                self.filter.include_completed = !self.filter.include_completed;
+                self.message = if self.filter.include_completed {
+                    "showing completed tasks".to_string()
+                } else {
+                    "hiding completed tasks".to_string()
+                };
+                return self.refresh();
+            }
+            KeyCode::Char('u') => {
+                self.message = match self.store.undo().map_err(|e| e.to_string())? {
+                    Some(operation) => format!("undid: {}", operation),
+                    None => "nothing to undo".to_string(),
+                };
+                return self.refresh();
+            }
+            _ => {}
+        }
+        Ok(())
+    }
+
+    fn type_key(&mut self, prompt: Prompt, mut text: String, key: KeyEvent) -> Result<(), String> {
+        match key.code {
+            KeyCode::Esc => Ok(()),
+            KeyCode::Enter => self.submit(prompt, text.trim()),
+            KeyCode::Backspace => {
+                text.pop();
+                self.prompt(prompt, text)
+            }
+            KeyCode::Char(c) => {
+                text.push(c);
+                self.prompt(prompt, text)
+            }
+            _ => self.prompt(prompt, text),
+        }
+    }
+
+    fn submit(&mut self, prompt: Prompt, text: &str) -> Result<(), String> {
+        let edit = |app: &mut App, edit: TaskEdit| {
+            let (project, task) = app.task().ok_or("select a task first")?;
+            let (project, id) = (project.to_string(), task.id.clone());
+            app.store
+                .edit_task(&id, project, edit)
+                .map_err(|e| e.to_string())
+        };
+
+        match prompt {
+            Prompt::Filter => {
+                self.filter.query = match text {
+                    "" => None,
+                    text => Some(query::parse_query(text).map_err(|e| e.to_string())?),
+                };
+                self.query = text.to_string();
+            }
+            _ if text.is_empty() => {}
+            Prompt::AddProject => return self.prompt(Prompt::Add(text.to_string()), String::new()),
+            Prompt::Add(project) => {
+                let (description, tags) = task::parse_tags(text);
+                let mut builder = TaskBuilder::new(description).tags(tags);
+                if let Some(priority) = &config::get().default_priority {
+                    builder = builder.priority(priority.clone());
+                }
+                let task = builder.build();
+                self.message = format!("added to {}: {}", project, task.description);
+                let id = task.id.clone();
+                self.store
+                    .add_task(task, project)
+                    .map_err(|e| e.to_string())?;
+                // select the new task
+                self.refresh()?;
+                let added = |row: &Row| matches!(row, Row::Task { task, .. } if task.id == id);
+                if let Some(i) = self.rows.iter().position(added) {
+                    self.selected = i;
+                }
+                return Ok(());
+            }
+            Prompt::Description => edit(
+                self,
+                TaskEdit {
+                    description: Some(text.to_string()),
+                    ..TaskEdit::default()
+                },
+            )?,
+            Prompt::Priority => {
+                let priority = text
+                    .parse::<Priority>()
+                    .map_err(|_| format!("unknown priority `{}`", text))?;
+                edit(
+                    self,
+                    TaskEdit {
+                        priority: Some(priority),
+                        ..TaskEdit::default()
+                    },
+                )?
+            }
+            Prompt::Deadline => {
+                let deadline = task::parse_deadline(text).map_err(|e| e.to_string())?;
+                edit(
+                    self,
+                    TaskEdit {
+                        deadline: Some(deadline),
+                        ..TaskEdit::default()
+                    },
+                )?
+            }
+            Prompt::Move => {
+                let (project, task) = self.task().ok_or("select a task first")?;
+                let (project, id) = (project.to_string(), task.id.clone());
+                self.store
+                    .move_task(project, &id, text.to_string())
+                    .map_err(|e| e.to_string())?;
+                self.message = format!("moved to {}", text);
+            }
+        }
+        self.refresh()
+    }
+
+    fn draw(&self, frame: &mut Frame) {
+        let [list_area, status_area, help_area] = Layout::vertical([
+            Constraint::Min(1),
+            Constraint::Length(1),
+            Constraint::Length(1),
+        ])
+        .areas(frame.area());
+
+        let items: Vec<ListItem> = self.rows.iter().map(row_line).map(ListItem::new).collect();
+        let mut title = format!(" {} ", self.store.path().display());
+        if !self.query.is_empty() {
+            title += &format!("[{}] ", self.query);
+        }
+        let list = List::new(items)
+            .block(Block::default().borders(Borders::ALL).title(title))
+            .highlight_style(Style::default().add_modifier(Modifier::REVERSED));
+        let mut state = ListState::default().with_selected(Some(self.selected));
+        frame.render_stateful_widget(list, list_area, &mut state);
+
+        let status = match &self.input {
+            Some((prompt, text)) => {
+                let label = prompt.label();
+                frame.set_cursor_position((
+                    status_area.x + (label.chars().count() + text.chars().count()) as u16,
+                    status_area.y,
+                ));
+                format!("{}{}", label, text)
+            }
+            None => self.message.clone(),
+        };
+        frame.render_widget(Paragraph::new(status), status_area);
+        frame.render_widget(
+            Paragraph::new(HELP).style(Style::default().add_modifier(Modifier::DIM)),
+            help_area,
+        );
+    }
+}
+
+/// A row, in the colors tasks are listed in
+fn row_line(row: &Row) -> Line<'static> {
+    match row {
+        Row::Project(summary) => Line::styled(
+            summary.to_string(),
+            Style::default().add_modifier(Modifier::BOLD),
+        ),
+        Row::Task {
+            task,
+            id_prefix_len,
+            depth,
+            progress,
+            blocked,
+            ..
+        } => {
+            let style = if task.is_done() {
+                Style::default().add_modifier(Modifier::DIM | Modifier::CROSSED_OUT)
+            } else if *blocked {
+                style(&config::get().colors.blocked)
+            } else {
+                style(config::get().colors.priority(task.priority.as_ref()))
+            };
+            let mut spans = vec![
+                Span::raw(format!(
+                    "  {}[{}]: ",
+                    "  ".repeat(*depth),
+                    task.id_prefix(*id_prefix_len)
+                )),
+                Span::styled(task.to_string(), style),
+            ];
+            if let Some(progress) = progress {
+                spans.push(Span::raw(format!(" [{}]", progress)));
+            }
+            if *blocked {
+                spans.push(Span::styled(" (blocked)", style));
+            }
+            Line::from(spans)
+        }
+    }
+}
+
+/// The configured style, for the terminal interface
+fn style(configured: &config::Style) -> Style {
+    let mut style = Style::default();
+    if let Some(c) = configured.foreground {
+        style = style.fg(color(c));
+    }
+    if let Some(c) = configured.background {
+        style = style.bg(color(c));
+    }
+    for (set, modifier) in [
+        (configured.bold, Modifier::BOLD),
+        (configured.dimmed, Modifier::DIM),
+        (configured.italic, Modifier::ITALIC),
+        (configured.underline, Modifier::UNDERLINED),
+    ] {
+        if set {
+            style = style.add_modifier(modifier);
+        }
+    }
+    style
+}
+
+fn color(color: colored::Color) -> Color {
+    use colored::Color as C;
+    match color {
+        C::Black => Color::Black,
+        C::Red => Color::Red,
+        C::Green => Color::Green,
+        C::Yellow => Color::Yellow,
+        C::Blue => Color::Blue,
+        C::Magenta => Color::Magenta,
+        C::Cyan => Color::Cyan,
+        C::White => Color::Gray,
+        C::BrightBlack => Color::DarkGray,
+        C::BrightRed => Color::LightRed,
+        C::BrightGreen => Color::LightGreen,
+        C::BrightYellow => Color::LightYellow,
+        C::BrightBlue => Color::LightBlue,
+        C::BrightMagenta => Color::LightMagenta,
+        C::BrightCyan => Color::LightCyan,
+        C::BrightWhite => Color::White,
+        C::TrueColor { r, g, b } => Color::Rgb(r, g, b),
+    }
+}
+
+/// Browse and edit the tasks of `store` until the user quits
+///
+/// Every change is made through `store`, as the other commands make them, so it is
+/// journaled and can be undone from the command line too.
+pub fn run(store: &TaskStore) -> Result<(), RustaskError> {
+    let mut app = App::new(store)?;
+    let mut terminal = ratatui::try_init()?;
+    // tasks are styled by the interface, not with escape codes
+    colored::control::set_override(false);
+    let result = event_loop(&mut app, &mut terminal);
+    colored::control::unset_override();
+    ratatui::try_restore()?;
+    result
+}
+
+fn event_loop(app: &mut App, terminal: &mut DefaultTerminal) -> Result<(), RustaskError> {
+    while !app.quit {
+        terminal.draw(|frame| app.draw(frame))?;
+        if let Event::Key(key) = event::read()? {
+            if key.kind == KeyEventKind::Press {
+                app.handle(key);
+            }
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+    use std::path::Path;
+
+    fn press(app: &mut App, keys: &str) {
+        for c in keys.chars() {
+            let code = match c {
+                '\n' => KeyCode::Enter,
+                '\x08' => KeyCode::Backspace,
+                c => KeyCode::Char(c),
+            };
+            app.handle(KeyEvent::from(code));
+        }
+    }
+
+    #[test]
+    fn keys() {
+        let path = Path::new("test_file_tui");
+        fs::write(path, "[]").unwrap();
+        let store = TaskStore::open(path).unwrap();
+        let mut app = App::new(&store).unwrap();
+        assert!(app.rows.is_empty());
+
+        press(&mut app, "awork\nfirst +a\n");
+        press(&mut app, "asecond\n");
+        assert_eq!(app.rows.len(), 3);
+        assert_eq!(app.task().unwrap().1.description, "second");
+
+        press(&mut app, "ph\x08high\n");
+        press(&mut app, "mhome\n");
+        let home = store.project("home").unwrap();
+        assert_eq!(home.tasks()[0].priority, Some(Priority::High));
+
+        press(&mut app, "/tag:a\n");
+        assert_eq!(app.rows.len(), 2);
+        press(&mut app, "jx");
+        assert!(app.message.starts_with("done: first"));
+        assert_eq!(app.rows.len(), 0);
+        press(&mut app, "c");
+        assert_eq!(app.rows.len(), 2);
+
+        press(&mut app, "/\x08\x08\x08\x08\x08\n");
+        press(&mut app, "u");
+        assert_eq!(app.message, "undid: done \"first\" in work");
+        press(&mut app, "gp");
+        assert_eq!(app.message, "select a task first");
+
+        press(&mut app, "q");
+        assert!(app.quit);
+        for entry in fs::read_dir(".").unwrap() {
+            let entry = entry.unwrap().path();
+            if entry.to_str().unwrap().starts_with("./test_file_tui") {
+                fs::remove_file(entry).unwrap();
+            }
+        }
+    }
+}